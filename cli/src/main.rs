@@ -0,0 +1,254 @@
+use std::collections::HashMap;
+use std::process::ExitCode;
+
+use anything_core::db::{self, is_valid_stage, StageState};
+use anything_core::engine::Engine;
+use anything_core::events::claims::recover_interrupted_events;
+use anything_core::events::session::session_status;
+use anything_core::events::triggers::trigger_scheduler;
+use anything_core::events::{scheduler, trigger_flow};
+use anything_core::file_manager::flow_index::FlowIndex;
+use anything_core::file_manager::flows::{list_flows, FlowSummary};
+use anything_core::file_manager::validation::{validate_flow_on_disk, Severity};
+use anything_core::notifications::{Event, EventSink};
+use anything_core::sql::sqlite::{db_dir, DbInstances};
+use clap::{Parser, Subcommand};
+use serde_json::Value as JsonValue;
+use tokio::time::{sleep, Duration};
+use tracing_subscriber::EnvFilter;
+
+// Same directory the app's sql plugin keeps its databases in
+const PACKAGE_NAME: &str = "anything";
+
+#[derive(Parser)]
+#[command(name = "anything", about = "Run Anything flows without the app")]
+struct Cli {
+    /// Stage database to use instead of the app's current one
+    #[arg(long, global = true)]
+    stage: Option<String>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run a flow and wait for its session to finish
+    Run {
+        /// Flow id or name
+        flow: String,
+        /// JSON the flow is triggered with
+        #[arg(long)]
+        input: Option<String>,
+    },
+    /// Check a flow for problems without running it
+    Validate {
+        /// Flow id or name
+        flow: String,
+    },
+    /// List the flows in the flows directory
+    List,
+    /// Show a flow's recent sessions
+    Sessions {
+        /// Flow id or name
+        flow: String,
+        #[arg(long, default_value_t = 20)]
+        limit: u32,
+    },
+    /// Run the scheduler and cron triggers until stopped
+    Daemon,
+}
+
+// Prints engine events to stdout as one JSON object per line, so they can be piped
+// into jq. Everything else the CLI has to say goes to stderr.
+struct StdoutSink;
+
+impl EventSink for StdoutSink {
+    fn send(&self, event: &Event) {
+        let mut json = serde_json::to_value(event).unwrap_or_default();
+        match json.as_object_mut() {
+            Some(fields) => {
+                fields.insert("event".to_string(), JsonValue::String(event.name().to_string()));
+            }
+            None => json = serde_json::json!({ "event": event.name() }),
+        }
+        println!("{}", json);
+    }
+}
+
+// Flows can be named by id, directory name or the name in their flow.toml
+fn find_flow(flow: &str) -> Result<FlowSummary, String> {
+    list_flows()?
+        .into_iter()
+        .find(|summary| {
+            summary.flow_id.as_deref() == Some(flow) || summary.flow_name == flow || summary.name.as_deref() == Some(flow)
+        })
+        .ok_or_else(|| format!("Flow {} not found in the flows directory", flow))
+}
+
+fn flow_id(flow: &str) -> Result<String, String> {
+    let summary = find_flow(flow)?;
+    summary
+        .flow_id
+        .ok_or_else(|| format!("Flow {} has no id: {}", flow, summary.error.unwrap_or_default()))
+}
+
+async fn start_engine() -> Result<Engine, String> {
+    let db_instances = DbInstances::new(db_dir(PACKAGE_NAME), db::migrations());
+    let stage = StageState::load(db_instances.dir());
+    db::import_legacy_db(&db_instances, db_instances.dir()).await?;
+    let engine = Engine::new(db_instances, FlowIndex::build(), stage, StdoutSink);
+    engine.db.connect(&engine.current_db()).await.map_err(|e| e.to_string())?;
+    Ok(engine)
+}
+
+fn spawn_scheduler(engine: &Engine) {
+    let engine = engine.clone();
+    tokio::spawn(async move {
+        // Events the last run left RUNNING are dealt with before anything new is picked up
+        recover_interrupted_events(&engine).await;
+        scheduler(&engine).await;
+    });
+}
+
+async fn run(engine: &Engine, flow: &str, input: Option<String>) -> Result<bool, String> {
+    let input = match input {
+        Some(input) => {
+            serde_json::from_str::<JsonValue>(&input).map_err(|e| format!("--input isn't JSON: {}", e))?;
+            input
+        }
+        None => String::new(),
+    };
+    let flow_id = flow_id(flow)?;
+    let db = engine.current_db();
+
+    spawn_scheduler(engine);
+    let session_id = trigger_flow(engine, &db, &flow_id, &input).await?;
+    eprintln!("Started session {}", session_id);
+
+    loop {
+        sleep(Duration::from_secs(1)).await;
+        let status = session_status(engine, &db, &session_id).await?;
+        if ["COMPLETE", "FAILED", "CANCELLED"].contains(&status.as_str()) {
+            eprintln!("Session {} is {}", session_id, status);
+            return Ok(status == "COMPLETE");
+        }
+    }
+}
+
+fn validate(engine: &Engine, flow: &str) -> Result<bool, String> {
+    let report = validate_flow_on_disk(&engine.flows, &flow_id(flow)?)?;
+    for diagnostic in &report.diagnostics {
+        let severity = match diagnostic.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        let at = diagnostic
+            .node_id
+            .as_ref()
+            .or(diagnostic.edge_id.as_ref())
+            .map(|id| format!(" ({})", id))
+            .unwrap_or_default();
+        eprintln!("{}[{}]{}: {}", severity, diagnostic.code, at, diagnostic.message);
+    }
+    println!("{}", if report.valid { "Flow is valid" } else { "Flow is invalid" });
+    Ok(report.valid)
+}
+
+fn list() -> Result<bool, String> {
+    for summary in list_flows()? {
+        match &summary.error {
+            Some(error) => println!("{}  (broken: {})", summary.flow_name, error),
+            None => println!(
+                "{}  {}  {} nodes",
+                summary.flow_id.as_deref().unwrap_or("-"),
+                summary.flow_name,
+                summary.node_count
+            ),
+        }
+    }
+    Ok(true)
+}
+
+async fn sessions(engine: &Engine, flow: &str, limit: u32) -> Result<bool, String> {
+    let query = "SELECT session_id, MIN(created_at) AS started_at, COUNT(*) AS events,
+            SUM(CASE WHEN event_status = 'FAILED' THEN 1 ELSE 0 END) AS failed,
+            (SELECT latest.session_status FROM events AS latest
+                WHERE latest.session_id = events.session_id
+                ORDER BY latest.created_at DESC LIMIT 1) AS status
+        FROM events WHERE flow_id = $1
+        GROUP BY session_id ORDER BY started_at DESC LIMIT $2"
+        .to_string();
+    let values = vec![JsonValue::String(flow_id(flow)?), JsonValue::from(limit)];
+    let rows = engine.db.select(engine.current_db(), query, values, None)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let text = |row: &HashMap<String, JsonValue>, key: &str| row.get(key).and_then(JsonValue::as_str).unwrap_or("").to_string();
+    let number = |row: &HashMap<String, JsonValue>, key: &str| row.get(key).and_then(JsonValue::as_u64).unwrap_or(0);
+    for row in &rows {
+        println!(
+            "{}  {:<9}  {}  {} events, {} failed",
+            text(row, "session_id"),
+            text(row, "status"),
+            text(row, "started_at"),
+            number(row, "events"),
+            number(row, "failed")
+        );
+    }
+    if rows.is_empty() {
+        eprintln!("No sessions yet");
+    }
+    Ok(true)
+}
+
+async fn daemon(engine: &Engine) -> Result<bool, String> {
+    if let Err(e) = engine.flows.watch() {
+        eprintln!("Error watching flows directory: {}", e);
+    }
+    spawn_scheduler(engine);
+    eprintln!("Running flows from the {} stage, stop with Ctrl-C", db::stage_from_db(&engine.current_db()));
+    trigger_scheduler(engine).await;
+    Ok(true)
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    // The engine logs through tracing. Only its warnings and errors are shown unless RUST_LOG asks for more.
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("warn")))
+        .init();
+
+    if let Some(stage) = &cli.stage {
+        if !is_valid_stage(stage) {
+            eprintln!("Invalid stage name: {}", stage);
+            return ExitCode::FAILURE;
+        }
+        // Takes precedence over the stage the app last switched to
+        std::env::set_var("ANYTHING_STAGE", stage);
+    }
+
+    let result = match cli.command {
+        Command::List => list(),
+        command => match start_engine().await {
+            Ok(engine) => match command {
+                Command::Run { flow, input } => run(&engine, &flow, input).await,
+                Command::Validate { flow } => validate(&engine, &flow),
+                Command::Sessions { flow, limit } => sessions(&engine, &flow, limit).await,
+                Command::Daemon => daemon(&engine).await,
+                Command::List => unreachable!(),
+            },
+            Err(e) => Err(e),
+        },
+    };
+
+    match result {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -1,7 +1,8 @@
 use anyhow::Result;
 use std::fs::create_dir_all;
 use std::path::PathBuf;
-use tauri::api::path::document_dir;
+use dirs::document_dir;
+use tracing::debug;
 
 //TODO: harmonize for one type of error handling. using anyhow
 pub fn get_app_dir() -> Result<PathBuf> {
@@ -12,14 +13,14 @@ pub fn get_app_dir() -> Result<PathBuf> {
 
 pub fn get_flows_dir() -> Result<PathBuf> {
     let dir = get_app_dir()?.join("flows");
-    println!("flows dir: {:?}", dir);
+    debug!("flows dir: {:?}", dir);
     create_dir_all(&dir)?;
     Ok(dir)
 }
 
 pub fn get_models_dir() -> Result<PathBuf> {
     let dir = get_app_dir()?.join("models");
-    println!("models dir: {:?}", dir);
+    debug!("models dir: {:?}", dir);
     create_dir_all(&dir)?;
     Ok(dir)
 }
@@ -28,4 +29,27 @@ pub fn get_logs_dir() -> Result<PathBuf> {
     let dir = get_app_dir()?.join("model_logs");
     create_dir_all(&dir)?;
     Ok(dir)
-}
\ No newline at end of file
+}
+pub fn get_archive_dir() -> Result<PathBuf> {
+    let dir = get_app_dir()?.join("archive");
+    create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+pub fn get_backups_dir() -> Result<PathBuf> {
+    let dir = get_app_dir()?.join("backups");
+    create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+pub fn get_templates_dir() -> Result<PathBuf> {
+    let dir = get_app_dir()?.join("templates");
+    create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+pub fn get_trash_dir() -> Result<PathBuf> {
+    let dir = get_app_dir()?.join("trash");
+    create_dir_all(&dir)?;
+    Ok(dir)
+}
@@ -0,0 +1,230 @@
+use std::fs;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use tokio::time::{sleep, Duration};
+use tracing::{error, info};
+
+use crate::config::{get_app_dir, get_backups_dir};
+use crate::db::stage_from_db;
+use crate::engine::Engine;
+
+const POLICY_FILE: &str = "backup.toml";
+
+/// Scheduled backups. Stored in `backup.toml` in the app dir.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupPolicy {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_interval_hours")]
+    pub interval_hours: u64,
+    /// Number of backups to keep per stage. Older ones are deleted.
+    #[serde(default = "default_keep")]
+    pub keep: usize,
+}
+
+fn default_interval_hours() -> u64 {
+    24
+}
+
+fn default_keep() -> usize {
+    7
+}
+
+impl Default for BackupPolicy {
+    fn default() -> Self {
+        BackupPolicy {
+            enabled: false,
+            interval_hours: default_interval_hours(),
+            keep: default_keep(),
+        }
+    }
+}
+
+impl BackupPolicy {
+    pub fn load() -> BackupPolicy {
+        get_app_dir()
+            .ok()
+            .and_then(|dir| fs::read_to_string(dir.join(POLICY_FILE)).ok())
+            .and_then(|content| toml::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        let content = toml::to_string(self)?;
+        fs::write(get_app_dir()?.join(POLICY_FILE), content)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupInfo {
+    pub stage: String,
+    pub file_name: String,
+    pub path: String,
+    pub size_bytes: u64,
+    pub created_at: String,
+}
+
+impl BackupInfo {
+    fn from_path(stage: &str, path: &Path) -> Option<BackupInfo> {
+        let metadata = fs::metadata(path).ok()?;
+        let created_at: DateTime<Utc> = metadata.modified().ok()?.into();
+        Some(BackupInfo {
+            stage: stage.to_string(),
+            file_name: path.file_name()?.to_str()?.to_string(),
+            path: path.to_string_lossy().to_string(),
+            size_bytes: metadata.len(),
+            created_at: created_at.to_rfc3339(),
+        })
+    }
+}
+
+fn stage_backups_dir(stage: &str) -> Result<std::path::PathBuf, String> {
+    let dir = get_backups_dir().map_err(|e| e.to_string())?.join(stage);
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    Ok(dir)
+}
+
+/// Takes a consistent snapshot with `VACUUM INTO` so the scheduler can keep writing meanwhile.
+pub async fn backup(engine: &Engine, db: &str, label: &str) -> Result<BackupInfo, String> {
+    let stage = stage_from_db(db);
+
+    // VACUUM INTO won't overwrite a file, so backups taken in the same millisecond get a counter
+    let dir = stage_backups_dir(&stage)?;
+    let timestamp = Utc::now().format("%Y%m%dT%H%M%S%3f").to_string();
+    let mut path = dir.join(format!("{}-{}{}.sqlite", stage, timestamp, label));
+    let mut n = 1;
+    while path.exists() {
+        path = dir.join(format!("{}-{}-{}{}.sqlite", stage, timestamp, n, label));
+        n += 1;
+    }
+
+    engine
+        .db
+        .execute(
+            db.to_string(),
+            "VACUUM INTO $1".to_string(),
+            vec![JsonValue::String(path.to_string_lossy().to_string())],
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+
+    info!("Backed up {} to {:?}", db, path);
+    BackupInfo::from_path(&stage, &path).ok_or(format!("Backup not found at {:?}", path))
+}
+
+// Deletes all but the newest `keep` backups of a stage. Names sort by timestamp.
+fn rotate(stage: &str, keep: usize) -> Result<(), String> {
+    let mut files: Vec<_> = fs::read_dir(stage_backups_dir(stage)?)
+        .map_err(|e| e.to_string())?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "sqlite"))
+        .collect();
+    files.sort();
+    files.reverse();
+
+    for old in files.iter().skip(keep) {
+        info!("Removing old backup {:?}", old);
+        fs::remove_file(old).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Backs up every loaded database once per `interval_hours` when enabled.
+pub async fn backup_scheduler(engine: &Engine) {
+    loop {
+        let policy = BackupPolicy::load();
+        sleep(Duration::from_secs(policy.interval_hours.max(1) * 60 * 60)).await;
+
+        if !policy.enabled {
+            continue;
+        }
+
+        for db in engine.db.loaded().await {
+            match backup(engine, &db, "").await {
+                Ok(_) => {
+                    if let Err(e) = rotate(&stage_from_db(&db), policy.keep) {
+                        error!("Error rotating backups for {}: {}", db, e);
+                    }
+                }
+                Err(e) => error!("Error backing up {}: {}", db, e),
+            }
+        }
+    }
+}
+
+/// Backs up a stage right now. Older backups are only rotated out when scheduled
+/// backups are enabled, since that's where the user agreed to `keep`.
+pub async fn backup_database(engine: &Engine, stage: Option<String>) -> Result<BackupInfo, String> {
+    let db = engine.db_for_stage(stage)?;
+    let info = backup(engine, &db, "").await?;
+    let policy = BackupPolicy::load();
+    if policy.enabled {
+        rotate(&info.stage, policy.keep.max(1))?;
+    }
+    Ok(info)
+}
+
+pub fn list_backups(engine: &Engine, stage: Option<String>) -> Result<Vec<BackupInfo>, String> {
+    let stage = stage_from_db(&engine.db_for_stage(stage)?);
+    let mut backups: Vec<BackupInfo> = fs::read_dir(stage_backups_dir(&stage)?)
+        .map_err(|e| e.to_string())?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| BackupInfo::from_path(&stage, &entry.path()))
+        .filter(|info| info.file_name.ends_with(".sqlite"))
+        .collect();
+    backups.sort_by(|a, b| b.file_name.cmp(&a.file_name));
+    Ok(backups)
+}
+
+/// Replaces a stage's database with one of its backups. The scheduler is paused and
+/// nothing else can use the database while the file is swapped. The current database is
+/// backed up first, just in case. Returns that safety backup.
+pub async fn restore_database(
+    engine: &Engine,
+    file_name: String,
+    stage: Option<String>,
+) -> Result<BackupInfo, String> {
+    let db = engine.db_for_stage(stage)?;
+    let stage = stage_from_db(&db);
+
+    if file_name.contains('/') || file_name.contains('\\') {
+        return Err(format!("Invalid backup file name: {}", file_name));
+    }
+    let source = stage_backups_dir(&stage)?.join(&file_name);
+    if !source.exists() {
+        return Err(format!("Backup not found: {}", file_name));
+    }
+
+    let control = &engine.control;
+    control.pause();
+    control.wait_until_idle().await;
+
+    let result = swap_database(engine, &db, &source).await;
+
+    control.resume();
+    result
+}
+
+async fn swap_database(engine: &Engine, db: &str, source: &Path) -> Result<BackupInfo, String> {
+    let safety = backup(engine, db, "-pre-restore").await?;
+
+    // Reopening runs migrations, so older backups come back on the current schema
+    engine.db.replace(db, source).await.map_err(|e| e.to_string())?;
+
+    info!("Restored {} from {:?}", db, source);
+    Ok(safety)
+}
+
+pub fn get_backup_policy() -> BackupPolicy {
+    BackupPolicy::load()
+}
+
+pub fn set_backup_policy(policy: BackupPolicy) -> Result<BackupPolicy, String> {
+    policy.save().map_err(|e| e.to_string())?;
+    Ok(policy)
+}
@@ -0,0 +1,278 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+use tracing::info;
+
+use crate::engine::Engine;
+use crate::sql::sqlite::{Migration, MigrationKind};
+use crate::sql::Storage;
+
+pub mod backup;
+
+// Stages ("environments") each get their own database file so flows can be
+// run in "dev" without touching the run history of "prod".
+pub const DEFAULT_STAGE: &str = "dev";
+
+// File in the db directory that remembers the last selected stage.
+const STAGE_FILE: &str = "current_stage";
+
+// The single database everything lived in before stages, imported into "dev" once.
+const LEGACY_DB_FILE: &str = "test.db";
+
+// Clones share the stage, so switching it in the app switches it for the engine too
+#[derive(Clone)]
+pub struct StageState {
+    current: Arc<Mutex<String>>,
+    dir: PathBuf,
+}
+
+impl StageState {
+    // `dir` is where the stage databases live, the directory of the sqlite storage.
+    // Reads the persisted stage, falling back to ANYTHING_STAGE and then "dev".
+    pub fn load(dir: &Path) -> Self {
+        let stage = std::env::var("ANYTHING_STAGE")
+            .ok()
+            .or_else(|| fs::read_to_string(dir.join(STAGE_FILE)).ok())
+            .map(|s| s.trim().to_string())
+            .filter(|s| is_valid_stage(s))
+            .unwrap_or_else(|| DEFAULT_STAGE.to_string());
+
+        StageState {
+            current: Arc::new(Mutex::new(stage)),
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    pub fn current(&self) -> String {
+        self.current.lock().unwrap().clone()
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct StageInfo {
+    stage: String,
+    db: String,
+}
+
+// Stage names end up in file names so keep them boring.
+pub fn is_valid_stage(stage: &str) -> bool {
+    !stage.is_empty()
+        && stage
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+// Connection string for a stage. Resolved relative to the db directory by the sqlite storage.
+pub fn db_string(stage: &str) -> String {
+    format!("sqlite:{}.sqlite", stage)
+}
+
+// Inverse of db_string. Used to tag events with the stage of the database they live in.
+pub fn stage_from_db(db: &str) -> String {
+    db.split_once(':')
+        .map(|(_, file)| file.trim_end_matches(".sqlite"))
+        .unwrap_or(DEFAULT_STAGE)
+        .to_string()
+}
+
+// Database for a stage passed in from a command, or the current one if none was given.
+pub fn stage_db(stage_state: &StageState, stage: Option<String>) -> Result<String, String> {
+    match stage {
+        Some(stage) if is_valid_stage(&stage) => Ok(db_string(&stage)),
+        Some(stage) => Err(format!("Invalid stage name: {}", stage)),
+        None => Ok(db_string(&stage_state.current())),
+    }
+}
+
+// Schema for every stage database. Append new versions, never edit old ones.
+pub fn migrations() -> Vec<Migration> {
+    vec![
+        Migration {
+            version: 1,
+            description: "create events table",
+            sql: "CREATE TABLE IF NOT EXISTS events (
+            event_id TEXT PRIMARY KEY,
+            session_id TEXT,
+            node_id TEXT,
+            node_type TEXT,
+            node_label TEXT,
+            flow_id TEXT,
+            flow_name TEXT,
+            flow_version TEXT,
+            worker_type TEXT,
+            worker_name TEXT,
+            stage TEXT,
+            event_status TEXT,
+            session_status TEXT,
+            created_at DATETIME,
+            event_result TEXT,
+            event_context TEXT,
+            data TEXT
+        );",
+            kind: MigrationKind::Up,
+        },
+        Migration {
+            version: 2,
+            description: "index events for polling and retention",
+            sql: "CREATE INDEX IF NOT EXISTS idx_events_status_created ON events (event_status, created_at);
+            CREATE INDEX IF NOT EXISTS idx_events_session ON events (session_id);
+            CREATE INDEX IF NOT EXISTS idx_events_flow ON events (flow_id, created_at);",
+            kind: MigrationKind::Up,
+        },
+        Migration {
+            version: 3,
+            description: "create flow snapshots and sessions",
+            sql: "CREATE TABLE IF NOT EXISTS flow_snapshots (
+                snapshot_hash TEXT PRIMARY KEY,
+                flow_id TEXT,
+                flow_version TEXT,
+                flow_definition TEXT,
+                flow_settings TEXT,
+                created_at DATETIME
+            );
+            CREATE TABLE IF NOT EXISTS sessions (
+                session_id TEXT PRIMARY KEY,
+                flow_id TEXT,
+                snapshot_hash TEXT REFERENCES flow_snapshots (snapshot_hash),
+                created_at DATETIME
+            );",
+            kind: MigrationKind::Up,
+        },
+        Migration {
+            version: 4,
+            description: "track node scopes and session context",
+            sql: "ALTER TABLE events ADD COLUMN scope TEXT NOT NULL DEFAULT '';
+            ALTER TABLE events ADD COLUMN handles TEXT;
+            ALTER TABLE sessions ADD COLUMN context TEXT NOT NULL DEFAULT '{}';
+            CREATE INDEX IF NOT EXISTS idx_events_session_node ON events (session_id, node_id, scope);",
+            kind: MigrationKind::Up,
+        },
+        Migration {
+            version: 5,
+            description: "link subflow sessions to their parents",
+            sql: "CREATE TABLE IF NOT EXISTS session_links (
+                child_session_id TEXT PRIMARY KEY,
+                parent_session_id TEXT,
+                parent_event_id TEXT,
+                parent_node_id TEXT,
+                depth INTEGER,
+                created_at DATETIME
+            );
+            CREATE INDEX IF NOT EXISTS idx_session_links_parent ON session_links (parent_event_id);",
+            kind: MigrationKind::Up,
+        },
+        Migration {
+            version: 6,
+            description: "let events wait until a time",
+            sql: "ALTER TABLE events ADD COLUMN resume_at DATETIME;
+            CREATE INDEX IF NOT EXISTS idx_events_status_resume ON events (event_status, resume_at);",
+            kind: MigrationKind::Up,
+        },
+        Migration {
+            version: 7,
+            description: "link replayed sessions to the originals",
+            sql: "CREATE TABLE IF NOT EXISTS session_replays (
+                session_id TEXT PRIMARY KEY,
+                original_session_id TEXT,
+                from_node_id TEXT,
+                created_at DATETIME
+            );
+            CREATE INDEX IF NOT EXISTS idx_session_replays_original ON session_replays (original_session_id);",
+            kind: MigrationKind::Up,
+        },
+        Migration {
+            version: 8,
+            description: "prioritize sessions",
+            sql: "ALTER TABLE sessions ADD COLUMN priority INTEGER;",
+            kind: MigrationKind::Up,
+        },
+        Migration {
+            version: 9,
+            description: "claim running events with a lease",
+            sql: "ALTER TABLE events ADD COLUMN claimed_at DATETIME;
+            ALTER TABLE events ADD COLUMN lease_until DATETIME;
+            CREATE INDEX IF NOT EXISTS idx_events_status_lease ON events (event_status, lease_until);",
+            kind: MigrationKind::Up,
+        },
+    ]
+}
+
+pub fn get_current_stage(stage: &StageState) -> StageInfo {
+    let stage = stage.current();
+    StageInfo {
+        db: db_string(&stage),
+        stage,
+    }
+}
+
+// Lists every stage that has a database file, plus the current one.
+pub fn get_stages(stage: &StageState) -> Result<Vec<String>, String> {
+    let mut stages: Vec<String> = fs::read_dir(stage.dir())
+        .map_err(|e| e.to_string())?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_str()?.to_string();
+            name.strip_suffix(".sqlite").map(|s| s.to_string())
+        })
+        .collect();
+
+    let current = stage.current();
+    if !stages.contains(&current) {
+        stages.push(current);
+    }
+    stages.sort();
+    Ok(stages)
+}
+
+// Points the engine at another stage, creating and migrating its database if needed.
+pub async fn set_stage(engine: &Engine, stage: String) -> Result<StageInfo, String> {
+    if !is_valid_stage(&stage) {
+        return Err(format!("Invalid stage name: {}", stage));
+    }
+
+    let db = db_string(&stage);
+    engine.db.connect(&db).await.map_err(|e| e.to_string())?;
+
+    *engine.stage.current.lock().unwrap() = stage.clone();
+
+    fs::write(engine.stage.dir().join(STAGE_FILE), &stage).map_err(|e| e.to_string())?;
+
+    info!("Switched to stage: {}", stage);
+    Ok(StageInfo { stage, db })
+}
+
+// Copies the run history of the pre-stage `test.db` into the "dev" stage, then renames
+// it so this only happens once. Its events table is the one migration 1 creates.
+pub async fn import_legacy_db(db: &dyn Storage, dir: &Path) -> Result<(), String> {
+    let legacy = dir.join(LEGACY_DB_FILE);
+    if !legacy.exists() {
+        return Ok(());
+    }
+    let path = legacy.to_str().ok_or("Legacy database path isn't valid UTF-8")?;
+
+    let dev = db_string(DEFAULT_STAGE);
+    db.connect(&dev).await.map_err(|e| e.to_string())?;
+    let columns = "event_id, session_id, node_id, node_type, node_label, flow_id, flow_name, flow_version,
+        worker_type, worker_name, stage, event_status, session_status, created_at, event_result, event_context, data";
+    // One query, so ATTACH and the copy run on the same connection. The frontend only
+    // created the table once it loaded, so it may not be there.
+    let query = format!(
+        "ATTACH DATABASE '{}' AS legacy;
+        CREATE TABLE IF NOT EXISTS legacy.events ({columns});
+        INSERT OR IGNORE INTO events ({columns}) SELECT {columns} FROM legacy.events;
+        DETACH DATABASE legacy;",
+        path.replace('\'', "''"),
+        columns = columns
+    );
+    let (rows, _) = db.execute(dev.clone(), query, vec![]).await.map_err(|e| e.to_string())?;
+
+    fs::rename(&legacy, dir.join(format!("{}.imported", LEGACY_DB_FILE))).map_err(|e| e.to_string())?;
+    info!("Imported {} events from {} into {}", rows, LEGACY_DB_FILE, dev);
+    Ok(())
+}
@@ -0,0 +1,81 @@
+use std::ops::Deref;
+use std::sync::Arc;
+
+use tokio::sync::Mutex;
+
+use crate::db::{db_string, stage_db, StageState};
+use crate::events::claims::RecoveryState;
+use crate::events::limits::HostRateLimiter;
+use crate::events::priority::FairQueue;
+use crate::events::retention::MaintenanceState;
+use crate::events::session::RunningWorkers;
+use crate::events::SchedulerControl;
+use crate::file_manager::flow_index::FlowIndex;
+use crate::notifications::{Event, EventSink};
+use crate::sql::Storage;
+
+/// Everything the flow engine runs on, so it runs the same inside the app, headless from
+/// the CLI or in a test with its own storage and sink. Clones share the same state.
+#[derive(Clone)]
+pub struct Engine(Arc<EngineState>);
+
+pub struct EngineState {
+    pub db: Box<dyn Storage>,
+    pub flows: FlowIndex,
+    pub stage: StageState,
+    pub control: SchedulerControl,
+    pub running: RunningWorkers,
+    pub rate_limiter: HostRateLimiter,
+    pub fair_queue: FairQueue,
+    pub recovery: RecoveryState,
+    pub maintenance: MaintenanceState,
+    /// Held while deciding what runs after a node. Two nodes finishing at once could otherwise
+    /// both decide a join node is ready and queue it twice.
+    pub advance: Mutex<()>,
+    sink: Box<dyn EventSink>,
+}
+
+impl Engine {
+    pub fn new(
+        db: impl Storage + 'static,
+        flows: FlowIndex,
+        stage: StageState,
+        sink: impl EventSink + 'static,
+    ) -> Engine {
+        Engine(Arc::new(EngineState {
+            db: Box::new(db),
+            flows,
+            stage,
+            control: SchedulerControl::default(),
+            running: RunningWorkers::default(),
+            rate_limiter: HostRateLimiter::default(),
+            fair_queue: FairQueue::default(),
+            recovery: RecoveryState::default(),
+            maintenance: MaintenanceState::default(),
+            advance: Mutex::new(()),
+            sink: Box::new(sink),
+        }))
+    }
+
+    /// Sends an event to whoever is watching the engine
+    pub fn notify(&self, event: Event) {
+        self.sink.send(&event);
+    }
+
+    /// Database for a stage passed in by a caller, or the current stage's if none was given
+    pub fn db_for_stage(&self, stage: Option<String>) -> Result<String, String> {
+        stage_db(&self.stage, stage)
+    }
+
+    pub fn current_db(&self) -> String {
+        db_string(&self.stage.current())
+    }
+}
+
+impl Deref for Engine {
+    type Target = EngineState;
+
+    fn deref(&self) -> &EngineState {
+        &self.0
+    }
+}
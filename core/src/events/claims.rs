@@ -0,0 +1,223 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Mutex;
+
+use chrono::{Duration as ChronoDuration, Utc};
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Duration};
+use tracing::{error, info, warn};
+
+use crate::engine::Engine;
+use crate::events::pause::timestamp;
+use crate::notifications::Event;
+
+/// How long a claim on a running event holds without being renewed
+pub const LEASE_SECS: i64 = 60;
+
+// Renewed well before it runs out so a slow database write doesn't lose it
+const RENEW_EVERY: Duration = Duration::from_secs(20);
+
+type Row = HashMap<String, JsonValue>;
+
+fn text<'a>(row: &'a Row, key: &str) -> &'a str {
+    row.get(key).and_then(JsonValue::as_str).unwrap_or("")
+}
+
+/// What happens to an event that was running when the app stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterruptPolicy {
+    /// Run it again
+    Requeue,
+    /// Fail it and its session
+    Fail,
+    /// Leave it for someone to requeue or fail by hand
+    Attention,
+}
+
+/// A node's `on_interrupt` field: `requeue`, `fail` or `attention`. Rest and terminal nodes
+/// default to `attention` since running them twice could repeat what they did, the rest
+/// only touch the engine and are requeued.
+pub fn interrupt_policy(worker_type: &str, data: &JsonValue) -> Result<InterruptPolicy, String> {
+    match data.get("on_interrupt").and_then(JsonValue::as_str).map(str::trim) {
+        None | Some("") => Ok(match worker_type {
+            "rest" | "terminal" => InterruptPolicy::Attention,
+            _ => InterruptPolicy::Requeue,
+        }),
+        Some("requeue") => Ok(InterruptPolicy::Requeue),
+        Some("fail") => Ok(InterruptPolicy::Fail),
+        Some("attention") => Ok(InterruptPolicy::Attention),
+        Some(other) => Err(format!("Unknown on_interrupt {:?}, expected requeue, fail or attention", other)),
+    }
+}
+
+fn lease_until() -> String {
+    timestamp(Utc::now() + ChronoDuration::seconds(LEASE_SECS))
+}
+
+/// Counts the sessions of the flow in `flow` that have started and not finished. A claimed
+/// start event counts too, so a session holds its place before its first nodes are queued.
+pub fn running_sessions_sql(flow: &str) -> String {
+    format!("(SELECT COUNT(DISTINCT running.session_id) FROM events AS running
+        WHERE running.flow_id = {} AND running.session_status = 'PENDING'
+        AND NOT (running.worker_type = 'start' AND running.event_status = 'PENDING'))", flow)
+}
+
+/// Marks a pending event RUNNING so no other pass picks it up. False if something else got to it
+/// first, or if `limit` is set and the event's flow already has that many sessions running.
+pub async fn claim_event(engine: &Engine, db: &str, event_id: &str, limit: Option<usize>) -> bool {
+    let mut query = "UPDATE events SET event_status = 'RUNNING', claimed_at = $1, lease_until = $2
+        WHERE event_id = $3 AND event_status = 'PENDING'"
+        .to_string();
+    let mut values = vec![
+        JsonValue::String(timestamp(Utc::now())),
+        JsonValue::String(lease_until()),
+        JsonValue::String(event_id.to_string()),
+    ];
+    // Checked in the same statement so two passes can't both see room and both claim
+    if let Some(limit) = limit {
+        query.push_str(&format!(" AND {} < $4", running_sessions_sql("events.flow_id")));
+        values.push(JsonValue::from(limit));
+    }
+    match engine.db.execute(db.to_string(), query, values).await {
+        Ok((claimed, _)) => claimed > 0,
+        Err(e) => {
+            error!("Error claiming event {}: {}", event_id, e);
+            false
+        }
+    }
+}
+
+/// Renews the event's lease until the returned task is aborted
+pub fn keep_lease(engine: &Engine, db: &str, event_id: &str) -> JoinHandle<()> {
+    let (engine, db, event_id) = (engine.clone(), db.to_string(), event_id.to_string());
+    tokio::spawn(async move {
+        loop {
+            sleep(RENEW_EVERY).await;
+            let query = "UPDATE events SET lease_until = $1 WHERE event_id = $2 AND event_status = 'RUNNING'".to_string();
+            let values = vec![JsonValue::String(lease_until()), JsonValue::String(event_id.clone())];
+            if let Err(e) = engine.db.execute(db.clone(), query, values).await {
+                error!("Error renewing the lease on event {}: {}", event_id, e);
+            }
+        }
+    })
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RecoveryReport {
+    pub recovered_at: String,
+    /// Sessions with an event that was recovered
+    pub session_ids: BTreeSet<String>,
+    pub requeued: usize,
+    pub failed: usize,
+    pub needs_attention: usize,
+}
+
+impl RecoveryReport {
+    fn is_empty(&self) -> bool {
+        self.requeued + self.failed + self.needs_attention == 0
+    }
+}
+
+/// The last recovery that found anything, for a UI that wasn't listening when it ran
+#[derive(Default)]
+pub struct RecoveryState(Mutex<Option<RecoveryReport>>);
+
+// Deals with the RUNNING events whose lease ran out, so their worker is gone
+async fn recover_expired(engine: &Engine, db: &str, report: &mut RecoveryReport) -> Result<(), String> {
+    let query = "SELECT * FROM events WHERE event_status = 'RUNNING' AND lease_until < $1".to_string();
+    let rows = engine.db.select(db.to_string(), query, vec![JsonValue::String(timestamp(Utc::now()))], None)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    for row in rows {
+        let event_id = text(&row, "event_id");
+        let data: JsonValue = serde_json::from_str(text(&row, "event_context")).unwrap_or_default();
+        let policy = interrupt_policy(text(&row, "worker_type"), &data).unwrap_or(InterruptPolicy::Attention);
+        warn!("Event {} was interrupted, recovering it with {:?}", event_id, policy);
+
+        match policy {
+            InterruptPolicy::Fail => {
+                super::mark_as_failed(engine, db, &row, "Interrupted because the app stopped while it ran".to_string()).await;
+                report.failed += 1;
+            }
+            InterruptPolicy::Requeue | InterruptPolicy::Attention => {
+                let status = if policy == InterruptPolicy::Requeue { "PENDING" } else { "ATTENTION" };
+                let query = "UPDATE events SET event_status = $1 WHERE event_id = $2 AND event_status = 'RUNNING'".to_string();
+                let values = vec![JsonValue::String(status.to_string()), JsonValue::String(event_id.to_string())];
+                engine.db.execute(db.to_string(), query, values)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                if policy == InterruptPolicy::Requeue {
+                    report.requeued += 1;
+                } else {
+                    report.needs_attention += 1;
+                }
+            }
+        }
+        report.session_ids.insert(text(&row, "session_id").to_string());
+    }
+    Ok(())
+}
+
+/// Recovers interrupted events in every loaded database and sends one notification
+/// listing the sessions they belong to. Runs at startup and on every scheduler pass.
+pub async fn recover_interrupted_events(engine: &Engine) {
+    let mut report = RecoveryReport {
+        recovered_at: timestamp(Utc::now()),
+        ..Default::default()
+    };
+    for db in engine.db.loaded().await {
+        if let Err(e) = recover_expired(engine, &db, &mut report).await {
+            error!("Error recovering interrupted events in {}: {}", db, e);
+        }
+    }
+    if report.is_empty() {
+        return;
+    }
+
+    engine.notify(Event::EventsRecovered {
+        message: format!(
+            "Recovered interrupted events: {} requeued, {} failed, {} need attention",
+            report.requeued, report.failed, report.needs_attention
+        ),
+        session_ids: report.session_ids.iter().cloned().collect(),
+    });
+    *engine.recovery.0.lock().unwrap() = Some(report);
+}
+
+pub fn get_recovery_report(engine: &Engine) -> Option<RecoveryReport> {
+    engine.recovery.0.lock().unwrap().clone()
+}
+
+/// Events left for someone to look at after they were interrupted
+pub async fn list_attention_events(engine: &Engine, stage: Option<String>) -> Result<Vec<Row>, String> {
+    let db = engine.db_for_stage(stage)?;
+    let query = "SELECT event_id, session_id, node_id, node_label, flow_id, flow_name, worker_type, claimed_at, created_at
+        FROM events WHERE event_status = 'ATTENTION' ORDER BY created_at ASC"
+        .to_string();
+    engine.db.select(db, query, vec![], None)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Runs an event that needs attention again, or fails it and its session
+pub async fn resolve_attention_event(engine: &Engine, event_id: String, requeue: bool, stage: Option<String>) -> Result<(), String> {
+    let db = engine.db_for_stage(stage)?;
+    let query = "SELECT * FROM events WHERE event_id = $1 AND event_status = 'ATTENTION'".to_string();
+    let rows = engine.db.select(db.clone(), query, vec![JsonValue::String(event_id.clone())], None)
+        .await
+        .map_err(|e| e.to_string())?;
+    let row = rows.first().ok_or_else(|| format!("Event {} doesn't need attention", event_id))?;
+
+    if requeue {
+        let query = "UPDATE events SET event_status = 'PENDING' WHERE event_id = $1 AND event_status = 'ATTENTION'".to_string();
+        engine.db.execute(db, query, vec![JsonValue::String(event_id.clone())])
+            .await
+            .map_err(|e| e.to_string())?;
+    } else {
+        super::mark_as_failed(engine, &db, row, "Failed by hand after it was interrupted".to_string()).await;
+    }
+    info!("Resolved event {} ({})", event_id, if requeue { "requeued" } else { "failed" });
+    Ok(())
+}
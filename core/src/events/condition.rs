@@ -0,0 +1,197 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+
+use crate::events::context::NodeContext;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Default)]
+pub enum Operator {
+    Equals,
+    NotEquals,
+    GreaterThan,
+    GreaterThanOrEqual,
+    LessThan,
+    LessThanOrEqual,
+    /// Substring of a string or element of an array
+    Contains,
+    /// Regular expression match anywhere in the value
+    Matches,
+    Exists,
+    NotExists,
+    #[default]
+    Truthy,
+    Falsy,
+}
+
+
+/// A check against a value in the node context, written inline in `flow.toml`:
+///
+/// ```toml
+/// condition = { field = "nodes.3.result.has_more", operator = "equals", value = true }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Condition {
+    /// Dotted path into the node context, like a `{{...}}` placeholder without the braces
+    pub field: String,
+    #[serde(default)]
+    pub operator: Operator,
+    #[serde(default)]
+    pub value: Option<JsonValue>,
+}
+
+fn truthy(value: &JsonValue) -> bool {
+    match value {
+        JsonValue::Null => false,
+        JsonValue::Bool(b) => *b,
+        JsonValue::Number(n) => n.as_f64().is_some_and(|n| n != 0.0),
+        JsonValue::String(s) => !s.is_empty() && s != "false" && s != "0",
+        JsonValue::Array(items) => !items.is_empty(),
+        JsonValue::Object(map) => !map.is_empty(),
+    }
+}
+
+// Numbers often arrive as strings from headers and text results
+fn as_number(value: &JsonValue) -> Option<f64> {
+    match value {
+        JsonValue::Number(n) => n.as_f64(),
+        JsonValue::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn equal(actual: &JsonValue, expected: &JsonValue) -> bool {
+    if actual == expected {
+        return true;
+    }
+    match (as_number(actual), as_number(expected)) {
+        (Some(a), Some(b)) => a == b,
+        _ => match (actual, expected) {
+            (JsonValue::String(a), other) | (other, JsonValue::String(a)) => match other {
+                JsonValue::Bool(b) => a == if *b { "true" } else { "false" },
+                JsonValue::Null => a == "null",
+                // Numbers, arrays and objects as their JSON text
+                other => serde_json::to_string(other).is_ok_and(|text| text == *a),
+            },
+            _ => false,
+        },
+    }
+}
+
+impl Condition {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.field.trim().is_empty() {
+            return Err("condition has no field".to_string());
+        }
+        let needs_value = !matches!(
+            self.operator,
+            Operator::Exists | Operator::NotExists | Operator::Truthy | Operator::Falsy
+        );
+        if needs_value && self.value.is_none() {
+            return Err(format!("condition on {} needs a value to compare with", self.field));
+        }
+        if self.operator == Operator::Matches {
+            self.pattern()?;
+        }
+        Ok(())
+    }
+
+    fn pattern(&self) -> Result<Regex, String> {
+        let pattern = self
+            .value
+            .as_ref()
+            .and_then(JsonValue::as_str)
+            .ok_or_else(|| format!("condition on {} needs a regex as its value", self.field))?;
+        Regex::new(pattern).map_err(|e| format!("Invalid regex {:?}: {}", pattern, e))
+    }
+
+    pub fn evaluate(&self, context: &NodeContext) -> Result<bool, String> {
+        self.validate()?;
+        let actual = context.lookup(self.field.trim());
+        let expected = self.value.clone().unwrap_or(JsonValue::Null);
+
+        let compare = |check: fn(f64, f64) -> bool| -> Result<bool, String> {
+            match actual.as_ref().and_then(as_number).zip(as_number(&expected)) {
+                Some((a, b)) => Ok(check(a, b)),
+                None => Ok(false),
+            }
+        };
+
+        match self.operator {
+            Operator::Exists => Ok(actual.is_some()),
+            Operator::NotExists => Ok(actual.is_none()),
+            Operator::Truthy => Ok(actual.as_ref().is_some_and(truthy)),
+            Operator::Falsy => Ok(!actual.as_ref().is_some_and(truthy)),
+            Operator::Equals => Ok(actual.as_ref().map_or(expected.is_null(), |a| equal(a, &expected))),
+            Operator::NotEquals => Ok(!actual.as_ref().map_or(expected.is_null(), |a| equal(a, &expected))),
+            Operator::GreaterThan => compare(|a, b| a > b),
+            Operator::GreaterThanOrEqual => compare(|a, b| a >= b),
+            Operator::LessThan => compare(|a, b| a < b),
+            Operator::LessThanOrEqual => compare(|a, b| a <= b),
+            Operator::Contains => Ok(match &actual {
+                Some(JsonValue::String(text)) => match &expected {
+                    JsonValue::String(part) => text.contains(part.as_str()),
+                    other => text.contains(&other.to_string()),
+                },
+                Some(JsonValue::Array(items)) => items.iter().any(|item| equal(item, &expected)),
+                Some(JsonValue::Object(map)) => expected.as_str().is_some_and(|key| map.contains_key(key)),
+                _ => false,
+            }),
+            Operator::Matches => {
+                let pattern = self.pattern()?;
+                Ok(match &actual {
+                    Some(JsonValue::String(text)) => pattern.is_match(text),
+                    Some(other) => pattern.is_match(&other.to_string()),
+                    None => false,
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_manager::settings::FlowSettings;
+    use serde_json::json;
+
+    fn context() -> NodeContext {
+        NodeContext::new(&FlowSettings::default()).with(
+            "input",
+            json!({ "status": "200", "count": 3, "tags": ["a", "b"], "name": "report.csv", "done": false }),
+        )
+    }
+
+    fn check(field: &str, operator: Operator, value: Option<JsonValue>) -> Result<bool, String> {
+        Condition { field: field.to_string(), operator, value }.evaluate(&context())
+    }
+
+    #[test]
+    fn numbers_compare_across_strings_and_numbers() {
+        assert_eq!(check("input.status", Operator::Equals, Some(json!(200))), Ok(true));
+        assert_eq!(check("input.count", Operator::GreaterThan, Some(json!("2"))), Ok(true));
+        assert_eq!(check("input.count", Operator::LessThanOrEqual, Some(json!(2))), Ok(false));
+        assert_eq!(check("input.name", Operator::GreaterThan, Some(json!(1))), Ok(false));
+        assert_eq!(check("input.count", Operator::NotEquals, Some(json!(3))), Ok(false));
+    }
+
+    #[test]
+    fn contains_matches_and_truthiness() {
+        assert_eq!(check("input.tags", Operator::Contains, Some(json!("b"))), Ok(true));
+        assert_eq!(check("input.name", Operator::Contains, Some(json!(".csv"))), Ok(true));
+        assert_eq!(check("input", Operator::Contains, Some(json!("tags"))), Ok(true));
+        assert_eq!(check("input.name", Operator::Matches, Some(json!(r"^report\.\w+$"))), Ok(true));
+        assert_eq!(check("input.done", Operator::Truthy, None), Ok(false));
+        assert_eq!(check("input.done", Operator::Falsy, None), Ok(true));
+        assert_eq!(check("input.missing", Operator::NotExists, None), Ok(true));
+        assert_eq!(check("input.missing", Operator::Equals, Some(JsonValue::Null)), Ok(true));
+    }
+
+    #[test]
+    fn invalid_conditions_are_errors() {
+        assert!(check(" ", Operator::Truthy, None).is_err());
+        assert!(check("input.count", Operator::Equals, None).is_err());
+        assert!(check("input.name", Operator::Matches, Some(json!("("))).is_err());
+    }
+}
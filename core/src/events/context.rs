@@ -0,0 +1,171 @@
+use std::collections::BTreeMap;
+
+use serde_json::{json, Value as JsonValue};
+
+use crate::file_manager::settings::FlowSettings;
+
+/// Values a node's fields can reference with `{{path.to.value}}`.
+///
+/// * `{{settings.*}}` resolves against the session's flow settings
+/// * `{{secrets.NAME}}` resolves from the settings `env`, never from the host's environment
+/// * `{{nodes.ID.result}}` is the result of an earlier node in the session
+/// * `{{loop.iteration}}` is the current iteration of the innermost loop
+/// * `{{item}}` and `{{item_index}}` are the element a map body runs for and its position
+/// * `{{trigger}}` is what the session was started with, e.g. the input of a subflow
+///
+/// Placeholders that don't resolve are left as they are so the node's output shows what was missing.
+#[derive(Debug, Clone, Default)]
+pub struct NodeContext {
+    values: JsonValue,
+    env: BTreeMap<String, String>,
+}
+
+impl NodeContext {
+    pub fn new(settings: &FlowSettings) -> NodeContext {
+        NodeContext {
+            values: json!({ "settings": settings.context() }),
+            env: settings.env.clone(),
+        }
+    }
+
+    /// Adds a top level namespace, e.g. `nodes` with the results of earlier nodes
+    pub fn with(mut self, key: &str, value: JsonValue) -> NodeContext {
+        if let Some(values) = self.values.as_object_mut() {
+            values.insert(key.to_string(), value);
+        }
+        self
+    }
+
+    pub fn lookup(&self, path: &str) -> Option<JsonValue> {
+        if let Some(name) = path.strip_prefix("secrets.") {
+            return self.env.get(name).cloned().map(JsonValue::String);
+        }
+
+        let mut value = &self.values;
+        for key in path.split('.') {
+            value = match value {
+                JsonValue::Object(map) => map.get(key)?,
+                JsonValue::Array(items) => items.get(key.parse::<usize>().ok()?)?,
+                _ => return None,
+            };
+        }
+        match value {
+            JsonValue::Null => None,
+            other => Some(other.clone()),
+        }
+    }
+
+    pub fn resolve(&self, path: &str) -> Option<String> {
+        match self.lookup(path)? {
+            JsonValue::String(text) => Some(text),
+            other => Some(other.to_string()),
+        }
+    }
+
+    pub fn render_str(&self, template: &str) -> String {
+        self.render_with(template, |value| value)
+    }
+
+    /// Renders a shell script without putting any value into it. Each placeholder becomes a
+    /// quoted reference to an environment variable, returned alongside, that holds its value.
+    /// This works in double quotes and unquoted words but not inside single quotes.
+    pub fn render_shell(&self, template: &str) -> (String, BTreeMap<String, String>) {
+        let mut values = BTreeMap::new();
+        let script = self.render_with(template, |value| {
+            let name = format!("ANYTHING_VALUE_{}", values.len());
+            values.insert(name.clone(), value);
+            format!("\"${{{}}}\"", name)
+        });
+        (script, values)
+    }
+
+    // Replaces each placeholder that resolves with what `insert` makes of its value
+    fn render_with(&self, template: &str, mut insert: impl FnMut(String) -> String) -> String {
+        let mut rendered = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(start) = rest.find("{{") {
+            rendered.push_str(&rest[..start]);
+            let after = &rest[start + 2..];
+            match after.find("}}") {
+                Some(end) => {
+                    let placeholder = &rest[start..start + 2 + end + 2];
+                    match self.resolve(after[..end].trim()) {
+                        Some(value) => rendered.push_str(&insert(value)),
+                        None => rendered.push_str(placeholder),
+                    }
+                    rest = &after[end + 2..];
+                }
+                None => {
+                    rendered.push_str(&rest[start..]);
+                    rest = "";
+                }
+            }
+        }
+        rendered.push_str(rest);
+        rendered
+    }
+
+    /// Renders every string inside a node's data
+    pub fn render(&self, value: &JsonValue) -> JsonValue {
+        match value {
+            JsonValue::String(text) => JsonValue::String(self.render_str(text)),
+            JsonValue::Array(items) => JsonValue::Array(items.iter().map(|item| self.render(item)).collect()),
+            JsonValue::Object(map) => JsonValue::Object(
+                map.iter()
+                    .map(|(key, item)| (key.clone(), self.render(item)))
+                    .collect(),
+            ),
+            other => other.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context() -> NodeContext {
+        let mut settings = FlowSettings::default();
+        settings.env.insert("API_KEY".to_string(), "secret".to_string());
+        settings.variables.insert("region".to_string(), json!("eu"));
+        NodeContext::new(&settings).with(
+            "nodes",
+            json!({ "3": { "result": { "items": [{ "name": "first" }], "count": 2, "empty": null } } }),
+        )
+    }
+
+    #[test]
+    fn placeholders_reach_into_results_settings_and_secrets() {
+        let context = context();
+        assert_eq!(context.render_str("{{nodes.3.result.items.0.name}}"), "first");
+        assert_eq!(context.render_str("count={{ nodes.3.result.count }}"), "count=2");
+        assert_eq!(context.render_str("{{settings.region}}/{{secrets.API_KEY}}"), "eu/secret");
+        assert_eq!(context.render_str("{{nodes.3.result.items}}"), r#"[{"name":"first"}]"#);
+    }
+
+    #[test]
+    fn unresolved_placeholders_are_left_as_they_are() {
+        let context = context();
+        assert_eq!(context.render_str("a {{nodes.9.result}} b"), "a {{nodes.9.result}} b");
+        assert_eq!(context.render_str("{{nodes.3.result.empty}}"), "{{nodes.3.result.empty}}");
+        assert_eq!(context.render_str("{{nodes.3.result.items.x}}"), "{{nodes.3.result.items.x}}");
+        assert_eq!(context.render_str("open {{nodes.3.result.count"), "open {{nodes.3.result.count");
+        // Secrets only come from the flow's settings, whatever the host has set
+        std::env::set_var("ANYTHING_TEST_HOST_SECRET", "leaked");
+        assert_eq!(context.render_str("{{secrets.ANYTHING_TEST_HOST_SECRET}}"), "{{secrets.ANYTHING_TEST_HOST_SECRET}}");
+        assert_eq!(context.render_str("{{secrets.PATH}}"), "{{secrets.PATH}}");
+        assert_eq!(context.render_str("no placeholders"), "no placeholders");
+    }
+
+    #[test]
+    fn shell_scripts_get_values_through_the_environment() {
+        let context = context().with("trigger", json!("$(rm -rf ~); echo \"pwned\""));
+        let (script, values) = context.render_shell("echo \"got {{trigger}}\" {{settings.region}} {{nodes.9.result}}");
+        assert_eq!(
+            script,
+            r#"echo "got "${ANYTHING_VALUE_0}"" "${ANYTHING_VALUE_1}" {{nodes.9.result}}"#
+        );
+        assert_eq!(values["ANYTHING_VALUE_0"], "$(rm -rf ~); echo \"pwned\"");
+        assert_eq!(values["ANYTHING_VALUE_1"], "eu");
+    }
+}
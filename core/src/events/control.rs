@@ -0,0 +1,372 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use serde_json::{json, Value as JsonValue};
+use tracing::info;
+
+use crate::engine::Engine;
+use crate::events::condition::Condition;
+use crate::events::context::NodeContext;
+use crate::events::graph::{child_scope, FlowGraph, LOOP_BODY_HANDLE, LOOP_DONE_HANDLE, ROUTER_DEFAULT_HANDLE};
+use crate::events::progress::{count_events, latest_event, map_state, record_loop_state, record_map_state, MapState};
+use crate::events::snapshot::session_snapshot;
+
+/// Hard cap on iterations whatever a loop node asks for
+pub const MAX_LOOP_ITERATIONS: u64 = 1000;
+/// Hard cap on the items a map node splits into
+pub const MAX_MAP_ITEMS: usize = 1000;
+
+/// How many times a loop node may run its body. Required so a loop can't spin forever.
+pub fn max_iterations(data: &JsonValue) -> Result<u64, String> {
+    let max = data
+        .get("max_iterations")
+        .and_then(|max| max.as_u64().or_else(|| max.as_str()?.trim().parse().ok()))
+        .ok_or("Loop node needs max_iterations")?;
+    if max == 0 || max > MAX_LOOP_ITERATIONS {
+        return Err(format!("max_iterations must be between 1 and {}", MAX_LOOP_ITERATIONS));
+    }
+    Ok(max)
+}
+
+// Control fields are inline tables in flow.toml but JSON strings when set from the editor
+fn parse_field<T: serde::de::DeserializeOwned>(value: &JsonValue) -> Result<T, serde_json::Error> {
+    match value {
+        JsonValue::String(s) => serde_json::from_str(s),
+        other => serde_json::from_value(other.clone()),
+    }
+}
+
+/// The condition a loop repeats while
+pub fn loop_condition(data: &JsonValue) -> Result<Option<Condition>, String> {
+    match data.get("condition") {
+        None | Some(JsonValue::Null) => Ok(None),
+        Some(JsonValue::String(s)) if s.trim().is_empty() => Ok(None),
+        Some(condition) => {
+            let condition: Condition = parse_field(condition).map_err(|e| format!("Invalid condition: {}", e))?;
+            condition.validate()?;
+            Ok(Some(condition))
+        }
+    }
+}
+
+/// One branch of a router: the edges leaving through `handle` fire when `condition` holds
+#[derive(Debug, Clone, Deserialize)]
+pub struct Route {
+    pub handle: String,
+    pub condition: Condition,
+}
+
+pub fn router_routes(data: &JsonValue) -> Result<Vec<Route>, String> {
+    let routes: Vec<Route> = match data.get("routes") {
+        None | Some(JsonValue::Null) => Vec::new(),
+        Some(routes) => parse_field(routes).map_err(|e| format!("Invalid routes: {}", e))?,
+    };
+    if routes.is_empty() {
+        return Err("Router node needs at least one route".to_string());
+    }
+
+    let mut handles = Vec::new();
+    for route in &routes {
+        let handle = route.handle.trim();
+        if handle.is_empty() {
+            return Err("Every route needs a handle".to_string());
+        }
+        if handle == ROUTER_DEFAULT_HANDLE || handles.contains(&handle) {
+            return Err(format!("Route handle {:?} is used more than once", handle));
+        }
+        route.condition.validate().map_err(|e| format!("Route {}: {}", handle, e))?;
+        handles.push(handle);
+    }
+    Ok(routes)
+}
+
+/// Whether a router takes every matching route or only the first one
+pub fn router_takes_all(data: &JsonValue) -> Result<bool, String> {
+    match data.get("mode").and_then(JsonValue::as_str).map(str::trim) {
+        None | Some("") | Some("first") => Ok(false),
+        Some("all") => Ok(true),
+        Some(other) => Err(format!("Unknown router mode {:?}, expected \"first\" or \"all\"", other)),
+    }
+}
+
+/// Context path of the array a map node runs its body for. Defaults to `input`.
+pub fn map_items_path(data: &JsonValue) -> &str {
+    data.get("items")
+        .and_then(JsonValue::as_str)
+        .map(str::trim)
+        .filter(|path| !path.is_empty())
+        .unwrap_or("input")
+}
+
+/// How many items of a map may run at once, `None` for all of them
+pub fn map_concurrency(data: &JsonValue) -> Result<Option<usize>, String> {
+    let value = match data.get("concurrency") {
+        None | Some(JsonValue::Null) => return Ok(None),
+        Some(JsonValue::String(s)) if s.trim().is_empty() => return Ok(None),
+        Some(value) => value,
+    };
+    match value.as_u64().or_else(|| value.as_str()?.trim().parse().ok()) {
+        Some(0) | None => Err("concurrency must be a whole number of at least 1".to_string()),
+        Some(limit) => Ok(Some(limit as usize)),
+    }
+}
+
+/// Handles a control node took, read back from its result. `None` means all of them.
+pub fn taken_handles(worker_type: &str, result: &str) -> Option<Vec<String>> {
+    match worker_type {
+        "loop" | "map" | "approval" => {
+            let result: JsonValue = serde_json::from_str(result).ok()?;
+            Some(vec![result.get("next")?.as_str()?.to_string()])
+        }
+        "router" => {
+            let result: JsonValue = serde_json::from_str(result).ok()?;
+            serde_json::from_value(result.get("handles")?.clone()).ok()
+        }
+        _ => None,
+    }
+}
+
+/// Whether the event waits for something outside the scheduler instead of completing
+pub fn waits(worker_type: &str, result: &str) -> bool {
+    match worker_type {
+        "subflow" | "delay" | "wait_until" | "approval" => serde_json::from_str::<JsonValue>(result)
+            .is_ok_and(|result| result.get("waiting") == Some(&JsonValue::Bool(true))),
+        _ => false,
+    }
+}
+
+/// Decides whether a loop goes round again. The body always runs once, after that
+/// it repeats while `condition` holds, up to `max_iterations` times in total.
+pub async fn run_loop(
+    engine: &Engine,
+    db: &str,
+    event_data: &HashMap<String, JsonValue>,
+    data: &JsonValue,
+    context: &NodeContext,
+) -> Result<String, String> {
+    let text = |key: &str| event_data.get(key).and_then(JsonValue::as_str).unwrap_or("");
+    let (session_id, node_id, scope) = (text("session_id"), text("node_id"), text("scope"));
+
+    let max = max_iterations(data)?;
+    let condition = loop_condition(data)?;
+    let iteration = count_events(engine, db, session_id, node_id, scope).await?.saturating_sub(1);
+
+    let again = (iteration as u64) < max
+        && match (&condition, iteration) {
+            (_, 0) | (None, _) => true,
+            (Some(condition), _) => condition.evaluate(context)?,
+        };
+
+    if again {
+        record_loop_state(engine, db, session_id, node_id, scope, iteration).await?;
+    }
+    let next = if again { LOOP_BODY_HANDLE } else { LOOP_DONE_HANDLE };
+    info!("Loop {} iteration {}: {}", node_id, iteration, next);
+
+    Ok(json!({ "iteration": iteration, "next": next }).to_string())
+}
+
+// Adds `input`, the result of the node that led here, to the context
+async fn with_input(
+    engine: &Engine,
+    db: &str,
+    session_id: &str,
+    node_id: &str,
+    context: NodeContext,
+) -> Result<(FlowGraph, NodeContext), String> {
+    let snapshot = session_snapshot(engine, db, session_id).await?;
+    let graph = FlowGraph::new(&snapshot.definition);
+    let context = add_input(&graph, node_id, context);
+    Ok((graph, context))
+}
+
+pub fn add_input(graph: &FlowGraph, node_id: &str, context: NodeContext) -> NodeContext {
+    let input = graph
+        .incoming(node_id)
+        .iter()
+        .filter(|e| !e.back)
+        .find_map(|e| context.lookup(&format!("nodes.{}.result", e.source)))
+        .unwrap_or(JsonValue::Null);
+    context.with("input", input)
+}
+
+/// Picks the branches a router takes. Conditions can use `input`, the result of the node
+/// that led to the router, as well as everything else in the node context.
+pub async fn run_router(
+    engine: &Engine,
+    db: &str,
+    event_data: &HashMap<String, JsonValue>,
+    data: &JsonValue,
+    context: NodeContext,
+) -> Result<String, String> {
+    let text = |key: &str| event_data.get(key).and_then(JsonValue::as_str).unwrap_or("");
+    let (session_id, node_id) = (text("session_id"), text("node_id"));
+
+    let (_, context) = with_input(engine, db, session_id, node_id, context).await?;
+    let handles = pick_routes(data, &context)?;
+    info!("Router {} takes {:?}", node_id, handles);
+
+    Ok(json!({ "handles": handles }).to_string())
+}
+
+/// The handles a router leaves through in `context`, which needs `input` already added
+pub fn pick_routes(data: &JsonValue, context: &NodeContext) -> Result<Vec<String>, String> {
+    let routes = router_routes(data)?;
+    let takes_all = router_takes_all(data)?;
+
+    let mut handles = Vec::new();
+    for route in &routes {
+        if route.condition.evaluate(context)? {
+            handles.push(route.handle.trim().to_string());
+            if !takes_all {
+                break;
+            }
+        }
+    }
+    if handles.is_empty() {
+        handles.push(ROUTER_DEFAULT_HANDLE.to_string());
+    }
+    Ok(handles)
+}
+
+/// Runs twice per scope. The first time it splits an array into items and starts the body
+/// for them; once every item is finished it gathers what the end of the body returned
+/// for each one into `results`, with `statuses` saying which items ran or were skipped.
+pub async fn run_map(
+    engine: &Engine,
+    db: &str,
+    event_data: &HashMap<String, JsonValue>,
+    data: &JsonValue,
+    context: NodeContext,
+) -> Result<String, String> {
+    let text = |key: &str| event_data.get(key).and_then(JsonValue::as_str).unwrap_or("");
+    let (session_id, node_id, scope) = (text("session_id"), text("node_id"), text("scope"));
+    let (graph, context) = with_input(engine, db, session_id, node_id, context).await?;
+
+    if count_events(engine, db, session_id, node_id, scope).await? <= 1 {
+        let path = map_items_path(data);
+        let items = match context.lookup(path) {
+            Some(JsonValue::Array(items)) => items,
+            // Results of rest and terminal nodes are text
+            Some(JsonValue::String(s)) => match serde_json::from_str(&s) {
+                Ok(JsonValue::Array(items)) => items,
+                _ => return Err(format!("{} is not an array", path)),
+            },
+            Some(_) => return Err(format!("{} is not an array", path)),
+            None => return Err(format!("{} doesn't resolve to anything", path)),
+        };
+        if items.len() > MAX_MAP_ITEMS {
+            return Err(format!("{} has {} items, maps take at most {}", path, items.len(), MAX_MAP_ITEMS));
+        }
+
+        let count = items.len();
+        let state = MapState {
+            items,
+            concurrency: map_concurrency(data)?,
+            ..MapState::default()
+        };
+        record_map_state(engine, db, session_id, node_id, scope, &state).await?;
+        info!("Map {} split into {} items", node_id, count);
+
+        if count == 0 {
+            return Ok(json!({ "results": [], "statuses": [], "next": LOOP_DONE_HANDLE }).to_string());
+        }
+        return Ok(json!({ "count": count, "next": LOOP_BODY_HANDLE }).to_string());
+    }
+
+    let state = map_state(engine, db, session_id, node_id, scope).await?;
+    let ends: Vec<&str> = graph
+        .incoming(node_id)
+        .iter()
+        .filter(|e| e.back)
+        .map(|e| e.source.as_str())
+        .collect();
+
+    let mut results = Vec::new();
+    let mut statuses = Vec::new();
+    for index in 0..state.items.len() {
+        let item_scope = child_scope(scope, node_id, index);
+        let mut outputs = serde_json::Map::new();
+        let mut ran = false;
+        for end in &ends {
+            let event = latest_event(engine, db, session_id, end, &item_scope).await?;
+            let event = event.as_ref();
+            let status = event.and_then(|e| e.get("event_status")).and_then(JsonValue::as_str);
+            let output = match (status, event.and_then(|e| e.get("event_result")).and_then(JsonValue::as_str)) {
+                (Some("COMPLETE"), Some(result)) => {
+                    ran = true;
+                    serde_json::from_str(result).unwrap_or_else(|_| JsonValue::String(result.to_string()))
+                }
+                _ => JsonValue::Null,
+            };
+            outputs.insert(end.to_string(), output);
+        }
+        // With a single end node the item's output is just its result
+        results.push(match ends.len() {
+            1 => outputs.into_iter().next().map(|(_, v)| v).unwrap_or_default(),
+            _ => JsonValue::Object(outputs),
+        });
+        statuses.push(if ran { "COMPLETE" } else { "SKIPPED" });
+    }
+
+    info!("Map {} gathered {} results", node_id, results.len());
+    Ok(json!({ "results": results, "statuses": statuses, "next": LOOP_DONE_HANDLE }).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_manager::settings::FlowSettings;
+
+    fn router(mode: &str) -> JsonValue {
+        json!({
+            "worker_type": "router",
+            "mode": mode,
+            "routes": [
+                { "handle": "big", "condition": { "field": "input.size", "operator": "greater_than", "value": 10 } },
+                { "handle": "even", "condition": { "field": "input.even", "operator": "truthy" } },
+            ],
+        })
+    }
+
+    fn input(size: u64) -> NodeContext {
+        NodeContext::new(&FlowSettings::default()).with("input", json!({ "size": size, "even": ([4, 12].contains(&size)) }))
+    }
+
+    #[test]
+    fn first_mode_takes_the_first_matching_route() {
+        assert_eq!(pick_routes(&router("first"), &input(12)), Ok(vec!["big".to_string()]));
+        assert_eq!(pick_routes(&router(""), &input(4)), Ok(vec!["even".to_string()]));
+    }
+
+    #[test]
+    fn all_mode_takes_every_matching_route() {
+        assert_eq!(pick_routes(&router("all"), &input(12)), Ok(vec!["big".to_string(), "even".to_string()]));
+    }
+
+    #[test]
+    fn default_route_is_taken_when_nothing_matches() {
+        assert_eq!(pick_routes(&router("all"), &input(3)), Ok(vec![ROUTER_DEFAULT_HANDLE.to_string()]));
+    }
+
+    #[test]
+    fn routers_need_unique_routes_and_a_known_mode() {
+        assert!(pick_routes(&router("some"), &input(3)).is_err());
+        assert!(pick_routes(&json!({ "routes": [] }), &input(3)).is_err());
+        let clash = json!({ "routes": [{ "handle": "default", "condition": { "field": "input.size" } }] });
+        assert!(pick_routes(&clash, &input(3)).is_err());
+        // The editor stores routes as a JSON string
+        let from_editor = json!({ "routes": r#"[{"handle": "any", "condition": {"field": "input.size"}}]"# });
+        assert_eq!(pick_routes(&from_editor, &input(3)), Ok(vec!["any".to_string()]));
+    }
+
+    #[test]
+    fn max_iterations_is_required_and_capped() {
+        assert_eq!(max_iterations(&json!({ "max_iterations": 5 })), Ok(5));
+        assert_eq!(max_iterations(&json!({ "max_iterations": " 7 " })), Ok(7));
+        assert!(max_iterations(&json!({})).is_err());
+        assert!(max_iterations(&json!({ "max_iterations": 0 })).is_err());
+        assert!(max_iterations(&json!({ "max_iterations": MAX_LOOP_ITERATIONS + 1 })).is_err());
+        assert_eq!(max_iterations(&json!({ "max_iterations": MAX_LOOP_ITERATIONS })), Ok(MAX_LOOP_ITERATIONS));
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+use serde_json::{json, Value as JsonValue};
+use tracing::info;
+
+use crate::engine::Engine;
+use crate::events::control::{add_input, map_items_path, pick_routes};
+use crate::events::graph::FlowGraph;
+use crate::events::pause::{run_approval, run_delay, run_wait_until};
+use crate::events::progress::top_level_context;
+use crate::events::snapshot::read_flow_definition;
+use crate::file_manager::settings::FlowSettings;
+
+/// What testing a node on its own resolved and, unless it was a dry run, returned
+#[derive(Debug, Serialize)]
+pub struct NodeTest {
+    pub node_id: String,
+    pub worker_type: String,
+    /// The node's fields with their placeholders filled in
+    pub fields: JsonValue,
+    /// What the node would send or run, for rest, terminal and map nodes
+    pub request: Option<JsonValue>,
+    /// What the node returned
+    pub result: Option<String>,
+}
+
+/// Runs one node of a flow outside of a session. `mock_results` stand in for the results
+/// of earlier nodes and `trigger` for what the session was started with. A dry run only
+/// resolves what rest and terminal nodes would do without doing it. Nothing is written
+/// to the database.
+pub async fn test_node(
+    engine: &Engine,
+    flow_id: String,
+    node_id: String,
+    mock_results: Option<HashMap<String, JsonValue>>,
+    trigger: Option<JsonValue>,
+    dry_run: Option<bool>,
+) -> Result<NodeTest, String> {
+    let dry_run = dry_run.unwrap_or(false);
+    let (definition, settings) = read_flow_definition(engine, &flow_id).await?;
+    let settings = FlowSettings::from_json(&settings)?;
+    let graph = FlowGraph::new(&definition);
+    let node = graph
+        .node(&node_id)
+        .ok_or_else(|| format!("Node {} not found in flow {}", node_id, flow_id))?;
+    let worker_type = graph.worker_type(&node_id).to_string();
+
+    // Shaped like the session context, with results parsed the way the engine records them
+    let nodes: serde_json::Map<String, JsonValue> = mock_results
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(id, result)| {
+            let result = match result {
+                JsonValue::String(s) => serde_json::from_str(&s).unwrap_or(JsonValue::String(s)),
+                other => other,
+            };
+            (id, json!({ "result": result, "scope": "" }))
+        })
+        .collect();
+    let context = json!({ "nodes": nodes, "trigger": trigger.unwrap_or_default() });
+    let node_context = top_level_context(&settings, &context);
+    let data = node.get("data").cloned().unwrap_or_default();
+    let fields = node_context.render(&data);
+
+    let mut test = NodeTest {
+        node_id: node_id.clone(),
+        worker_type: worker_type.clone(),
+        fields,
+        request: None,
+        result: None,
+    };
+
+    // Only rest and terminal nodes reach outside the app, the rest can run even in a dry run
+    let event_data = HashMap::new();
+    match worker_type.as_str() {
+        "rest" => {
+            let request = super::rest_request(&test.fields);
+            test.request = Some(serde_json::to_value(&request).map_err(|e| e.to_string())?);
+            if !dry_run {
+                test.result = Some(super::run_rest(engine, &test.fields).await?);
+            }
+        }
+        "terminal" => {
+            let (command, values) = node_context.render_shell(data["command"].as_str().unwrap_or_default());
+            test.request = Some(json!({ "command": command, "values": values }));
+            if !dry_run {
+                let output = super::run_terminal_command(&command, &settings.env, &values)
+                    .await
+                    .map_err(|e| format!("Terminal command failed: {}", e))?;
+                test.result = Some(output);
+            }
+        }
+        "router" => {
+            let handles = pick_routes(&test.fields, &add_input(&graph, &node_id, node_context))?;
+            test.result = Some(json!({ "handles": handles }).to_string());
+        }
+        "map" => {
+            let path = map_items_path(&test.fields);
+            let items = add_input(&graph, &node_id, node_context).lookup(path);
+            test.request = Some(json!({ "items": items }));
+        }
+        "delay" => test.result = Some(run_delay(&event_data, &test.fields)?),
+        "wait_until" => test.result = Some(run_wait_until(&event_data, &test.fields)?),
+        "approval" => test.result = Some(run_approval(&event_data, &test.fields)?),
+        // These only make sense as part of a session, their fields are all there is to show
+        "start" | "loop" | "subflow" => {}
+        other => return Err(format!("Unknown worker type: {}", other)),
+    }
+
+    info!("Tested node {} of flow {} (dry run: {})", node_id, flow_id, dry_run);
+    Ok(test)
+}
@@ -0,0 +1,340 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use serde_json::Value as JsonValue;
+
+/// Source handle of a loop or map node that leads into the repeated subgraph
+pub const LOOP_BODY_HANDLE: &str = "body";
+/// Source handle of a loop or map node that is followed once it is finished
+pub const LOOP_DONE_HANDLE: &str = "done";
+/// Source handle of a router node that is followed when no route matches
+pub const ROUTER_DEFAULT_HANDLE: &str = "default";
+
+/// Loops run their body once per iteration and maps once per item. Both get their
+/// body back through back edges and run it in scopes of their own.
+pub fn repeats_body(worker_type: &str) -> bool {
+    worker_type == "loop" || worker_type == "map"
+}
+
+#[derive(Debug, Clone)]
+pub struct Edge {
+    pub source: String,
+    pub source_handle: Option<String>,
+    pub target: String,
+    /// Goes from the end of a loop or map body back to the node that repeats it
+    pub back: bool,
+}
+
+impl Edge {
+    pub fn leaves_through(&self, handle: &str) -> bool {
+        self.source_handle.as_deref() == Some(handle)
+    }
+}
+
+/// A flow definition as a graph. Built from a snapshot so it can't change while a
+/// session runs. Edges with unknown endpoints are dropped; `validate` reports them.
+#[derive(Debug, Default)]
+pub struct FlowGraph {
+    nodes: HashMap<String, JsonValue>,
+    order: Vec<String>,
+    outgoing: HashMap<String, Vec<Edge>>,
+    incoming: HashMap<String, Vec<Edge>>,
+    /// Loop or map node id to the nodes it repeats
+    bodies: HashMap<String, HashSet<String>>,
+    /// Loops and maps each node is inside of, outermost first
+    enclosing: HashMap<String, Vec<String>>,
+}
+
+impl FlowGraph {
+    pub fn new(definition: &JsonValue) -> FlowGraph {
+        let mut graph = FlowGraph::default();
+
+        for node in definition.get("nodes").and_then(JsonValue::as_array).into_iter().flatten() {
+            if let Some(id) = node.get("id").and_then(JsonValue::as_str) {
+                if graph.nodes.insert(id.to_string(), node.clone()).is_none() {
+                    graph.order.push(id.to_string());
+                }
+            }
+        }
+
+        let mut edges = Vec::new();
+        for edge in definition.get("edges").and_then(JsonValue::as_array).into_iter().flatten() {
+            let field = |key: &str| edge.get(key).and_then(JsonValue::as_str).map(|s| s.to_string());
+            if let (Some(source), Some(target)) = (field("source"), field("target")) {
+                if graph.nodes.contains_key(&source) && graph.nodes.contains_key(&target) {
+                    edges.push(Edge {
+                        source,
+                        source_handle: field("sourceHandle"),
+                        target,
+                        back: false,
+                    });
+                }
+            }
+        }
+
+        // A loop's body is everything reachable through its body handle without coming back through it
+        let loop_ids: Vec<String> = graph
+            .order
+            .iter()
+            .filter(|id| repeats_body(graph.worker_type(id)))
+            .cloned()
+            .collect();
+        for loop_id in &loop_ids {
+            let mut body = HashSet::new();
+            let mut queue: VecDeque<&str> = edges
+                .iter()
+                .filter(|e| &e.source == loop_id && e.leaves_through(LOOP_BODY_HANDLE))
+                .map(|e| e.target.as_str())
+                .collect();
+            while let Some(node_id) = queue.pop_front() {
+                if node_id == loop_id.as_str() || !body.insert(node_id.to_string()) {
+                    continue;
+                }
+                queue.extend(edges.iter().filter(|e| e.source == node_id).map(|e| e.target.as_str()));
+            }
+            graph.bodies.insert(loop_id.clone(), body);
+        }
+
+        for edge in edges.iter_mut() {
+            edge.back = graph.bodies.get(&edge.target).is_some_and(|body| body.contains(&edge.source));
+        }
+
+        // Outer loops have bigger bodies, so sorting by size puts them first
+        let mut loops: Vec<(&String, &HashSet<String>)> = graph.bodies.iter().collect();
+        loops.sort_by(|a, b| b.1.len().cmp(&a.1.len()).then(a.0.cmp(b.0)));
+        for (loop_id, body) in loops {
+            for node_id in body {
+                graph.enclosing.entry(node_id.clone()).or_default().push(loop_id.clone());
+            }
+        }
+
+        for edge in edges {
+            graph.incoming.entry(edge.target.clone()).or_default().push(edge.clone());
+            graph.outgoing.entry(edge.source.clone()).or_default().push(edge);
+        }
+
+        graph
+    }
+
+    pub fn node(&self, node_id: &str) -> Option<&JsonValue> {
+        self.nodes.get(node_id)
+    }
+
+    pub fn data(&self, node_id: &str) -> &JsonValue {
+        self.nodes
+            .get(node_id)
+            .and_then(|node| node.get("data"))
+            .unwrap_or(&JsonValue::Null)
+    }
+
+    pub fn worker_type(&self, node_id: &str) -> &str {
+        self.data(node_id)
+            .get("worker_type")
+            .and_then(JsonValue::as_str)
+            .unwrap_or_default()
+    }
+
+    /// Node ids in the order they appear in `flow.toml`
+    pub fn node_ids(&self) -> &[String] {
+        &self.order
+    }
+
+    /// The first start node, the one sessions begin at
+    pub fn start_node(&self) -> Option<&str> {
+        self.order
+            .iter()
+            .find(|id| self.worker_type(id) == "start")
+            .map(|id| id.as_str())
+    }
+
+    pub fn outgoing(&self, node_id: &str) -> &[Edge] {
+        self.outgoing.get(node_id).map_or(&[], |edges| edges.as_slice())
+    }
+
+    pub fn incoming(&self, node_id: &str) -> &[Edge] {
+        self.incoming.get(node_id).map_or(&[], |edges| edges.as_slice())
+    }
+
+    pub fn loop_body(&self, loop_id: &str) -> Option<&HashSet<String>> {
+        self.bodies.get(loop_id)
+    }
+
+    /// Loops and maps the node is inside of, outermost first
+    pub fn enclosing_loops(&self, node_id: &str) -> &[String] {
+        self.enclosing.get(node_id).map_or(&[], |loops| loops.as_slice())
+    }
+
+    /// A cycle that doesn't go through a loop node's back edge, as the path around it.
+    /// Those would run forever.
+    pub fn find_cycle(&self) -> Option<Vec<String>> {
+        fn visit<'a>(
+            graph: &'a FlowGraph,
+            node: &'a str,
+            done: &mut HashSet<&'a str>,
+            path: &mut Vec<&'a str>,
+        ) -> Option<Vec<String>> {
+            if let Some(position) = path.iter().position(|n| *n == node) {
+                let mut cycle: Vec<String> = path[position..].iter().map(|n| n.to_string()).collect();
+                cycle.push(node.to_string());
+                return Some(cycle);
+            }
+            if done.contains(node) {
+                return None;
+            }
+            path.push(node);
+            for edge in graph.outgoing(node).iter().filter(|e| !e.back) {
+                if let Some(cycle) = visit(graph, &edge.target, done, path) {
+                    return Some(cycle);
+                }
+            }
+            path.pop();
+            done.insert(node);
+            None
+        }
+
+        let mut done = HashSet::new();
+        for node_id in &self.order {
+            if let Some(cycle) = visit(self, node_id, &mut done, &mut Vec::new()) {
+                return Some(cycle);
+            }
+        }
+        None
+    }
+
+    /// Nodes reachable from the start node, following every edge
+    pub fn reachable_from_start(&self) -> HashSet<&str> {
+        match self.start_node() {
+            Some(start) => self.descendants(start),
+            None => HashSet::new(),
+        }
+    }
+
+    /// The node and every node reachable from it, following every edge
+    pub fn descendants<'a>(&'a self, node_id: &'a str) -> HashSet<&'a str> {
+        let mut reached = HashSet::new();
+        let mut queue = VecDeque::from([node_id]);
+        while let Some(node_id) = queue.pop_front() {
+            if reached.insert(node_id) {
+                queue.extend(self.outgoing(node_id).iter().map(|e| e.target.as_str()));
+            }
+        }
+        reached
+    }
+}
+
+// Scopes tell apart the runs of a node inside loops and maps. They are the path of
+// loop iterations (or map items) the node runs in, e.g. "3#0/7#2" is the third
+// iteration of loop 7 inside the first iteration of loop 3. Nodes outside any loop run in "".
+
+pub fn child_scope(scope: &str, loop_id: &str, iteration: usize) -> String {
+    let segment = format!("{}#{}", loop_id, iteration);
+    if scope.is_empty() {
+        segment
+    } else {
+        format!("{}/{}", scope, segment)
+    }
+}
+
+/// The first `depth` loop iterations of a scope
+pub fn truncate_scope(scope: &str, depth: usize) -> String {
+    if depth == 0 {
+        return String::new();
+    }
+    scope.split('/').take(depth).collect::<Vec<_>>().join("/")
+}
+
+/// The innermost loop iteration of a scope, as (loop id, iteration)
+pub fn innermost_iteration(scope: &str) -> Option<(&str, usize)> {
+    let segment = scope.rsplit('/').next().filter(|s| !s.is_empty())?;
+    let (loop_id, iteration) = segment.rsplit_once('#')?;
+    Some((loop_id, iteration.parse().ok()?))
+}
+
+/// The scope the loop of the innermost iteration runs in
+pub fn parent_scope(scope: &str) -> String {
+    match scope.rsplit_once('/') {
+        Some((parent, _)) => parent.to_string(),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // Nodes as (id, worker type) and edges as (source, source handle, target)
+    fn flow(nodes: &[(&str, &str)], edges: &[(&str, &str, &str)]) -> JsonValue {
+        json!({
+            "nodes": nodes.iter().map(|(id, worker_type)| json!({ "id": id, "data": { "worker_type": worker_type } })).collect::<Vec<_>>(),
+            "edges": edges.iter().enumerate().map(|(i, (source, handle, target))| {
+                let mut edge = json!({ "id": format!("e{}", i), "source": source, "target": target });
+                if !handle.is_empty() {
+                    edge["sourceHandle"] = json!(handle);
+                }
+                edge
+            }).collect::<Vec<_>>(),
+        })
+    }
+
+    // start -> outer loop 2 -> inner loop 3 -> 5, back to 3; 3 done -> 6, back to 2; 2 done -> 7
+    fn nested_loops() -> FlowGraph {
+        FlowGraph::new(&flow(
+            &[("1", "start"), ("2", "loop"), ("3", "loop"), ("5", "rest"), ("6", "rest"), ("7", "rest")],
+            &[
+                ("1", "", "2"),
+                ("2", "body", "3"),
+                ("3", "body", "5"),
+                ("5", "", "3"),
+                ("3", "done", "6"),
+                ("6", "", "2"),
+                ("2", "done", "7"),
+            ],
+        ))
+    }
+
+    #[test]
+    fn back_edges_of_loops_are_not_cycles() {
+        let graph = nested_loops();
+        assert_eq!(graph.find_cycle(), None);
+        let back: Vec<(&str, &str)> = graph
+            .node_ids()
+            .iter()
+            .flat_map(|id| graph.outgoing(id))
+            .filter(|e| e.back)
+            .map(|e| (e.source.as_str(), e.target.as_str()))
+            .collect();
+        assert_eq!(back, vec![("5", "3"), ("6", "2")]);
+    }
+
+    #[test]
+    fn cycles_without_a_loop_node_are_found() {
+        let graph = FlowGraph::new(&flow(
+            &[("1", "start"), ("2", "rest"), ("3", "rest")],
+            &[("1", "", "2"), ("2", "", "3"), ("3", "", "2")],
+        ));
+        assert_eq!(graph.find_cycle(), Some(vec!["2".to_string(), "3".to_string(), "2".to_string()]));
+    }
+
+    #[test]
+    fn enclosing_loops_are_outermost_first() {
+        let graph = nested_loops();
+        assert_eq!(graph.enclosing_loops("5"), ["2", "3"]);
+        assert_eq!(graph.enclosing_loops("6"), ["2"]);
+        assert!(graph.enclosing_loops("7").is_empty());
+        assert!(graph.enclosing_loops("1").is_empty());
+    }
+
+    #[test]
+    fn scopes_nest_loop_iterations() {
+        let outer = child_scope("", "2", 0);
+        let inner = child_scope(&outer, "3", 4);
+        assert_eq!(outer, "2#0");
+        assert_eq!(inner, "2#0/3#4");
+        assert_eq!(innermost_iteration(&inner), Some(("3", 4)));
+        assert_eq!(innermost_iteration(""), None);
+        assert_eq!(parent_scope(&inner), "2#0");
+        assert_eq!(parent_scope(&outer), "");
+        assert_eq!(truncate_scope(&inner, 1), "2#0");
+        assert_eq!(truncate_scope(&inner, 0), "");
+    }
+}
@@ -0,0 +1,107 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+use tokio::time::{sleep, Duration};
+use tracing::info;
+
+use crate::config::get_app_dir;
+use crate::engine::Engine;
+
+const LIMITS_FILE: &str = "limits.toml";
+
+// Window the per host request limits count over
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Limits shared by every flow and stage. Stored in `limits.toml` in the app dir.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EngineLimits {
+    /// Most events of a worker type running at once, e.g. `rest = 4`
+    #[serde(default)]
+    pub worker_types: BTreeMap<String, usize>,
+    /// Most requests a minute rest nodes send to a host, e.g. `"api.github.com" = 60`
+    #[serde(default)]
+    pub requests_per_minute: BTreeMap<String, usize>,
+}
+
+impl EngineLimits {
+    pub fn load() -> EngineLimits {
+        get_app_dir()
+            .ok()
+            .and_then(|dir| fs::read_to_string(dir.join(LIMITS_FILE)).ok())
+            .and_then(|content| toml::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        let content = toml::to_string(self)?;
+        fs::write(get_app_dir()?.join(LIMITS_FILE), content)?;
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some((worker_type, _)) = self.worker_types.iter().find(|(_, limit)| **limit == 0) {
+            return Err(format!("The limit for {} workers must be at least 1", worker_type));
+        }
+        if let Some((host, _)) = self.requests_per_minute.iter().find(|(_, limit)| **limit == 0) {
+            return Err(format!("The request limit for {} must be at least 1", host));
+        }
+        Ok(())
+    }
+
+    /// Worker types that already run as many events as they may
+    pub fn saturated_worker_types(&self, running: &HashMap<String, usize>) -> Vec<String> {
+        self.worker_types
+            .iter()
+            .filter(|(worker_type, limit)| running.get(worker_type.as_str()).copied().unwrap_or(0) >= **limit)
+            .map(|(worker_type, _)| worker_type.clone())
+            .collect()
+    }
+}
+
+/// When requests to each rate limited host were sent in the last minute
+#[derive(Default)]
+pub struct HostRateLimiter(Mutex<HashMap<String, VecDeque<Instant>>>);
+
+/// Waits until a request to `url` fits in its host's limit. Hosts without a limit go straight through.
+pub async fn wait_for_host(engine: &Engine, url: &str) {
+    let host = match reqwest::Url::parse(url).ok().and_then(|url| url.host_str().map(str::to_string)) {
+        Some(host) => host,
+        None => return,
+    };
+    let limit = match EngineLimits::load().requests_per_minute.get(&host) {
+        Some(limit) => *limit,
+        None => return,
+    };
+
+    loop {
+        let wait = {
+            let mut hosts = engine.rate_limiter.0.lock().unwrap();
+            let sent = hosts.entry(host.clone()).or_default();
+            let now = Instant::now();
+            while sent.front().is_some_and(|at| now.duration_since(*at) >= RATE_WINDOW) {
+                sent.pop_front();
+            }
+            if sent.len() < limit {
+                sent.push_back(now);
+                return;
+            }
+            // The oldest request leaves the window first
+            RATE_WINDOW - now.duration_since(sent[0])
+        };
+        info!("Rate limit for {} reached, waiting {:?}", host, wait);
+        sleep(wait).await;
+    }
+}
+
+pub fn get_engine_limits() -> EngineLimits {
+    EngineLimits::load()
+}
+
+pub fn set_engine_limits(limits: EngineLimits) -> Result<EngineLimits, String> {
+    limits.validate()?;
+    limits.save().map_err(|e| e.to_string())?;
+    Ok(limits)
+}
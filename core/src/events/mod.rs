@@ -0,0 +1,881 @@
+use tokio::time::{sleep, Duration};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use tracing::{debug, error, info, warn};
+use crate::sql::Error;
+use crate::db::stage_from_db;
+use crate::engine::Engine;
+use serde_json::Value as JsonValue;
+use uuid::Uuid;
+
+use crate::notifications::Event; 
+use tokio::process::Command;
+use std::collections::BTreeMap;
+
+pub mod claims;
+pub mod condition;
+pub mod context;
+pub mod control;
+pub mod dry_run;
+pub mod graph;
+pub mod limits;
+pub mod pause;
+pub mod priority;
+pub mod progress;
+pub mod replay;
+pub mod rest; 
+pub mod retention;
+pub mod session;
+pub mod snapshot;
+pub mod subflow;
+pub mod triggers;
+use control::{run_loop, run_map, run_router, taken_handles, waits};
+use graph::FlowGraph;
+use progress::{advance, node_context, record_node_result, record_trigger, SETTLED_EVENT_STATUSES};
+use snapshot::{session_snapshot, start_session, take_snapshot};
+use pause::{run_approval, run_delay, run_wait_until, wake_due_events};
+use claims::{claim_event, keep_lease, recover_interrupted_events, running_sessions_sql};
+use limits::{wait_for_host, EngineLimits};
+use priority::{event_priority_sql, record_session_priority};
+use session::{cancel_session_tree, HELD_SESSION_STATUSES};
+use subflow::{run_subflow, wake_parent};
+use crate::file_manager::flow_index::FlowIndex;
+use crate::file_manager::settings::{ConcurrencyPolicy, FlowSettings};
+use crate::file_manager::validation::validate;
+use rest::{ApiRequest, call_api, send_request}; 
+
+extern crate chrono;
+use chrono::Utc; 
+
+/// Worker types `execute_worker_task` knows how to run
+pub const SUPPORTED_WORKER_TYPES: [&str; 10] = [
+    "start", "rest", "terminal", "loop", "router", "map", "subflow", "delay", "wait_until", "approval",
+];
+
+/// Lets work like restoring a backup stop the scheduler from picking up events
+/// and wait for the ones already running to finish.
+#[derive(Default)]
+pub struct SchedulerControl {
+    paused: AtomicBool,
+    in_flight: AtomicUsize,
+}
+
+impl SchedulerControl {
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::Release);
+    }
+
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::Release);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Acquire)
+    }
+
+    pub async fn wait_until_idle(&self) {
+        while self.in_flight.load(Ordering::Acquire) > 0 {
+            sleep(Duration::from_millis(100)).await;
+        }
+    }
+}
+
+pub async fn scheduler(engine: &Engine){
+    loop {
+        if engine.control.is_paused() {
+            debug!("Scheduler is paused");
+            sleep(Duration::from_secs(4)).await; 
+            continue;
+        }
+
+        // Events whose worker went away with a lease that ran out
+        recover_interrupted_events(engine).await;
+
+        // Every loaded stage database gets its own pass so "prod" keeps running while testing in "dev"
+        let dbs = engine.db.loaded().await;
+
+        for db in dbs {
+            let engine = engine.clone(); 
+            engine.control.in_flight.fetch_add(1, Ordering::AcqRel);
+  
+            tokio::spawn(async move {
+                wake_due_events(&engine, &db).await;
+                process(&engine, &db).await;
+                engine.control.in_flight.fetch_sub(1, Ordering::AcqRel);
+            });
+        }
+
+       sleep(Duration::from_secs(4)).await; 
+    }
+}
+
+//TODO: write it bettter. This nesting makes me ill
+async fn process(engine: &Engine, db: &str) {
+
+    let res = fetch_event(engine, db).await;
+
+    match res {
+        Ok(items) => {
+            if let Some(item) = items.first() { 
+                    if let Some(worker_type) = item.get("worker_type") {
+                            if let Some(worker_type_str) = worker_type.as_str() {
+                                    let settings = event_settings(engine, db, item).await;
+                                    let limit = if worker_type_str == "start" { settings.concurrency_limit } else { None };
+                                    if limit.is_some() && at_concurrency_limit(engine, db, item, &settings).await {
+                                        match settings.concurrency_policy {
+                                            // Queued starts over the limit aren't fetched, see `fetch_event`,
+                                            // and the claim below checks the limit again
+                                            ConcurrencyPolicy::Queue => {}
+                                            ConcurrencyPolicy::Skip => {
+                                                skip_session(engine, db, item).await;
+                                                return;
+                                            }
+                                            ConcurrencyPolicy::ReplaceOldest => replace_oldest_session(engine, db, item).await,
+                                        }
+                                    }
+                                    let event_id = item.get("event_id").and_then(JsonValue::as_str).unwrap_or("");
+                                    let session_id = item.get("session_id").and_then(JsonValue::as_str).unwrap_or("");
+                                    if !claim_event(engine, db, event_id, limit).await {
+                                        info!("event_id: {} was claimed by another pass or its flow is at its concurrency limit", event_id);
+                                        return;
+                                    }
+                                    let lease = keep_lease(engine, db, event_id);
+                                    let running = &engine.running;
+                                    let interrupt = running.register(event_id, session_id, worker_type_str);
+                                    // Dropping the worker kills its terminal process or HTTP request
+                                    let outcome = tokio::select! {
+                                        result = run_with_policy(engine, db, worker_type_str, item, &settings) => Some(result),
+                                        _ = interrupt.notified() => None,
+                                    };
+                                    running.unregister(event_id);
+                                    lease.abort();
+                                    let result = match outcome {
+                                        Some(result) => result,
+                                        None => {
+                                            info!("event_id: {} was interrupted because its session was cancelled", event_id);
+                                            return;
+                                        }
+                                    };
+                                    match result {
+                                        Ok(result_string) => {
+                                            if waits(worker_type_str, &result_string) {
+                                                mark_as_waiting(engine, db, item, result_string).await;
+                                                return;
+                                            }
+                                            let handles = taken_handles(worker_type_str, &result_string);
+                                            save_result(engine, db, event_id.to_string(), result_string.clone()).await;
+                                            mark_as_done(engine, db, item, handles, &result_string).await;
+                                            info!("event_id: {} marked as COMPLETE after passing through execute_worker_task", event_id);
+                                            info!("Session ID: {} Evaluated", session_id) 
+                                        },
+                                        Err(err) => {
+                                            error!("Failed to execute worker task: {}", err);
+                                            mark_as_failed(engine, db, item, err).await;
+                                        }
+                                    }
+                            } else {
+                            warn!("Worker type is not a string")
+                            }                        
+                    } else {
+                        warn!("event_name not found in the item.");
+                    }
+            } else {
+                engine.notify(Event::EventProcessing { 
+                    message: "No items to process".to_string(), 
+                    event_id: "".to_string(),
+                    node_id: "".to_string(),
+                    flow_id: "".to_string(),
+                    session_id: "".to_string(),
+                     }); 
+                debug!("No items in the response.");
+            }
+        }
+        Err(err) => {
+            error!("Error: {}", err);
+        }
+    }
+}
+
+// The settings the event's session started with, or the flow's current ones if it hasn't started yet
+async fn event_settings(engine: &Engine, db: &str, item: &HashMap<String, JsonValue>) -> FlowSettings {
+    let session_id = item.get("session_id").and_then(JsonValue::as_str).unwrap_or("");
+    let flow_id = item.get("flow_id").and_then(JsonValue::as_str).unwrap_or("");
+
+    match session_snapshot(engine, db, session_id).await {
+        Ok(snapshot) => FlowSettings::from_json(&snapshot.settings).unwrap_or_else(|e| {
+            warn!("Using default settings for flow {}: {}", flow_id, e);
+            FlowSettings::default()
+        }),
+        Err(_) => {
+            let id = flow_id.to_string();
+            engine.flows.read_blocking(move |index| flow_settings(index, &id)).await.unwrap_or_else(|e| {
+                warn!("Using default settings for flow {}: {}", flow_id, e);
+                FlowSettings::default()
+            })
+        }
+    }
+}
+
+// The flow's current settings on disk. Needs the index's read lock held.
+fn flow_settings(index: &FlowIndex, flow_id: &str) -> FlowSettings {
+    let settings = match index.get(flow_id) {
+        Some(dir) => FlowSettings::load(&dir),
+        None => Ok(FlowSettings::default()),
+    };
+    settings.unwrap_or_else(|e| {
+        warn!("Using default settings for flow {}: {}", flow_id, e);
+        FlowSettings::default()
+    })
+}
+
+// The concurrency limits of the flows with starts waiting whose extra starts queue, by flow id
+async fn queued_limits(engine: &Engine, db: &str) -> std::result::Result<serde_json::Map<String, JsonValue>, Error> {
+    let query = "SELECT DISTINCT flow_id FROM events WHERE event_status = 'PENDING' AND worker_type = 'start'".to_string();
+    let rows = engine.db.select(db.to_string(), query, vec![], None).await?;
+    let flow_ids: Vec<String> = rows
+        .iter()
+        .filter_map(|row| row.get("flow_id").and_then(JsonValue::as_str))
+        .map(|flow_id| flow_id.to_string())
+        .collect();
+    let limits = engine.flows.read_blocking(move |index| {
+        let mut limits = serde_json::Map::new();
+        for flow_id in flow_ids {
+            let settings = flow_settings(index, &flow_id);
+            if let (Some(limit), ConcurrencyPolicy::Queue) = (settings.concurrency_limit, settings.concurrency_policy) {
+                limits.insert(flow_id, JsonValue::from(limit));
+            }
+        }
+        limits
+    }).await;
+    Ok(limits.unwrap_or_else(|e| {
+        warn!("Not holding back starts over their concurrency limit: {}", e);
+        serde_json::Map::new()
+    }))
+}
+
+// Sessions of this flow that have started and not finished yet
+async fn at_concurrency_limit(engine: &Engine, db: &str, item: &HashMap<String, JsonValue>, settings: &FlowSettings) -> bool {
+    let limit = match settings.concurrency_limit {
+        Some(limit) => limit,
+        None => return false,
+    };
+    let flow_id = item.get("flow_id").and_then(JsonValue::as_str).unwrap_or("");
+
+    let query = format!("SELECT {} AS running", running_sessions_sql("$1"));
+    let values = vec![JsonValue::String(flow_id.to_string())];
+    match engine.db.select(db.to_string(), query, values, None).await {
+        Ok(rows) => {
+            let running = rows.first().and_then(|row| row.get("running")).and_then(JsonValue::as_u64).unwrap_or(0);
+            running as usize >= limit
+        }
+        Err(e) => {
+            error!("Error counting running sessions for flow {}: {}", flow_id, e);
+            false
+        }
+    }
+}
+
+// Drops a start event instead of queueing it, for flows that skip starts over their limit
+async fn skip_session(engine: &Engine, db: &str, item: &HashMap<String, JsonValue>) {
+    let text = |key: &str| item.get(key).and_then(JsonValue::as_str).unwrap_or("").to_string();
+    let (event_id, flow_id, session_id) = (text("event_id"), text("flow_id"), text("session_id"));
+
+    let query = "UPDATE events SET event_status = 'SKIPPED', session_status = 'CANCELLED' WHERE event_id = $1".to_string();
+    if let Err(e) = engine.db.execute(db.to_string(), query, vec![JsonValue::String(event_id.clone())]).await {
+        error!("Error skipping start event {}: {:?}", event_id, e);
+        return;
+    }
+    engine.notify(Event::SessionCancelled {
+        message: format!("Session {} was skipped, flow {} is at its concurrency limit", session_id, flow_id),
+        flow_id,
+        session_id,
+    });
+}
+
+// Cancels the flow's longest running session to make room for the one starting
+async fn replace_oldest_session(engine: &Engine, db: &str, item: &HashMap<String, JsonValue>) {
+    let flow_id = item.get("flow_id").and_then(JsonValue::as_str).unwrap_or("");
+    let query = "SELECT session_id FROM events
+        WHERE flow_id = $1 AND session_status = 'PENDING' AND worker_type != 'start'
+        GROUP BY session_id ORDER BY MIN(created_at) ASC LIMIT 1".to_string();
+    let rows = match engine.db.select(db.to_string(), query, vec![JsonValue::String(flow_id.to_string())], None).await {
+        Ok(rows) => rows,
+        Err(e) => {
+            error!("Error finding the oldest session of flow {}: {}", flow_id, e);
+            return;
+        }
+    };
+    if let Some(oldest) = rows.first().and_then(|row| row.get("session_id")).and_then(JsonValue::as_str) {
+        info!("Flow {} is at its concurrency limit, replacing session {}", flow_id, oldest);
+        if let Err(e) = cancel_session_tree(engine, db, oldest).await {
+            error!("Error cancelling session {}: {}", oldest, e);
+        }
+    }
+}
+
+// Runs the worker with the flow's timeout and retry policy. Start events are neither
+// timed out nor retried since they only create the session's events.
+async fn run_with_policy(
+    engine: &Engine,
+    db: &str,
+    worker_type: &str,
+    item: &HashMap<String, JsonValue>,
+    settings: &FlowSettings,
+) -> std::result::Result<String, String> {
+    let mut attempt = 0;
+    loop {
+        let run = execute_worker_task(engine, db, worker_type, item, settings);
+        let result = match settings.timeout() {
+            Some(limit) if worker_type != "start" => match tokio::time::timeout(limit, run).await {
+                Ok(result) => result,
+                Err(_) => Err(format!("Timed out after {} seconds", limit.as_secs())),
+            },
+            _ => run.await,
+        };
+
+        match result {
+            Err(err) if worker_type != "start" && attempt < settings.retry.retries => {
+                attempt += 1;
+                warn!("Worker task failed: {}. Retry {} of {}", err, attempt, settings.retry.retries);
+                sleep(Duration::from_secs(settings.retry.backoff_secs)).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+async fn fetch_event(
+    engine: &Engine,
+    db: &str,
+) -> std::result::Result<Vec<HashMap<String, JsonValue>>, Error> {
+    // Access the dbInstances from the engine
+    let db_instances = &engine.db; 
+    //make Query
+    let db = db.to_string();
+    // Worker types at their limit wait, without holding up the events behind them
+    let running = engine.running.count_by_worker_type();
+    let saturated = EngineLimits::load().saturated_worker_types(&running);
+    let placeholders: Vec<String> = (0..saturated.len()).map(|i| format!("${}", i + 3)).collect();
+    let saturated_filter = if saturated.is_empty() {
+        String::new()
+    } else {
+        format!("AND events.worker_type NOT IN ({})", placeholders.join(", "))
+    };
+    // Start events of flows with as many sessions running as their limit allows wait for one to
+    // finish, without holding up the events of those sessions.
+    let limits = queued_limits(engine, &db).await?;
+    // Events of paused and cancelled sessions stay where they are. The oldest event of each
+    // flow at each priority is a candidate, so a big fan-out can't hold up other flows.
+    let query = format!("SELECT * FROM (
+            SELECT events.*, {priority} AS effective_priority,
+                ROW_NUMBER() OVER (PARTITION BY events.flow_id, {priority} ORDER BY events.created_at ASC, events.rowid ASC) AS flow_rank
+            FROM events LEFT JOIN sessions ON sessions.session_id = events.session_id
+            WHERE events.event_status = $1 {saturated}
+            AND NOT (events.worker_type = 'start' AND EXISTS (
+                SELECT 1 FROM json_each($2) AS queued WHERE queued.key = events.flow_id AND queued.value <= {running}))
+            AND NOT EXISTS (SELECT 1 FROM events AS held WHERE held.session_id = events.session_id AND held.session_status IN {held})
+        ) WHERE flow_rank = 1
+        ORDER BY effective_priority DESC, created_at ASC",
+        priority = event_priority_sql(), saturated = saturated_filter, held = HELD_SESSION_STATUSES,
+        running = running_sessions_sql("events.flow_id"));
+    let mut values = vec![
+        JsonValue::String("PENDING".to_string()),
+        JsonValue::String(JsonValue::Object(limits).to_string()),
+    ];
+    values.extend(saturated.into_iter().map(JsonValue::String));
+    
+    debug!("Fetching Next Event"); 
+    // Call the select function with the fetched dbInstances state
+    let candidates = db_instances.select(db.clone(), query, values, None).await?;
+    Ok(engine.fair_queue.pick(&db, candidates).into_iter().collect())
+}
+
+// Queues a node of the session's flow to run in `scope`, or records that it was skipped
+async fn create_event(
+    engine: &Engine,
+    db: &str,
+    node: &JsonValue,
+    flow_info: &JsonValue,
+    session_id: &str,
+    scope: &str,
+    status: &str,
+) -> std::result::Result<(), Error> {
+    insert_event(engine, db, node, flow_info, session_id, scope, status, "").await
+}
+
+// `data` is what the session was started with when the node is a start node
+#[allow(clippy::too_many_arguments)]
+async fn insert_event(
+    engine: &Engine,
+    db: &str,
+    node: &JsonValue,
+    flow_info: &JsonValue,
+    session_id: &str,
+    scope: &str,
+    status: &str,
+    data: &str,
+) -> std::result::Result<(), Error> {
+    let db_instances = &engine.db; 
+
+    let stage = stage_from_db(db);
+    let db = db.to_string();
+
+    let node_id = node.get("id").and_then(|v| v.as_str()).unwrap_or_default();
+    let node_type = node.get("type").and_then(|v| v.as_str()).unwrap_or_default();
+    let node_data = node.get("data").cloned().unwrap_or_default();
+    let worker_type = node_data.get("worker_type").and_then(|wt| wt.as_str()).unwrap_or_default();
+    let worker_name = node_data.get("worker_name").and_then(|wt| wt.as_str()).unwrap_or_default();
+    let node_label = node_data.get("node_label").and_then(|wt| wt.as_str()).unwrap_or_default();
+
+    let flow_id = flow_info.get("id").and_then(|v| v.as_str()).unwrap_or_default();
+    let flow_name = flow_info.get("name").and_then(|v| v.as_str()).unwrap_or_default();
+    let flow_version = flow_info.get("version").and_then(|v| v.as_str()).unwrap_or_default();
+
+    let query = "
+        INSERT INTO events (event_id, session_id, node_id, node_type, node_label, flow_id, flow_name, flow_version, stage, worker_type, worker_name, event_status, session_status, created_at, data, event_context, scope) 
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17)
+    ";
+
+    let values = vec![
+        JsonValue::String(Uuid::new_v4().to_string()),       // event_id
+        JsonValue::String(session_id.to_string()),           // session_id
+        JsonValue::String(node_id.to_string()),              // node_id
+        JsonValue::String(node_type.to_string()),            // node_type
+        JsonValue::String(node_label.to_string()),           // node_label
+        JsonValue::String(flow_id.to_string()),              // flow_id
+        JsonValue::String(flow_name.to_string()),            // flow_name
+        JsonValue::String(flow_version.to_string()),         // flow_version
+        JsonValue::String(stage),                            // stage
+        JsonValue::String(worker_type.to_string()),          // worker_type
+        JsonValue::String(worker_name.to_string()),          // worker_name
+        JsonValue::String(status.to_string()),               // event_status
+        JsonValue::String("PENDING".to_string()),            // session_status
+        JsonValue::String(Utc::now().to_rfc3339()),          // created_at
+        JsonValue::String(data.to_string()),                 // data
+        JsonValue::String(node_data.to_string()),            // event_context
+        JsonValue::String(scope.to_string()),                // scope
+    ];
+
+    match db_instances.execute(db, query.to_string(), values).await {
+        Ok(_) => {
+            info!("Node {} is {} in scope {:?}", node_id, status, scope);
+            Ok(())
+        },
+        Err(e) => {
+            error!("Error adding event to db: {}", e);
+            Err(e)          
+        },
+    }
+}
+
+/// Starts a new session of the flow with `input` as what it was triggered with
+pub async fn trigger_flow(engine: &Engine, db: &str, flow_id: &str, input: &str) -> Result<String, String> {
+    let (start, flow_info) = subflow::flow_start(engine, flow_id).await?;
+    let session_id = Uuid::new_v4().to_string();
+    insert_event(engine, db, &start, &flow_info, &session_id, "", "PENDING", input)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(session_id)
+}
+
+// Completes the event, queues the nodes that can run next and completes the session
+// once nothing is left to run
+async fn mark_as_done(
+    engine: &Engine,
+    db: &str,
+    event_data: &HashMap<String, JsonValue>,
+    handles: Option<Vec<String>>,
+    result: &str,
+) {
+    let db_instances = &engine.db; 
+
+    let node_id = event_data.get("node_id").and_then(JsonValue::as_str).unwrap_or("").to_string();
+    let flow_id = event_data.get("flow_id").and_then(JsonValue::as_str).unwrap_or("").to_string();
+    let event_id = event_data.get("event_id").and_then(JsonValue::as_str).unwrap_or("").to_string();
+    let session_id = event_data.get("session_id").and_then(JsonValue::as_str).unwrap_or("").to_string();
+    let scope = event_data.get("scope").and_then(JsonValue::as_str).unwrap_or("").to_string();
+
+    let db = db.to_string();
+    let update_event_query = "UPDATE events
+    SET event_status = 'COMPLETE', handles = $1
+    WHERE event_id = $2 AND event_status != 'CANCELLED'".to_string();
+    let handles_value = match &handles {
+        Some(handles) => JsonValue::String(serde_json::to_string(handles).unwrap_or_default()),
+        None => JsonValue::Null,
+    };
+    let values = vec![handles_value, JsonValue::String(event_id.clone())];
+
+    
+    match db_instances.execute(db.clone(), update_event_query, values).await {
+        Ok((0, _)) => {
+            info!("event_id: {} was cancelled while it ran", event_id);
+            return;
+        }
+        Ok(_) => {}
+        Err(e) => {
+            error!("Error executing the query to set Event to COMPLETE: {:?}", e);
+            return;
+        }
+    }
+
+    if let Err(e) = record_node_result(engine, &db, &session_id, &node_id, &scope, result).await {
+        error!("Error recording result of node {}: {}", node_id, e);
+    }
+
+    if let Err(e) = advance(engine, &db, &session_id, &node_id, &scope, handles).await {
+        mark_as_failed(engine, &db, event_data, format!("Could not queue the next nodes: {}", e)).await;
+        return;
+    }
+
+     // Check if all events with the same session_id are 'COMPLETE' or 'SKIPPED'
+     let check_events_query = format!("
+     SELECT COUNT(*)
+     FROM events
+     WHERE session_id = $1 AND event_status NOT IN {}", SETTLED_EVENT_STATUSES);
+     let values = vec![JsonValue::String(session_id.clone())];
+
+     let response = db_instances.select(db.clone(), check_events_query, values, None).await; 
+
+     if let Ok(rows) = response {
+        if let Some(first_row) = rows.first() {
+            debug!("first_row: {:?}", first_row);
+            if let Some(number) = first_row.get("COUNT(*)") {
+                // Now `number` contains the number you are looking for
+                debug!("The count of session events left is: {:?}", number);
+
+                    debug!("count in mark_as_done: {}", number);
+                    if number == 0 {
+                        info!("Setting all session events as complete"); 
+                        // If all events are 'COMPLETE', update Session_status to 'COMPLETE'
+                        let update_session_query = "
+                        UPDATE events
+                        SET session_status = 'COMPLETE'
+                        WHERE session_id = $1".to_string();
+                        let values = vec![JsonValue::String(session_id.clone())];
+
+                        if let Err(e) = db_instances.execute(db.clone(), update_session_query, values).await {
+                            error!("Error executing the query: {:?}", e);
+                        }
+                        wake_parent(engine, &db, &session_id).await;
+                        engine.notify(Event::SessionComplete { 
+                            event_id: event_id.to_string(),
+                            node_id: node_id.to_string(),
+                            flow_id: flow_id.to_string(),
+                            session_id: session_id.clone().to_string(),
+                        });
+                    }
+            } else {
+                warn!("The key 'COUNT(*)' was not found in the row");
+            }
+        } else {
+            warn!("No rows returned");
+        }
+    } else {
+        error!("An error occurred");
+    }
+        
+  
+}
+
+// Parks an event that is waiting on something outside the scheduler: a time, an approval
+// or a subflow session. The scheduler wakes it at resume_at, everything else queues it again.
+async fn mark_as_waiting(engine: &Engine, db: &str, event_data: &HashMap<String, JsonValue>, result: String) {
+    let node_id = event_data.get("node_id").and_then(JsonValue::as_str).unwrap_or("");
+    let flow_id = event_data.get("flow_id").and_then(JsonValue::as_str).unwrap_or("");
+    let event_id = event_data.get("event_id").and_then(JsonValue::as_str).unwrap_or("");
+    let session_id = event_data.get("session_id").and_then(JsonValue::as_str).unwrap_or("");
+
+    let parsed: JsonValue = serde_json::from_str(&result).unwrap_or_default();
+    let resume_at = match parsed.get("resume_at").and_then(JsonValue::as_str) {
+        Some(resume_at) => JsonValue::String(resume_at.to_string()),
+        None => JsonValue::Null,
+    };
+
+    let query = "UPDATE events
+    SET event_status = 'WAITING', event_result = $1, resume_at = $2
+    WHERE event_id = $3 AND event_status != 'CANCELLED'".to_string();
+    let values = vec![JsonValue::String(result), resume_at.clone(), JsonValue::String(event_id.to_string())];
+
+    match engine.db.execute(db.to_string(), query, values).await {
+        Ok((0, _)) => return,
+        Ok(_) => {}
+        Err(e) => {
+            error!("Error executing the query to set Event to WAITING: {:?}", e);
+            return;
+        }
+    }
+    info!("event_id: {} is WAITING", event_id);
+
+    engine.notify(Event::EventWaiting {
+        message: parsed.get("message").and_then(JsonValue::as_str).unwrap_or("Waiting").to_string(),
+        resume_at: resume_at.as_str().map(|s| s.to_string()),
+        event_id: event_id.to_string(),
+        node_id: node_id.to_string(),
+        flow_id: flow_id.to_string(),
+        session_id: session_id.to_string(),
+    });
+}
+
+async fn save_result(
+    engine: &Engine,
+    db: &str,
+    event_id: String,
+    result: String,
+) {
+    let db_instances = &engine.db; 
+
+    let db = db.to_string();
+    let update_event_query = "UPDATE events
+    SET event_result = $1
+    WHERE event_id = $2".to_string();
+    let values = vec![JsonValue::String(result.clone()), JsonValue::String(event_id.clone())];
+
+    if let Err(e) = db_instances.execute(db.clone(), update_event_query, values).await {
+        error!("Error executing the query to set Event result to processor result: {:?}", e);
+    }
+}
+
+// Marks the event and its session FAILED so it isn't picked up again, and tells the UI why
+async fn mark_as_failed(
+    engine: &Engine,
+    db: &str,
+    event_data: &HashMap<String, JsonValue>,
+    error: String,
+) {
+    let db_instances = &engine.db; 
+
+    let db = db.to_string();
+    let node_id = event_data.get("node_id").and_then(JsonValue::as_str).unwrap_or("");
+    let flow_id = event_data.get("flow_id").and_then(JsonValue::as_str).unwrap_or("");
+    let event_id = event_data.get("event_id").and_then(JsonValue::as_str).unwrap_or("");
+    let session_id = event_data.get("session_id").and_then(JsonValue::as_str).unwrap_or("");
+
+    let update_event_query = "UPDATE events
+    SET event_status = 'FAILED', event_result = $1
+    WHERE event_id = $2 AND event_status != 'CANCELLED'".to_string();
+    let result = serde_json::json!({ "error": error }).to_string();
+    let values = vec![JsonValue::String(result), JsonValue::String(event_id.to_string())];
+
+    match db_instances.execute(db.clone(), update_event_query, values).await {
+        Ok((0, _)) => return,
+        Ok(_) => {}
+        Err(e) => {
+            error!("Error executing the query to set Event to FAILED: {:?}", e);
+            return;
+        }
+    }
+
+    let update_session_query = "UPDATE events
+    SET session_status = 'FAILED'
+    WHERE session_id = $1 AND session_status != 'CANCELLED'".to_string();
+    let values = vec![JsonValue::String(session_id.to_string())];
+
+    if let Err(e) = db_instances.execute(db.clone(), update_session_query, values).await {
+        error!("Error executing the query to set Session to FAILED: {:?}", e);
+    }
+    wake_parent(engine, &db, session_id).await;
+
+    engine.notify(Event::EventFailed {
+        message: error,
+        event_id: event_id.to_string(),
+        node_id: node_id.to_string(),
+        flow_id: flow_id.to_string(),
+        session_id: session_id.to_string(),
+    });
+}
+
+// Starts a session: freezes the flow, checks it can run and records the snapshot. The
+// nodes after the start node are queued as the ones before them finish.
+async fn begin_session(engine: &Engine, db: &str, flow_id: &str, node_id: &str, session_id: &str, input: &str) -> std::result::Result<(), String> {
+      let snapshot = prepare_session(engine, db, flow_id, session_id).await?;
+      let graph = FlowGraph::new(&snapshot.definition);
+      if graph.start_node() != Some(node_id) {
+          return Err(format!("Node {} is not the start node of flow {}", node_id, flow_id));
+      }
+      let trigger = graph.data(node_id).get("worker_name").and_then(JsonValue::as_str).unwrap_or("");
+      let settings = FlowSettings::from_json(&snapshot.settings)?;
+      record_session_priority(engine, db, session_id, settings.priority, trigger).await?;
+      record_trigger(engine, db, session_id, input).await?;
+      Ok(())
+}
+
+// Freezes the flow as it is right now, checks it can run and records the session.
+// Edits made while the session runs don't leak into it.
+async fn prepare_session(engine: &Engine, db: &str, flow_id: &str, session_id: &str) -> std::result::Result<snapshot::FlowSnapshot, String> {
+      let snapshot = take_snapshot(engine, db, flow_id).await?;
+      let report = validate(&snapshot.definition);
+      if !report.valid {
+          return Err(format!("Flow {} is invalid: {}", flow_id, report.error_summary()));
+      }
+      let settings = FlowSettings::from_json(&snapshot.settings)?;
+      if !settings.enabled {
+          return Err(format!("Flow {} is disabled in its settings", flow_id));
+      }
+      start_session(engine, db, session_id, &snapshot).await?;
+
+      info!("Session {} runs against flow snapshot {}", session_id, snapshot.snapshot_hash); 
+      Ok(snapshot)
+}
+
+// `values` are what the command's placeholders rendered to, passed as environment variables
+async fn run_terminal_command(cmd: &str, env: &BTreeMap<String, String>, values: &BTreeMap<String, String>) -> std::result::Result<String, String> {
+ 
+    debug!("Running command: {}", cmd);
+
+   // kill_on_drop so a timed out node doesn't leave the process running
+   let output = Command::new("sh")
+    .arg("-c")
+    .arg(cmd)
+    .envs(env)
+    .envs(values)
+    .kill_on_drop(true)
+    .output()
+    .await
+    .map_err(|e| e.to_string())?; 
+
+    
+    if output.status.success() {
+        let s = String::from_utf8_lossy(&output.stdout);
+        Ok(s.to_string())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        Err(format!("{}: {}", output.status, stderr.trim()))
+    }
+}
+
+// The request a rest node sends for its rendered fields
+fn rest_request(context_json: &JsonValue) -> ApiRequest {
+    let method = context_json["method"].as_str().unwrap_or_default().to_string();
+    let url = context_json["url"].as_str().unwrap_or_default().to_string();
+    let headers_str = context_json["headers"].as_str().unwrap_or("");
+
+    let headers_map = serde_json::from_str::<HashMap<String, String>>(headers_str).ok();
+
+    // Assuming body is a JSON object or string, convert to a serialized string
+    let body = match &context_json["body"] {
+        JsonValue::Object(obj) => Some(serde_json::to_string(obj).unwrap_or_default()),
+        JsonValue::Array(arr) => Some(serde_json::to_string(arr).unwrap_or_default()),
+        JsonValue::String(s) => Some(s.clone()),
+        _ => None,
+    };
+
+    ApiRequest {
+        method,
+        url,
+        headers: headers_map,
+        body,
+    }
+}
+
+async fn run_rest(engine: &Engine, context_json: &JsonValue) -> std::result::Result<String, String> {
+    let api_request = rest_request(context_json);
+    debug!("api_request: {:?}", api_request);
+    wait_for_host(engine, &api_request.url).await;
+
+    // Lets a router after this node branch on the status instead of the session failing
+    let continue_on_error = matches!(&context_json["continue_on_error"], JsonValue::Bool(true))
+        || context_json["continue_on_error"].as_str() == Some("true");
+    if continue_on_error {
+        return match send_request(api_request).await {
+            Ok(response) => {
+                let body = serde_json::from_str(&response.body).unwrap_or(JsonValue::String(response.body));
+                Ok(serde_json::json!({ "status": response.status, "body": body }).to_string())
+            },
+            Err(e) => Err(e.to_string())
+        };
+    }
+
+    match call_api(api_request).await {
+        Ok(result) => Ok(result),
+        Err(e) => Err(e.to_string())
+    }
+}
+
+//gets marked as done after it leaves here. Kinda a bad pattern i think
+async fn execute_worker_task(engine: &Engine, db: &str, worker_type: &str, event_data: &HashMap<String, JsonValue>, settings: &FlowSettings) -> std::result::Result<String, String> {
+
+    // Get values for eventProcessing Message
+    let node_id = event_data.get("node_id").and_then(JsonValue::as_str).unwrap_or("");
+    let flow_id = event_data.get("flow_id").and_then(JsonValue::as_str).unwrap_or("");
+    let event_id = event_data.get("event_id").and_then(JsonValue::as_str).unwrap_or("");
+    let session_id = event_data.get("session_id").and_then(JsonValue::as_str).unwrap_or("");
+    let scope = event_data.get("scope").and_then(JsonValue::as_str).unwrap_or("");
+
+    //write message 
+    let message = format!("Executing Worker Task: {} for node_id: {} and flow_id: {} and event_id: {}", worker_type, node_id, flow_id, event_id);
+
+    engine.notify(Event::EventProcessing {    
+        message,
+        event_id: event_id.to_string(),
+        node_id: node_id.to_string(),
+        flow_id: flow_id.to_string(),
+        session_id: session_id.to_string()
+         }); 
+
+    // Fills in {{settings.*}}, {{secrets.*}} and earlier results in the node's fields
+    let node_context = node_context(engine, db, settings, session_id, scope).await;
+   
+    match worker_type {
+        "start" => {
+            if flow_id.is_empty() {
+                return Err("flow_id is missing".to_string());
+            }
+            let input = event_data.get("data").and_then(JsonValue::as_str).unwrap_or("");
+            begin_session(engine, db, flow_id, node_id, session_id, input).await?;
+            Ok("{\"status\": \"session started\"}".to_string())
+        },
+        "rest" => { 
+            let context_str = event_data["event_context"].as_str().unwrap_or("");
+            let context_json: JsonValue = node_context.render(&serde_json::from_str(context_str).unwrap_or_default());
+
+            run_rest(engine, &context_json).await
+        },
+        "terminal" => {
+            let context_str = event_data["event_context"].as_str().unwrap_or("");
+            let context_json: JsonValue = serde_json::from_str(context_str).unwrap_or_default();
+
+            // Rendered values reach the command as variables so they can't inject shell code
+            let (command, values) = node_context.render_shell(context_json["command"].as_str().unwrap_or_default());
+              
+            match run_terminal_command(&command, &settings.env, &values).await {
+                Ok(result) => Ok(result),
+                Err(e) => Err(format!("Terminal command failed: {}", e))
+            }
+        },
+        "loop" => {
+            let context_str = event_data["event_context"].as_str().unwrap_or("");
+            let context_json: JsonValue = node_context.render(&serde_json::from_str(context_str).unwrap_or_default());
+
+            run_loop(engine, db, event_data, &context_json, &node_context).await
+        },
+        "router" => {
+            let context_str = event_data["event_context"].as_str().unwrap_or("");
+            let context_json: JsonValue = node_context.render(&serde_json::from_str(context_str).unwrap_or_default());
+
+            run_router(engine, db, event_data, &context_json, node_context).await
+        },
+        "map" => {
+            let context_str = event_data["event_context"].as_str().unwrap_or("");
+            let context_json: JsonValue = node_context.render(&serde_json::from_str(context_str).unwrap_or_default());
+
+            run_map(engine, db, event_data, &context_json, node_context).await
+        },
+        "delay" | "wait_until" | "approval" => {
+            let context_str = event_data["event_context"].as_str().unwrap_or("");
+            let context_json: JsonValue = node_context.render(&serde_json::from_str(context_str).unwrap_or_default());
+
+            match worker_type {
+                "delay" => run_delay(event_data, &context_json),
+                "wait_until" => run_wait_until(event_data, &context_json),
+                _ => run_approval(event_data, &context_json),
+            }
+        },
+        "subflow" => {
+            let context_str = event_data["event_context"].as_str().unwrap_or("");
+            let context_json: JsonValue = node_context.render(&serde_json::from_str(context_str).unwrap_or_default());
+
+            run_subflow(engine, db, event_data, &context_json).await
+        },
+        _ => Err(format!("Unknown worker type: {}", worker_type))
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use serde_json::{json, Value as JsonValue};
+use tracing::{error, info};
+
+use crate::engine::Engine;
+
+/// Source handles of an approval node
+pub const APPROVED_HANDLE: &str = "approved";
+pub const REJECTED_HANDLE: &str = "rejected";
+
+// Waiting nodes that can be resumed from the UI before their time is up
+const RESUMABLE_WORKER_TYPES: [&str; 3] = ["delay", "wait_until", "approval"];
+
+type Row = HashMap<String, JsonValue>;
+
+fn text<'a>(row: &'a Row, key: &str) -> &'a str {
+    row.get(key).and_then(JsonValue::as_str).unwrap_or("")
+}
+
+// One format everywhere so resume_at and leases can be compared as text in SQL
+pub fn timestamp(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// A delay in seconds, or with a unit: `90`, `"30s"`, `"5m"`, `"2h"`, `"1d"`
+pub fn parse_duration(value: &JsonValue) -> Result<Duration, String> {
+    let invalid = || format!("Invalid duration {}, expected seconds or a number with s, m, h or d", value);
+    let seconds = match value {
+        JsonValue::Number(n) => n.as_u64().ok_or_else(invalid)?,
+        JsonValue::String(s) => {
+            let s = s.trim();
+            let (number, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+                Some(position) => s.split_at(position),
+                None => (s, "s"),
+            };
+            let number: u64 = number.parse().map_err(|_| invalid())?;
+            let multiplier = match unit.trim() {
+                "s" => 1,
+                "m" => 60,
+                "h" => 60 * 60,
+                "d" => 24 * 60 * 60,
+                _ => return Err(invalid()),
+            };
+            number.checked_mul(multiplier).ok_or_else(invalid)?
+        }
+        _ => return Err(invalid()),
+    };
+    i64::try_from(seconds).map(Duration::seconds).map_err(|_| invalid())
+}
+
+pub fn parse_until(value: &str) -> Result<DateTime<Utc>, String> {
+    DateTime::parse_from_rfc3339(value.trim())
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|e| format!("Invalid timestamp {:?}, expected RFC 3339: {}", value, e))
+}
+
+// Set once the event has waited, so the run after it was woken completes it
+fn resumed_from(event_data: &Row) -> Option<&str> {
+    event_data
+        .get("resume_at")
+        .and_then(JsonValue::as_str)
+        .filter(|resume_at| !resume_at.is_empty())
+}
+
+/// Waits `duration` the first time it runs and completes when it is woken.
+pub fn run_delay(event_data: &Row, data: &JsonValue) -> Result<String, String> {
+    if let Some(resume_at) = resumed_from(event_data) {
+        return Ok(json!({ "waited_until": resume_at, "resumed_at": timestamp(Utc::now()) }).to_string());
+    }
+    let duration = parse_duration(data.get("duration").ok_or("Delay node needs a duration")?)?;
+    Ok(json!({ "waiting": true, "resume_at": timestamp(Utc::now() + duration) }).to_string())
+}
+
+/// Waits until `until` unless that has already passed.
+pub fn run_wait_until(event_data: &Row, data: &JsonValue) -> Result<String, String> {
+    if let Some(resume_at) = resumed_from(event_data) {
+        return Ok(json!({ "waited_until": resume_at, "resumed_at": timestamp(Utc::now()) }).to_string());
+    }
+    let until = parse_until(data.get("until").and_then(JsonValue::as_str).ok_or("Wait node needs an until timestamp")?)?;
+    if until <= Utc::now() {
+        return Ok(json!({ "waited_until": timestamp(until), "resumed_at": timestamp(Utc::now()) }).to_string());
+    }
+    Ok(json!({ "waiting": true, "resume_at": timestamp(until) }).to_string())
+}
+
+/// Waits for `resume_event` and then leaves through the approved or rejected handle.
+pub fn run_approval(event_data: &Row, data: &JsonValue) -> Result<String, String> {
+    let decision: JsonValue = serde_json::from_str(text(event_data, "event_result")).unwrap_or_default();
+    if let Some(approved) = decision.get("approved").and_then(JsonValue::as_bool) {
+        let next = if approved { APPROVED_HANDLE } else { REJECTED_HANDLE };
+        return Ok(json!({ "approved": approved, "note": decision["note"], "next": next }).to_string());
+    }
+    let message = data.get("message").and_then(JsonValue::as_str).unwrap_or("Waiting for approval");
+    Ok(json!({ "waiting": true, "message": message }).to_string())
+}
+
+/// Queues waiting events whose time has come. Runs every scheduler pass, so waits
+/// carry on where they left off after a restart.
+pub async fn wake_due_events(engine: &Engine, db: &str) {
+    let query = "UPDATE events SET event_status = 'PENDING'
+        WHERE event_status = 'WAITING' AND resume_at IS NOT NULL AND resume_at <= $1"
+        .to_string();
+    let values = vec![JsonValue::String(timestamp(Utc::now()))];
+    if let Err(e) = engine.db.execute(db.to_string(), query, values).await {
+        error!("Error waking waiting events: {}", e);
+    }
+}
+
+/// Events waiting on a delay, a timestamp, an approval or a subflow
+pub async fn list_waiting_events(engine: &Engine, stage: Option<String>) -> Result<Vec<Row>, String> {
+    let db = engine.db_for_stage(stage)?;
+    let query = "SELECT event_id, session_id, node_id, node_label, flow_id, flow_name, worker_type, resume_at, event_result, created_at
+        FROM events WHERE event_status = 'WAITING' ORDER BY created_at ASC"
+        .to_string();
+    engine.db.select(db, query, vec![], None)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Resumes a waiting event now. Approval nodes take the decision, which defaults to approved.
+pub async fn resume_event(
+    engine: &Engine,
+    event_id: String,
+    approved: Option<bool>,
+    note: Option<String>,
+    stage: Option<String>,
+) -> Result<(), String> {
+    let db = engine.db_for_stage(stage)?;
+    let query = "SELECT event_status, worker_type FROM events WHERE event_id = $1".to_string();
+    let rows = engine.db.select(db.clone(), query, vec![JsonValue::String(event_id.clone())], None)
+        .await
+        .map_err(|e| e.to_string())?;
+    let event = rows.first().ok_or_else(|| format!("Event {} not found", event_id))?;
+
+    if text(event, "event_status") != "WAITING" {
+        return Err(format!("Event {} is not waiting", event_id));
+    }
+    let worker_type = text(event, "worker_type");
+    if !RESUMABLE_WORKER_TYPES.contains(&worker_type) {
+        return Err(format!("A {} node can't be resumed by hand", worker_type));
+    }
+
+    let (query, values) = if worker_type == "approval" {
+        let decision = json!({ "approved": approved.unwrap_or(true), "note": note });
+        (
+            "UPDATE events SET event_status = 'PENDING', event_result = $1 WHERE event_id = $2 AND event_status = 'WAITING'",
+            vec![JsonValue::String(decision.to_string()), JsonValue::String(event_id.clone())],
+        )
+    } else {
+        (
+            "UPDATE events SET event_status = 'PENDING', resume_at = $1 WHERE event_id = $2 AND event_status = 'WAITING'",
+            vec![JsonValue::String(timestamp(Utc::now())), JsonValue::String(event_id.clone())],
+        )
+    };
+    engine.db.execute(db, query.to_string(), values)
+        .await
+        .map_err(|e| e.to_string())?;
+    info!("Resumed event {}", event_id);
+    Ok(())
+}
@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as JsonValue};
+
+use crate::engine::Engine;
+
+/// How soon a session's events run. Higher levels go first, flows at the same level take turns.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionPriority {
+    /// Cron and anything else nobody is waiting on
+    #[default]
+    Batch,
+    Webhook,
+    /// Chat and manual runs, where someone is watching
+    Interactive,
+}
+
+impl SessionPriority {
+    pub fn level(self) -> i64 {
+        match self {
+            SessionPriority::Batch => 0,
+            SessionPriority::Webhook => 1,
+            SessionPriority::Interactive => 2,
+        }
+    }
+
+    /// The priority a trigger gives the sessions it starts, by the start node's worker name
+    pub fn for_trigger(worker_name: &str) -> SessionPriority {
+        match worker_name {
+            "app_chat_trigger" | "manual_trigger" => SessionPriority::Interactive,
+            name if name.contains("webhook") => SessionPriority::Webhook,
+            _ => SessionPriority::Batch,
+        }
+    }
+}
+
+/// The priority of a pending event in SQL over `events` joined to its `sessions` row. Start
+/// events run before their session exists, so they go by their parent session or trigger.
+pub fn event_priority_sql() -> String {
+    format!(
+        "COALESCE(sessions.priority,
+            (SELECT parent.priority FROM session_links
+                JOIN sessions AS parent ON parent.session_id = session_links.parent_session_id
+                WHERE session_links.child_session_id = events.session_id),
+            CASE WHEN events.worker_name IN ('app_chat_trigger', 'manual_trigger') THEN {}
+                WHEN events.worker_name LIKE '%webhook%' THEN {}
+                ELSE {} END)",
+        SessionPriority::Interactive.level(),
+        SessionPriority::Webhook.level(),
+        SessionPriority::Batch.level()
+    )
+}
+
+/// When each flow last had an event picked, per database, so flows at the same priority take turns
+#[derive(Default)]
+pub struct FairQueue(Mutex<HashMap<String, HashMap<String, u64>>>);
+
+impl FairQueue {
+    /// Picks from the oldest pending event of each flow: the highest priority first, then
+    /// the flow that has waited longest for a turn.
+    pub fn pick(&self, db: &str, candidates: Vec<HashMap<String, JsonValue>>) -> Option<HashMap<String, JsonValue>> {
+        let priority = |row: &HashMap<String, JsonValue>| row.get("effective_priority").and_then(JsonValue::as_i64).unwrap_or(0);
+        let flow_id = |row: &HashMap<String, JsonValue>| row.get("flow_id").and_then(JsonValue::as_str).unwrap_or("").to_string();
+
+        let top = candidates.iter().map(priority).max()?;
+        let mut served = self.0.lock().unwrap();
+        let turns = served.entry(db.to_string()).or_default();
+
+        // Candidates come oldest first, which breaks ties between flows that never had a turn
+        let chosen = candidates
+            .into_iter()
+            .filter(|row| priority(row) == top)
+            .min_by_key(|row| turns.get(&flow_id(row)).copied().unwrap_or(0))?;
+
+        let next_turn = turns.values().max().copied().unwrap_or(0) + 1;
+        turns.insert(flow_id(&chosen), next_turn);
+        Some(chosen)
+    }
+}
+
+/// Gives a session its priority when it starts. Subflows run at their parent's priority,
+/// other sessions at the flow's setting or else their trigger's.
+pub async fn record_session_priority(
+    engine: &Engine,
+    db: &str,
+    session_id: &str,
+    setting: Option<SessionPriority>,
+    trigger_worker_name: &str,
+) -> Result<(), String> {
+    let query = "UPDATE sessions SET priority = COALESCE(
+            (SELECT parent.priority FROM session_links
+                JOIN sessions AS parent ON parent.session_id = session_links.parent_session_id
+                WHERE session_links.child_session_id = $1),
+            $2)
+        WHERE session_id = $1";
+    let own = setting.unwrap_or_else(|| SessionPriority::for_trigger(trigger_worker_name));
+    let values = vec![JsonValue::String(session_id.to_string()), json!(own.level())];
+    engine.db.execute(db.to_string(), query.to_string(), values)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+pub struct QueueDepth {
+    pub flow_id: String,
+    pub flow_name: String,
+    pub priority: i64,
+    /// Events ready to run, or running
+    pub pending: u64,
+    /// Events waiting on a delay, approval or subflow
+    pub waiting: u64,
+}
+
+/// How many events each flow has queued at each priority, highest priority first
+pub async fn get_queue_depth(engine: &Engine, stage: Option<String>) -> Result<Vec<QueueDepth>, String> {
+    let db = engine.db_for_stage(stage)?;
+    let query = format!(
+        "SELECT events.flow_id, MAX(events.flow_name) AS flow_name, {} AS priority,
+            SUM(CASE WHEN events.event_status IN ('PENDING', 'RUNNING') THEN 1 ELSE 0 END) AS pending,
+            SUM(CASE WHEN events.event_status = 'WAITING' THEN 1 ELSE 0 END) AS waiting
+        FROM events LEFT JOIN sessions ON sessions.session_id = events.session_id
+        WHERE events.event_status IN ('PENDING', 'RUNNING', 'WAITING')
+        GROUP BY events.flow_id, priority
+        ORDER BY priority DESC, pending DESC",
+        event_priority_sql()
+    );
+    let rows = engine.db.select(db, query, vec![], None)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let text = |row: &HashMap<String, JsonValue>, key: &str| row.get(key).and_then(JsonValue::as_str).unwrap_or("").to_string();
+    let number = |row: &HashMap<String, JsonValue>, key: &str| row.get(key).and_then(JsonValue::as_u64).unwrap_or(0);
+    Ok(rows
+        .iter()
+        .map(|row| QueueDepth {
+            flow_id: text(row, "flow_id"),
+            flow_name: text(row, "flow_name"),
+            priority: row.get("priority").and_then(JsonValue::as_i64).unwrap_or(0),
+            pending: number(row, "pending"),
+            waiting: number(row, "waiting"),
+        })
+        .collect())
+}
@@ -0,0 +1,600 @@
+use std::collections::{HashMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as JsonValue};
+use tracing::error;
+
+use crate::engine::Engine;
+use crate::events::context::NodeContext;
+use crate::events::graph::{
+    child_scope, innermost_iteration, parent_scope, repeats_body, truncate_scope, Edge, FlowGraph, LOOP_BODY_HANDLE,
+};
+use crate::events::snapshot::session_snapshot;
+use crate::file_manager::settings::FlowSettings;
+
+// Event statuses that let the nodes after them be decided
+pub const SETTLED_EVENT_STATUSES: &str = "('COMPLETE', 'SKIPPED')";
+
+enum EdgeState {
+    /// The source finished and took this edge
+    Fired,
+    /// The source was skipped or took a different branch
+    Dead,
+    /// The source hasn't finished yet
+    Waiting,
+}
+
+pub type Row = HashMap<String, JsonValue>;
+
+/// Where a map node is with its items, kept in the session context
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MapState {
+    pub items: Vec<JsonValue>,
+    /// How many items may run at once, all of them if not set
+    pub concurrency: Option<usize>,
+    /// Items 0..started have been started
+    pub started: usize,
+    /// Items whose body has finished, in the order they finished
+    pub finished: Vec<usize>,
+}
+
+// A map inside a loop or another map runs once per scope, so its state is keyed by both
+fn map_key(map_id: &str, scope: &str) -> String {
+    if scope.is_empty() {
+        map_id.to_string()
+    } else {
+        format!("{}/{}", scope, map_id)
+    }
+}
+
+fn text<'a>(row: &'a Row, key: &str) -> &'a str {
+    row.get(key).and_then(JsonValue::as_str).unwrap_or("")
+}
+
+/// Results of earlier nodes and loop state, kept on the session row.
+pub async fn session_context(engine: &Engine, db: &str, session_id: &str) -> JsonValue {
+    let query = "SELECT context FROM sessions WHERE session_id = $1".to_string();
+    let values = vec![JsonValue::String(session_id.to_string())];
+    match engine.db.select(db.to_string(), query, values, None).await {
+        Ok(rows) => rows
+            .first()
+            .and_then(|row| row.get("context"))
+            .and_then(JsonValue::as_str)
+            .and_then(|context| serde_json::from_str(context).ok())
+            .unwrap_or_else(|| json!({})),
+        Err(e) => {
+            error!("Error reading context of session {}: {}", session_id, e);
+            json!({})
+        }
+    }
+}
+
+/// Everything a node's fields can reference while it runs in `scope`.
+pub async fn node_context(
+    engine: &Engine,
+    db: &str,
+    settings: &FlowSettings,
+    session_id: &str,
+    scope: &str,
+) -> NodeContext {
+    let context = session_context(engine, db, session_id).await;
+
+    // The innermost loop iteration and map item the node runs in
+    let (mut current_loop, mut item, mut item_index) = (JsonValue::Null, JsonValue::Null, JsonValue::Null);
+    let depth = scope.split('/').filter(|s| !s.is_empty()).count();
+    for level in 1..=depth {
+        let (id, iteration) = match innermost_iteration(&truncate_scope(scope, level)) {
+            Some((id, iteration)) => (id.to_string(), iteration),
+            None => continue,
+        };
+        let key = map_key(&id, &truncate_scope(scope, level - 1));
+        match context.get("maps").and_then(|maps| maps.get(&key)) {
+            Some(state) => {
+                item = state["items"].get(iteration).cloned().unwrap_or_default();
+                item_index = json!(iteration);
+            }
+            None => current_loop = json!({ "id": id, "iteration": iteration }),
+        }
+    }
+
+    let mut nodes = context.get("nodes").cloned().unwrap_or_else(|| json!({}));
+    if depth > 0 {
+        scoped_results(engine, db, session_id, scope, &mut nodes).await;
+    }
+
+    top_level_context(settings, &context)
+        .with("nodes", nodes)
+        .with("loop", current_loop)
+        .with("item", item)
+        .with("item_index", item_index)
+}
+
+/// The context of a node outside of any loop or map, from a session context shaped like
+/// the one kept in `sessions.context`
+pub fn top_level_context(settings: &FlowSettings, context: &JsonValue) -> NodeContext {
+    NodeContext::new(settings)
+        .with("nodes", context.get("nodes").cloned().unwrap_or_else(|| json!({})))
+        .with("loops", context.get("loops").cloned().unwrap_or_else(|| json!({})))
+        .with("loop", JsonValue::Null)
+        .with("trigger", context.get("trigger").cloned().unwrap_or_default())
+        .with("item", JsonValue::Null)
+        .with("item_index", JsonValue::Null)
+}
+
+// Map items run side by side, so the latest result of a node may be another item's. This
+// puts back the results from the scope the node runs in and the scopes around it.
+async fn scoped_results(
+    engine: &Engine,
+    db: &str,
+    session_id: &str,
+    scope: &str,
+    nodes: &mut JsonValue,
+) {
+    let depth = scope.split('/').count();
+    let scopes: Vec<String> = (0..=depth).map(|level| truncate_scope(scope, level)).collect();
+    let placeholders: Vec<String> = (0..scopes.len()).map(|i| format!("${}", i + 2)).collect();
+    let query = format!(
+        "SELECT node_id, scope, event_result FROM events
+        WHERE session_id = $1 AND event_status = 'COMPLETE' AND scope IN ({})
+        ORDER BY length(scope) ASC, created_at ASC, rowid ASC",
+        placeholders.join(", ")
+    );
+    let mut values = vec![JsonValue::String(session_id.to_string())];
+    values.extend(scopes.into_iter().map(JsonValue::String));
+
+    let rows = match engine.db.select(db.to_string(), query, values, None).await {
+        Ok(rows) => rows,
+        Err(e) => {
+            error!("Error reading results in scope {:?}: {}", scope, e);
+            return;
+        }
+    };
+    if let Some(nodes) = nodes.as_object_mut() {
+        for row in rows {
+            let result = text(&row, "event_result");
+            let parsed = serde_json::from_str(result).unwrap_or_else(|_| JsonValue::String(result.to_string()));
+            nodes.insert(text(&row, "node_id").to_string(), json!({ "result": parsed, "scope": text(&row, "scope") }));
+        }
+    }
+}
+
+// `$.section."key"`. SQLite can't escape a quote inside a path label, validation keeps them out of node ids.
+fn context_path(section: &str, key: &str) -> Result<String, String> {
+    if key.contains('"') {
+        return Err(format!("Can't store {:?} in the session context, it has a quote", key));
+    }
+    Ok(format!("$.{}.\"{}\"", section, key))
+}
+
+// Sets one key of the session context. json_set creates the parents if needed.
+async fn set_context(
+    engine: &Engine,
+    db: &str,
+    session_id: &str,
+    path: String,
+    value: &JsonValue,
+) -> Result<(), String> {
+    let query = "UPDATE sessions SET context = json_set(COALESCE(context, '{}'), $1, json($2)) WHERE session_id = $3".to_string();
+    let values = vec![
+        JsonValue::String(path),
+        JsonValue::String(value.to_string()),
+        JsonValue::String(session_id.to_string()),
+    ];
+    engine.db.execute(db.to_string(), query, values)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Makes a node's result available to later nodes as `{{nodes.ID.result}}`. Results
+/// that are JSON can be reached into, e.g. `{{nodes.ID.result.items.0.name}}`.
+pub async fn record_node_result(
+    engine: &Engine,
+    db: &str,
+    session_id: &str,
+    node_id: &str,
+    scope: &str,
+    result: &str,
+) -> Result<(), String> {
+    let parsed = serde_json::from_str(result).unwrap_or_else(|_| JsonValue::String(result.to_string()));
+    let value = json!({ "result": parsed, "scope": scope });
+    set_context(engine, db, session_id, context_path("nodes", node_id)?, &value).await
+}
+
+/// Makes what the session was started with, like a subflow's input, available as `{{trigger}}`
+pub async fn record_trigger(
+    engine: &Engine,
+    db: &str,
+    session_id: &str,
+    input: &str,
+) -> Result<(), String> {
+    let value = serde_json::from_str(input).unwrap_or_else(|_| JsonValue::String(input.to_string()));
+    set_context(engine, db, session_id, "$.trigger".to_string(), &value).await
+}
+
+pub async fn record_loop_state(
+    engine: &Engine,
+    db: &str,
+    session_id: &str,
+    loop_id: &str,
+    scope: &str,
+    iteration: usize,
+) -> Result<(), String> {
+    let value = json!({ "iteration": iteration, "scope": scope });
+    set_context(engine, db, session_id, context_path("loops", loop_id)?, &value).await
+}
+
+pub async fn map_state(
+    engine: &Engine,
+    db: &str,
+    session_id: &str,
+    map_id: &str,
+    scope: &str,
+) -> Result<MapState, String> {
+    let context = session_context(engine, db, session_id).await;
+    let state = context
+        .get("maps")
+        .and_then(|maps| maps.get(map_key(map_id, scope)))
+        .ok_or_else(|| format!("Map {} hasn't split its items in scope {:?}", map_id, scope))?;
+    serde_json::from_value(state.clone()).map_err(|e| e.to_string())
+}
+
+pub async fn record_map_state(
+    engine: &Engine,
+    db: &str,
+    session_id: &str,
+    map_id: &str,
+    scope: &str,
+    state: &MapState,
+) -> Result<(), String> {
+    let value = serde_json::to_value(state).map_err(|e| e.to_string())?;
+    set_context(engine, db, session_id, context_path("maps", &map_key(map_id, scope))?, &value).await
+}
+
+/// The last time a node was queued in `scope`
+pub async fn latest_event(
+    engine: &Engine,
+    db: &str,
+    session_id: &str,
+    node_id: &str,
+    scope: &str,
+) -> Result<Option<Row>, String> {
+    let query = "SELECT event_status, event_result, handles FROM events
+        WHERE session_id = $1 AND node_id = $2 AND scope = $3
+        ORDER BY created_at DESC, rowid DESC LIMIT 1"
+        .to_string();
+    let values = vec![
+        JsonValue::String(session_id.to_string()),
+        JsonValue::String(node_id.to_string()),
+        JsonValue::String(scope.to_string()),
+    ];
+    let rows = engine.db.select(db.to_string(), query, values, None)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(rows.into_iter().next())
+}
+
+/// Number of times the node has been queued in this scope. For a loop node that is
+/// one more than the iteration it is on.
+pub async fn count_events(
+    engine: &Engine,
+    db: &str,
+    session_id: &str,
+    node_id: &str,
+    scope: &str,
+) -> Result<usize, String> {
+    let query = "SELECT COUNT(*) AS count FROM events WHERE session_id = $1 AND node_id = $2 AND scope = $3".to_string();
+    let values = vec![
+        JsonValue::String(session_id.to_string()),
+        JsonValue::String(node_id.to_string()),
+        JsonValue::String(scope.to_string()),
+    ];
+    let rows = engine.db.select(db.to_string(), query, values, None)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(rows
+        .first()
+        .and_then(|row| row.get("count"))
+        .and_then(JsonValue::as_u64)
+        .unwrap_or(0) as usize)
+}
+
+async fn edge_state(
+    engine: &Engine,
+    db: &str,
+    graph: &FlowGraph,
+    session_id: &str,
+    edge: &Edge,
+    target_scope: &str,
+) -> Result<EdgeState, String> {
+    // The source runs in the part of the target's scope for the loops it is inside of
+    let source_scope = truncate_scope(target_scope, graph.enclosing_loops(&edge.source).len());
+    let event = match latest_event(engine, db, session_id, &edge.source, &source_scope).await? {
+        Some(event) => event,
+        None => return Ok(EdgeState::Waiting),
+    };
+
+    Ok(match text(&event, "event_status") {
+        "SKIPPED" => EdgeState::Dead,
+        "COMPLETE" => {
+            let handles: Option<Vec<String>> = event
+                .get("handles")
+                .and_then(JsonValue::as_str)
+                .and_then(|handles| serde_json::from_str(handles).ok());
+            match handles {
+                None => EdgeState::Fired,
+                Some(handles) if edge.source_handle.as_ref().is_some_and(|h| handles.contains(h)) => EdgeState::Fired,
+                // A loop that went round again, or a map that split its items, hasn't decided its other edges yet
+                Some(_) if repeats_body(graph.worker_type(&edge.source)) => EdgeState::Waiting,
+                Some(_) => EdgeState::Dead,
+            }
+        }
+        _ => EdgeState::Waiting,
+    })
+}
+
+/// Whether a node should be queued in `scope`: `Some("PENDING")` once every edge into it
+/// is decided and at least one fired, `Some("SKIPPED")` if none of them did.
+async fn decide(
+    engine: &Engine,
+    db: &str,
+    graph: &FlowGraph,
+    session_id: &str,
+    node_id: &str,
+    scope: &str,
+) -> Result<Option<&'static str>, String> {
+    if count_events(engine, db, session_id, node_id, scope).await? > 0 {
+        return Ok(None);
+    }
+
+    let mut fired = false;
+    for edge in graph.incoming(node_id).iter().filter(|e| !e.back) {
+        match edge_state(engine, db, graph, session_id, edge, scope).await? {
+            EdgeState::Waiting => return Ok(None),
+            EdgeState::Fired => fired = true,
+            EdgeState::Dead => {}
+        }
+    }
+    Ok(Some(if fired { "PENDING" } else { "SKIPPED" }))
+}
+
+// Called when a node at the end of a map body finishes. Once every back edge of the item
+// is decided it starts the next item waiting for a free slot, and after the last item
+// queues the map node again to gather the results.
+async fn item_finished(
+    engine: &Engine,
+    db: &str,
+    graph: &FlowGraph,
+    flow_info: &JsonValue,
+    session_id: &str,
+    map_id: &str,
+    body_scope: &str,
+) -> Result<(), String> {
+    let index = match innermost_iteration(body_scope) {
+        Some((id, index)) if id == map_id => index,
+        _ => return Err(format!("Node finished in scope {:?} outside of map {}", body_scope, map_id)),
+    };
+
+    for edge in graph.incoming(map_id).iter().filter(|e| e.back) {
+        if let EdgeState::Waiting = edge_state(engine, db, graph, session_id, edge, body_scope).await? {
+            return Ok(());
+        }
+    }
+
+    let scope = parent_scope(body_scope);
+    let mut state = map_state(engine, db, session_id, map_id, &scope).await?;
+    if state.finished.contains(&index) {
+        return Ok(());
+    }
+    state.finished.push(index);
+
+    let next = (state.started < state.items.len()).then_some(state.started);
+    if next.is_some() {
+        state.started += 1;
+    }
+    record_map_state(engine, db, session_id, map_id, &scope, &state).await?;
+
+    if let Some(next) = next {
+        start_item(engine, db, graph, flow_info, session_id, map_id, &scope, next).await?;
+    } else if state.finished.len() == state.items.len()
+        && count_events(engine, db, session_id, map_id, &scope).await? == 1
+    {
+        if let Some(node) = graph.node(map_id) {
+            super::create_event(engine, db, node, flow_info, session_id, &scope, "PENDING")
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+// Queues the first nodes of a map body for one item
+#[allow(clippy::too_many_arguments)]
+async fn start_item(
+    engine: &Engine,
+    db: &str,
+    graph: &FlowGraph,
+    flow_info: &JsonValue,
+    session_id: &str,
+    map_id: &str,
+    scope: &str,
+    index: usize,
+) -> Result<(), String> {
+    let item_scope = child_scope(scope, map_id, index);
+    for edge in graph.outgoing(map_id).iter().filter(|e| e.leaves_through(LOOP_BODY_HANDLE)) {
+        if let Some(status) = decide(engine, db, graph, session_id, &edge.target, &item_scope).await? {
+            if let Some(node) = graph.node(&edge.target) {
+                super::create_event(engine, db, node, flow_info, session_id, &item_scope, status)
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Marks the first items of a map that just split as started, as many as its concurrency allows
+async fn start_items(
+    engine: &Engine,
+    db: &str,
+    session_id: &str,
+    map_id: &str,
+    scope: &str,
+) -> Result<Vec<usize>, String> {
+    let mut state = map_state(engine, db, session_id, map_id, scope).await?;
+    let limit = state.concurrency.unwrap_or(state.items.len()).min(state.items.len());
+    let started: Vec<usize> = (state.started..limit).collect();
+    state.started = state.started.max(limit);
+    record_map_state(engine, db, session_id, map_id, scope, &state).await?;
+    Ok(started)
+}
+
+// Called when a node at the end of a loop body finishes. Queues the loop node again once
+// every back edge of this iteration is decided.
+async fn next_iteration(
+    engine: &Engine,
+    db: &str,
+    graph: &FlowGraph,
+    flow_info: &JsonValue,
+    session_id: &str,
+    loop_id: &str,
+    body_scope: &str,
+) -> Result<(), String> {
+    let iteration = match innermost_iteration(body_scope) {
+        Some((id, iteration)) if id == loop_id => iteration,
+        _ => return Err(format!("Node finished in scope {:?} outside of loop {}", body_scope, loop_id)),
+    };
+
+    for edge in graph.incoming(loop_id).iter().filter(|e| e.back) {
+        if let EdgeState::Waiting = edge_state(engine, db, graph, session_id, edge, body_scope).await? {
+            return Ok(());
+        }
+    }
+
+    let scope = parent_scope(body_scope);
+    if count_events(engine, db, session_id, loop_id, &scope).await? != iteration + 1 {
+        return Ok(());
+    }
+    if let Some(node) = graph.node(loop_id) {
+        super::create_event(engine, db, node, flow_info, session_id, &scope, "PENDING")
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Queues whatever can run now that `node_id` has finished in `scope`. `handles` are the
+/// source handles it took, `None` for all of them. Nodes that can no longer run because
+/// every edge into them is dead are recorded as SKIPPED, and so on down the graph.
+pub async fn advance(
+    engine: &Engine,
+    db: &str,
+    session_id: &str,
+    node_id: &str,
+    scope: &str,
+    handles: Option<Vec<String>>,
+) -> Result<(), String> {
+    let _guard = engine.advance.lock().await;
+
+    let snapshot = session_snapshot(engine, db, session_id).await?;
+    let graph = FlowGraph::new(&snapshot.definition);
+    let flow_info = snapshot.definition.get("flow").cloned().unwrap_or_default();
+
+    let queue = VecDeque::from([(node_id.to_string(), scope.to_string(), handles, false)]);
+    follow_edges(engine, db, &graph, &flow_info, session_id, queue).await
+}
+
+/// Queues the top level nodes that can run now, for a session that was started from
+/// the results of another one instead of from its start node.
+pub async fn queue_ready_nodes(
+    engine: &Engine,
+    db: &str,
+    session_id: &str,
+    node_ids: &[String],
+) -> Result<(), String> {
+    let _guard = engine.advance.lock().await;
+
+    let snapshot = session_snapshot(engine, db, session_id).await?;
+    let graph = FlowGraph::new(&snapshot.definition);
+    let flow_info = snapshot.definition.get("flow").cloned().unwrap_or_default();
+
+    let mut queue = VecDeque::new();
+    for node_id in node_ids.iter().filter(|id| graph.enclosing_loops(id).is_empty()) {
+        if let Some(status) = decide(engine, db, &graph, session_id, node_id, "").await? {
+            if let Some(node) = graph.node(node_id) {
+                super::create_event(engine, db, node, &flow_info, session_id, "", status)
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+            if status == "SKIPPED" {
+                queue.push_back((node_id.clone(), String::new(), Some(Vec::new()), true));
+            }
+        }
+    }
+    follow_edges(engine, db, &graph, &flow_info, session_id, queue).await
+}
+
+// Works through finished nodes as (node id, scope, handles taken, skipped), deciding the
+// nodes after them. Skipped nodes are pushed back on the queue so their skip spreads.
+async fn follow_edges(
+    engine: &Engine,
+    db: &str,
+    graph: &FlowGraph,
+    flow_info: &JsonValue,
+    session_id: &str,
+    mut queue: VecDeque<(String, String, Option<Vec<String>>, bool)>,
+) -> Result<(), String> {
+    while let Some((node_id, scope, handles, skipped)) = queue.pop_front() {
+        let worker_type = graph.worker_type(&node_id);
+        let depth = graph.enclosing_loops(&node_id).len();
+        let took_body = !skipped && handles.as_ref().is_some_and(|h| h.iter().any(|h| h == LOOP_BODY_HANDLE));
+
+        // Scopes the body starts in: the next iteration of a loop, or the first items of a map
+        let body_scopes: Vec<String> = match worker_type {
+            "loop" if took_body => {
+                let iteration = count_events(engine, db, session_id, &node_id, &scope).await?.saturating_sub(1);
+                vec![child_scope(&scope, &node_id, iteration)]
+            }
+            "map" if took_body => start_items(engine, db, session_id, &node_id, &scope)
+                .await?
+                .into_iter()
+                .map(|index| child_scope(&scope, &node_id, index))
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        for edge in graph.outgoing(&node_id) {
+            if edge.back {
+                if graph.worker_type(&edge.target) == "map" {
+                    item_finished(engine, db, graph, flow_info, session_id, &edge.target, &scope).await?;
+                } else {
+                    next_iteration(engine, db, graph, flow_info, session_id, &edge.target, &scope).await?;
+                }
+                continue;
+            }
+
+            let target_scopes = if repeats_body(worker_type) && edge.leaves_through(LOOP_BODY_HANDLE) {
+                body_scopes.clone()
+            } else if graph.enclosing_loops(&edge.target).len() == depth {
+                vec![scope.clone()]
+            } else {
+                // Nodes inside a loop or map body are decided when it starts an iteration or item
+                continue;
+            };
+
+            for target_scope in target_scopes {
+                if let Some(status) = decide(engine, db, graph, session_id, &edge.target, &target_scope).await? {
+                    if let Some(node) = graph.node(&edge.target) {
+                        super::create_event(engine, db, node, flow_info, session_id, &target_scope, status)
+                            .await
+                            .map_err(|e| e.to_string())?;
+                    }
+                    if status == "SKIPPED" {
+                        queue.push_back((edge.target.clone(), target_scope, Some(Vec::new()), true));
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,215 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::Utc;
+use serde_json::{json, Value as JsonValue};
+use tracing::info;
+use uuid::Uuid;
+
+use crate::engine::Engine;
+use crate::events::graph::FlowGraph;
+use crate::events::progress::{queue_ready_nodes, SETTLED_EVENT_STATUSES};
+use crate::events::session::STARTING_SESSION_STATUS;
+use crate::events::snapshot::session_snapshot;
+
+type Row = HashMap<String, JsonValue>;
+
+fn text<'a>(row: &'a Row, key: &str) -> &'a str {
+    row.get(key).and_then(JsonValue::as_str).unwrap_or("")
+}
+
+async fn select_rows(engine: &Engine, db: &str, query: &str, values: Vec<JsonValue>) -> Result<Vec<Row>, String> {
+    engine.db.select(db.to_string(), query.to_string(), values, None)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+// The statement linking a replayed session to the original, as a query and its values
+fn replay_record(session_id: &str, original_session_id: &str, from_node_id: Option<&str>) -> (String, Vec<JsonValue>) {
+    let query = "INSERT INTO session_replays (session_id, original_session_id, from_node_id, created_at) VALUES ($1, $2, $3, $4)";
+    let values = vec![
+        JsonValue::String(session_id.to_string()),
+        JsonValue::String(original_session_id.to_string()),
+        from_node_id.map_or(JsonValue::Null, |id| JsonValue::String(id.to_string())),
+        JsonValue::String(Utc::now().to_rfc3339()),
+    ];
+    (query.to_string(), values)
+}
+
+// Starts a new session of the flow with what the original session was triggered with
+async fn replay(engine: &Engine, db: &str, original_session_id: &str, flow_id: &str) -> Result<String, String> {
+    let query = "SELECT data FROM events WHERE session_id = $1 AND worker_type = 'start' ORDER BY created_at ASC LIMIT 1";
+    let rows = select_rows(engine, db, query, vec![JsonValue::String(original_session_id.to_string())]).await?;
+    let input = rows.first().map(|row| text(row, "data").to_string()).unwrap_or_default();
+
+    let session_id = super::trigger_flow(engine, db, flow_id, &input).await?;
+    let (query, values) = replay_record(&session_id, original_session_id, None);
+    engine.db.execute(db.to_string(), query, values)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    info!("Replaying session {} as {}", original_session_id, session_id);
+    Ok(session_id)
+}
+
+async fn original_flow_id(engine: &Engine, db: &str, session_id: &str) -> Result<String, String> {
+    let query = "SELECT flow_id FROM events WHERE session_id = $1 LIMIT 1";
+    let rows = select_rows(engine, db, query, vec![JsonValue::String(session_id.to_string())]).await?;
+    let row = rows.first().ok_or_else(|| format!("Session {} not found", session_id))?;
+    Ok(text(row, "flow_id").to_string())
+}
+
+// The original context without anything recorded by the nodes that run again
+fn context_before(context: &JsonValue, rerun: &HashSet<&str>) -> JsonValue {
+    let mut context = context.clone();
+    for key in ["nodes", "loops"] {
+        if let Some(entries) = context.get_mut(key).and_then(JsonValue::as_object_mut) {
+            entries.retain(|node_id, _| !rerun.contains(node_id.as_str()));
+        }
+    }
+    // Map state is keyed by the map's scope and id, like `2#0/3#4/5`. It goes if the map
+    // or any loop or map it runs in runs again.
+    if let Some(maps) = context.get_mut("maps").and_then(JsonValue::as_object_mut) {
+        maps.retain(|key, _| {
+            !key.split('/')
+                .map(|segment| segment.split_once('#').map_or(segment, |(node_id, _)| node_id))
+                .any(|node_id| rerun.contains(node_id))
+        });
+    }
+    context
+}
+
+/// Runs a session's flow again from the start with the same trigger input, as a new
+/// session. The flow is run as it is now, so fixes made since are picked up.
+pub async fn replay_session(engine: &Engine, session_id: String, stage: Option<String>) -> Result<String, String> {
+    let db = engine.db_for_stage(stage)?;
+    let flow_id = original_flow_id(engine, &db, &session_id).await?;
+    replay(engine, &db, &session_id, &flow_id).await
+}
+
+/// Runs a session again from one of its nodes as a new session. The results of the
+/// nodes that don't come after it are copied over, so only the node and what follows
+/// it run again.
+pub async fn retry_from_node(engine: &Engine, session_id: String, node_id: String, stage: Option<String>) -> Result<String, String> {
+    let db = engine.db_for_stage(stage)?;
+    let flow_id = original_flow_id(engine, &db, &session_id).await?;
+
+    let original = session_snapshot(engine, &db, &session_id).await?;
+    let graph = FlowGraph::new(&original.definition);
+    if graph.node(&node_id).is_none() {
+        return Err(format!("Node {} is not in the flow session {} ran", node_id, session_id));
+    }
+    if graph.start_node() == Some(node_id.as_str()) {
+        return replay(engine, &db, &session_id, &flow_id).await;
+    }
+    if !graph.enclosing_loops(&node_id).is_empty() {
+        return Err(format!("Node {} runs inside a loop or map, retry from the loop or map instead", node_id));
+    }
+
+    // Every node before it has to have finished, or there is nothing to start it from
+    let query = format!(
+        "SELECT DISTINCT node_id FROM events WHERE session_id = $1 AND scope = '' AND event_status IN {}",
+        SETTLED_EVENT_STATUSES
+    );
+    let rows = select_rows(engine, &db, &query, vec![JsonValue::String(session_id.clone())]).await?;
+    let settled: HashSet<&str> = rows.iter().map(|row| text(row, "node_id")).collect();
+    if let Some(edge) = graph
+        .incoming(&node_id)
+        .iter()
+        .find(|e| !e.back && !settled.contains(e.source.as_str()))
+    {
+        return Err(format!("Node {} comes after {}, which didn't finish in session {}", node_id, edge.source, session_id));
+    }
+
+    let rerun = graph.descendants(&node_id);
+
+    let query = "SELECT context FROM sessions WHERE session_id = $1";
+    let rows = select_rows(engine, &db, query, vec![JsonValue::String(session_id.clone())]).await?;
+    let context: JsonValue = rows
+        .first()
+        .and_then(|row| serde_json::from_str(text(row, "context")).ok())
+        .unwrap_or_else(|| json!({}));
+
+    // The new session runs against the original snapshot since the copied results belong to
+    // its graph. It is built in one transaction and held until its first nodes are queued.
+    let new_session_id = Uuid::new_v4().to_string();
+    let session = (
+        "INSERT INTO sessions (session_id, flow_id, snapshot_hash, created_at, context, priority)
+        SELECT $1, flow_id, snapshot_hash, $2, $3, priority FROM sessions WHERE session_id = $4"
+            .to_string(),
+        vec![
+            JsonValue::String(new_session_id.clone()),
+            JsonValue::String(Utc::now().to_rfc3339()),
+            JsonValue::String(context_before(&context, &rerun).to_string()),
+            JsonValue::String(session_id.clone()),
+        ],
+    );
+    // Copy the events of the nodes that don't run again, keeping when they happened
+    let events = (
+        format!(
+            "INSERT INTO events (event_id, session_id, node_id, node_type, node_label, flow_id, flow_name, flow_version, stage, worker_type, worker_name, event_status, session_status, created_at, event_result, event_context, data, scope, handles)
+            SELECT lower(hex(randomblob(16))), $1, node_id, node_type, node_label, flow_id, flow_name, flow_version, stage, worker_type, worker_name, event_status, '{}', created_at, event_result, event_context, data, scope, handles
+            FROM events
+            WHERE session_id = $2 AND event_status IN {}
+            AND node_id NOT IN (SELECT value FROM json_each($3))",
+            STARTING_SESSION_STATUS, SETTLED_EVENT_STATUSES
+        ),
+        vec![
+            JsonValue::String(new_session_id.clone()),
+            JsonValue::String(session_id.clone()),
+            JsonValue::String(json!(rerun.iter().collect::<Vec<_>>()).to_string()),
+        ],
+    );
+    let replay = replay_record(&new_session_id, &session_id, Some(&node_id));
+    engine.db.execute_all(db.clone(), vec![session, events, replay])
+        .await
+        .map_err(|e| e.to_string())?;
+
+    // Besides the node, queue the nodes on other branches that hadn't run yet
+    let ready: Vec<String> = graph
+        .node_ids()
+        .iter()
+        .filter(|id| **id == node_id || (!rerun.contains(id.as_str()) && !settled.contains(id.as_str())))
+        .cloned()
+        .collect();
+    let queued = queue_ready_nodes(engine, &db, &new_session_id, &ready).await;
+    // A session that couldn't be queued is cancelled so none of it runs
+    let status = if queued.is_ok() { "PENDING" } else { "CANCELLED" };
+    let query = format!(
+        "UPDATE events SET session_status = $1 WHERE session_id = $2 AND session_status IN ('{}', 'PENDING')",
+        STARTING_SESSION_STATUS
+    );
+    let values = vec![JsonValue::String(status.to_string()), JsonValue::String(new_session_id.clone())];
+    engine.db.execute(db.clone(), query, values)
+        .await
+        .map_err(|e| e.to_string())?;
+    queued?;
+
+    info!("Retrying session {} from node {} as {}", session_id, node_id, new_session_id);
+    Ok(new_session_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn context_before_drops_what_rerun_nodes_recorded() {
+        let context = json!({
+            "nodes": { "1": { "result": "kept" }, "3": { "result": "dropped" } },
+            "loops": { "2": { "iteration": 4 }, "3": { "iteration": 1 } },
+            "maps": {
+                "5": { "items": [] },
+                "3": { "items": [] },
+                "2#0/5": { "items": [] },
+                "3#1/5": { "items": [] },
+                "2#0/3#4/6": { "items": [] },
+            },
+        });
+        let rerun: HashSet<&str> = ["3", "6"].into_iter().collect();
+        let before = context_before(&context, &rerun);
+
+        assert_eq!(before["nodes"], json!({ "1": { "result": "kept" } }));
+        assert_eq!(before["loops"], json!({ "2": { "iteration": 4 } }));
+        assert_eq!(before["maps"], json!({ "5": { "items": [] }, "2#0/5": { "items": [] } }));
+    }
+}
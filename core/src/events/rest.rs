@@ -1,8 +1,9 @@
-use reqwest::{Error, Method, header::HeaderMap, header::HeaderName};
-use serde::Deserialize;
+use reqwest::{Error, Method, RequestBuilder, header::HeaderMap, header::HeaderName};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use tracing::debug;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct ApiRequest {
     pub url: String,
     pub method: String,
@@ -10,8 +11,14 @@ pub struct ApiRequest {
     pub body: Option<String>,
 }
 
-// #[tauri::command]
-pub async fn call_api(api_request: ApiRequest) -> Result<String, Error> {
+/// A response of any status, for nodes that branch on errors instead of failing
+#[derive(Debug, Serialize)]
+pub struct ApiResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+fn build_request(api_request: ApiRequest) -> RequestBuilder {
     let client = reqwest::Client::new();
     
     // Parse HTTP method
@@ -37,12 +44,17 @@ pub async fn call_api(api_request: ApiRequest) -> Result<String, Error> {
         request_builder = request_builder.body(body);
     }
 
+    request_builder
+}
+
+// #[tauri::command]
+pub async fn call_api(api_request: ApiRequest) -> Result<String, Error> {
     // Execute request
-    let response = request_builder.send().await?;
+    let response = build_request(api_request).send().await?;
 
     if response.status().is_success() {
         let text = response.text().await?;
-        println!("res from rest call processor: {:?}", text);
+        debug!("res from rest call processor: {:?}", text);
         Ok(text)
     } else {
         //TODO: this is probabaly bad error handling
@@ -50,4 +62,10 @@ pub async fn call_api(api_request: ApiRequest) -> Result<String, Error> {
     }
 }
 
-
+// Like call_api but error statuses come back as a response. Only fails if no response arrives.
+pub async fn send_request(api_request: ApiRequest) -> Result<ApiResponse, Error> {
+    let response = build_request(api_request).send().await?;
+    let status = response.status().as_u16();
+    let body = response.text().await?;
+    Ok(ApiResponse { status, body })
+}
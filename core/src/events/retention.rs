@@ -0,0 +1,381 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use chrono::{Duration as ChronoDuration, Utc};
+use flate2::{write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use tokio::time::{sleep, Duration};
+use tracing::{error, info};
+
+use crate::config::{get_app_dir, get_archive_dir};
+use crate::db::stage_from_db;
+use crate::engine::Engine;
+use crate::sql::{Error, Storage};
+
+const POLICY_FILE: &str = "retention.toml";
+
+// Sessions in these states are finished and safe to archive or delete.
+const FINISHED_SESSION_STATUSES: &str = "('COMPLETE', 'FAILED', 'CANCELLED')";
+
+// Keeps the IN (...) lists well under SQLite's bound parameter limit
+const DELETE_CHUNK_SIZE: usize = 500;
+
+/// How much run history to keep. Stored in `retention.toml` in the app dir.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetentionPolicy {
+    /// Keep only the newest N finished sessions of each flow.
+    #[serde(default)]
+    pub keep_sessions_per_flow: Option<i64>,
+    /// Drop finished sessions whose last event is older than this many days.
+    #[serde(default)]
+    pub keep_days: Option<i64>,
+    /// Write sessions to compressed JSONL under the archive dir before deleting them.
+    #[serde(default = "default_true")]
+    pub archive: bool,
+    /// Run VACUUM and ANALYZE after deleting.
+    #[serde(default = "default_true")]
+    pub vacuum: bool,
+    #[serde(default = "default_interval_hours")]
+    pub interval_hours: u64,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_interval_hours() -> u64 {
+    24
+}
+
+// Keeps everything until the user turns pruning on
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        RetentionPolicy {
+            keep_sessions_per_flow: None,
+            keep_days: None,
+            archive: default_true(),
+            vacuum: default_true(),
+            interval_hours: default_interval_hours(),
+        }
+    }
+}
+
+impl RetentionPolicy {
+    pub fn load() -> RetentionPolicy {
+        get_app_dir()
+            .ok()
+            .and_then(|dir| fs::read_to_string(dir.join(POLICY_FILE)).ok())
+            .and_then(|content| toml::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        let content = toml::to_string(self)?;
+        fs::write(get_app_dir()?.join(POLICY_FILE), content)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MaintenanceReport {
+    pub db: String,
+    pub started_at: String,
+    pub finished_at: String,
+    pub sessions_removed: usize,
+    pub events_removed: u64,
+    pub archive_file: Option<String>,
+    pub vacuumed: bool,
+    pub error: Option<String>,
+}
+
+/// Reports from the last maintenance run, one per stage database.
+#[derive(Default)]
+pub struct MaintenanceState(Mutex<Vec<MaintenanceReport>>);
+
+/// Runs maintenance on every loaded database once per `interval_hours`.
+pub async fn maintenance_scheduler(engine: &Engine) {
+    loop {
+        let policy = RetentionPolicy::load();
+        sleep(Duration::from_secs(policy.interval_hours.max(1) * 60 * 60)).await;
+
+        let reports = run_all(engine, &policy).await;
+        info!("Maintenance finished: {:?}", reports);
+    }
+}
+
+async fn run_all(engine: &Engine, policy: &RetentionPolicy) -> Vec<MaintenanceReport> {
+    let dbs = engine.db.loaded().await;
+    let mut reports = Vec::new();
+    for db in dbs {
+        reports.push(run_maintenance_for(engine, &db, policy).await);
+    }
+    *engine.maintenance.0.lock().unwrap() = reports.clone();
+    reports
+}
+
+async fn run_maintenance_for(engine: &Engine, db: &str, policy: &RetentionPolicy) -> MaintenanceReport {
+    let mut report = MaintenanceReport {
+        db: db.to_string(),
+        started_at: Utc::now().to_rfc3339(),
+        finished_at: String::new(),
+        sessions_removed: 0,
+        events_removed: 0,
+        archive_file: None,
+        vacuumed: false,
+        error: None,
+    };
+
+    if let Err(e) = prune(engine, db, policy, &mut report).await {
+        error!("Error running maintenance on {}: {}", db, e);
+        report.error = Some(e);
+    }
+
+    report.finished_at = Utc::now().to_rfc3339();
+    report
+}
+
+async fn prune(
+    engine: &Engine,
+    db: &str,
+    policy: &RetentionPolicy,
+    report: &mut MaintenanceReport,
+) -> Result<(), String> {
+    let session_ids = expired_sessions(&*engine.db, db, policy)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if !session_ids.is_empty() {
+        if policy.archive {
+            let file = archive_sessions(engine, db, &session_ids).await?;
+            report.archive_file = Some(file);
+        }
+
+        for chunk in session_ids.chunks(DELETE_CHUNK_SIZE) {
+            let query = format!(
+                "DELETE FROM events WHERE session_id IN ({})",
+                placeholders(chunk.len())
+            );
+            let values = chunk.iter().map(|id| JsonValue::String(id.clone())).collect();
+            let (deleted, _) = engine.db.execute(db.to_string(), query, values)
+                .await
+                .map_err(|e| e.to_string())?;
+            report.events_removed += deleted;
+
+            let query = format!(
+                "DELETE FROM sessions WHERE session_id IN ({})",
+                placeholders(chunk.len())
+            );
+            let values = chunk.iter().map(|id| JsonValue::String(id.clone())).collect();
+            engine.db.execute(db.to_string(), query, values)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        report.sessions_removed = session_ids.len();
+
+        // Snapshots are shared between sessions so only drop the ones nothing points at anymore
+        let query = "DELETE FROM flow_snapshots WHERE snapshot_hash NOT IN (SELECT snapshot_hash FROM sessions WHERE snapshot_hash IS NOT NULL)";
+        engine.db.execute(db.to_string(), query.to_string(), vec![])
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+
+    if policy.vacuum {
+        for query in ["VACUUM", "ANALYZE"] {
+            engine.db.execute(db.to_string(), query.to_string(), vec![])
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        report.vacuumed = true;
+    }
+
+    Ok(())
+}
+
+// Finished sessions that fall outside the policy, either by count per flow or by age.
+async fn expired_sessions(
+    storage: &dyn Storage,
+    db: &str,
+    policy: &RetentionPolicy,
+) -> Result<Vec<String>, Error> {
+    let query = format!(
+        "SELECT session_id FROM (
+            SELECT session_id,
+                MAX(created_at) AS last_event_at,
+                ROW_NUMBER() OVER (PARTITION BY flow_id ORDER BY MAX(created_at) DESC) AS session_rank
+            FROM events
+            GROUP BY session_id, flow_id
+            HAVING SUM(session_status NOT IN {}) = 0
+        )
+        WHERE ($1 IS NOT NULL AND session_rank > $1)
+           OR ($2 IS NOT NULL AND last_event_at < $2)",
+        FINISHED_SESSION_STATUSES
+    );
+
+    let cutoff = policy
+        .keep_days
+        .map(|days| (Utc::now() - ChronoDuration::days(days)).to_rfc3339());
+
+    let values = vec![
+        policy
+            .keep_sessions_per_flow
+            .map(JsonValue::from)
+            .unwrap_or(JsonValue::Null),
+        cutoff.map(JsonValue::String).unwrap_or(JsonValue::Null),
+    ];
+
+    let rows = storage.select(db.to_string(), query, values, None).await?;
+
+    Ok(rows
+        .iter()
+        .filter_map(|row| row.get("session_id").and_then(JsonValue::as_str))
+        .map(|id| id.to_string())
+        .collect())
+}
+
+// Writes every event of the given sessions to a gzipped JSONL file and returns its path.
+async fn archive_sessions(
+    engine: &Engine,
+    db: &str,
+    session_ids: &[String],
+) -> Result<String, String> {
+    let dir = get_archive_dir()
+        .map_err(|e| e.to_string())?
+        .join(stage_from_db(db));
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    let (path, file) = create_archive_file(&dir).map_err(|e| e.to_string())?;
+    let mut encoder = GzEncoder::new(file, Compression::default());
+
+    for chunk in session_ids.chunks(DELETE_CHUNK_SIZE) {
+        let query = format!(
+            "SELECT * FROM events WHERE session_id IN ({}) ORDER BY session_id, created_at",
+            placeholders(chunk.len())
+        );
+        let values = chunk.iter().map(|id| JsonValue::String(id.clone())).collect();
+        let rows: Vec<HashMap<String, JsonValue>> =
+            engine.db.select(db.to_string(), query, values, None)
+                .await
+                .map_err(|e| e.to_string())?;
+
+        for row in rows {
+            let line = serde_json::to_string(&row).map_err(|e| e.to_string())?;
+            writeln!(encoder, "{}", line).map_err(|e| e.to_string())?;
+        }
+    }
+
+    encoder.finish().map_err(|e| e.to_string())?;
+
+    info!("Archived {} sessions to {:?}", session_ids.len(), path);
+    Ok(path.to_string_lossy().to_string())
+}
+
+// A new file, never one an earlier run in the same millisecond already wrote
+fn create_archive_file(dir: &Path) -> std::io::Result<(PathBuf, fs::File)> {
+    let timestamp = Utc::now().format("%Y%m%dT%H%M%S%3f").to_string();
+    let mut n = 0;
+    loop {
+        let name = match n {
+            0 => format!("events-{}.jsonl.gz", timestamp),
+            n => format!("events-{}-{}.jsonl.gz", timestamp, n),
+        };
+        let path = dir.join(name);
+        match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => n += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn placeholders(count: usize) -> String {
+    (1..=count)
+        .map(|i| format!("${}", i))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+pub fn get_retention_policy() -> RetentionPolicy {
+    RetentionPolicy::load()
+}
+
+pub fn set_retention_policy(policy: RetentionPolicy) -> Result<RetentionPolicy, String> {
+    policy.save().map_err(|e| e.to_string())?;
+    Ok(policy)
+}
+
+/// Runs retention, archival and vacuum right now instead of waiting for the next interval.
+pub async fn run_maintenance(engine: &Engine) -> Result<Vec<MaintenanceReport>, String> {
+    let policy = RetentionPolicy::load();
+    Ok(run_all(engine, &policy).await)
+}
+
+pub fn get_maintenance_report(engine: &Engine) -> Vec<MaintenanceReport> {
+    engine.maintenance.0.lock().unwrap().clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::migrations;
+    use crate::sql::sqlite::DbInstances;
+
+    async fn storage_with_sessions(statuses: &[(&str, &str)]) -> (DbInstances, String) {
+        let dir = std::env::temp_dir().join(format!("anything-retention-{}", uuid::Uuid::new_v4()));
+        let storage = DbInstances::new(dir, migrations());
+        let db = "sqlite:test.sqlite".to_string();
+        storage.connect(&db).await.unwrap();
+        for (session_id, status) in statuses {
+            storage
+                .execute(
+                    db.clone(),
+                    "INSERT INTO events (event_id, session_id, flow_id, session_status, created_at)
+                        VALUES ($1, $2, 'flow', $3, '2020-01-01T00:00:00+00:00')"
+                        .to_string(),
+                    vec![
+                        JsonValue::String(uuid::Uuid::new_v4().to_string()),
+                        JsonValue::String(session_id.to_string()),
+                        JsonValue::String(status.to_string()),
+                    ],
+                )
+                .await
+                .unwrap();
+        }
+        (storage, db)
+    }
+
+    #[tokio::test]
+    async fn default_policy_keeps_everything() {
+        let (storage, db) = storage_with_sessions(&[("done", "COMPLETE"), ("failed", "FAILED")]).await;
+        let expired = expired_sessions(&storage, &db, &RetentionPolicy::default()).await.unwrap();
+        assert!(expired.is_empty());
+    }
+
+    #[tokio::test]
+    async fn cancelled_sessions_expire_but_running_ones_dont() {
+        let (storage, db) = storage_with_sessions(&[
+            ("done", "COMPLETE"),
+            ("cancelled", "CANCELLED"),
+            ("running", "RUNNING"),
+        ])
+        .await;
+        let policy = RetentionPolicy {
+            keep_days: Some(1),
+            ..RetentionPolicy::default()
+        };
+        let mut expired = expired_sessions(&storage, &db, &policy).await.unwrap();
+        expired.sort();
+        assert_eq!(expired, vec!["cancelled".to_string(), "done".to_string()]);
+    }
+
+    #[test]
+    fn archive_files_are_never_overwritten() {
+        let dir = std::env::temp_dir().join(format!("anything-archive-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let (first, _) = create_archive_file(&dir).unwrap();
+        let (second, _) = create_archive_file(&dir).unwrap();
+        assert_ne!(first, second);
+    }
+}
@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use serde_json::Value as JsonValue;
+use tokio::sync::Notify;
+use tracing::info;
+
+use crate::engine::Engine;
+use crate::events::subflow::wake_parent;
+use crate::notifications::Event;
+
+/// Session status of a session that is still being set up, e.g. a retry whose results are being copied
+pub const STARTING_SESSION_STATUS: &str = "STARTING";
+/// Session statuses that stop the scheduler from picking up the session's events
+pub const HELD_SESSION_STATUSES: &str = "('PAUSED', 'CANCELLED', 'STARTING')";
+
+struct RunningWorker {
+    session_id: String,
+    worker_type: String,
+    interrupt: Arc<Notify>,
+}
+
+/// Workers running right now, so cancelling a session can stop them halfway.
+#[derive(Default)]
+pub struct RunningWorkers(Mutex<HashMap<String, RunningWorker>>);
+
+impl RunningWorkers {
+    /// Call before running an event's worker. The returned notify fires if its session is cancelled.
+    pub fn register(&self, event_id: &str, session_id: &str, worker_type: &str) -> Arc<Notify> {
+        let interrupt = Arc::new(Notify::new());
+        self.0.lock().unwrap().insert(
+            event_id.to_string(),
+            RunningWorker {
+                session_id: session_id.to_string(),
+                worker_type: worker_type.to_string(),
+                interrupt: interrupt.clone(),
+            },
+        );
+        interrupt
+    }
+
+    pub fn unregister(&self, event_id: &str) {
+        self.0.lock().unwrap().remove(event_id);
+    }
+
+    /// How many events of each worker type are running
+    pub fn count_by_worker_type(&self) -> HashMap<String, usize> {
+        let mut counts = HashMap::new();
+        for worker in self.0.lock().unwrap().values() {
+            *counts.entry(worker.worker_type.clone()).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    fn interrupt(&self, session_id: &str) {
+        let workers = self.0.lock().unwrap();
+        for worker in workers.values().filter(|worker| worker.session_id == session_id) {
+            // notify_one keeps the permit if the worker isn't waiting on it yet
+            worker.interrupt.notify_one();
+        }
+    }
+}
+
+// The session and every subflow session it started, parents first
+async fn session_tree(engine: &Engine, db: &str, session_id: &str) -> Result<Vec<String>, String> {
+    let mut sessions = vec![session_id.to_string()];
+    let mut next = 0;
+    while next < sessions.len() {
+        let query = "SELECT child_session_id FROM session_links WHERE parent_session_id = $1".to_string();
+        let values = vec![JsonValue::String(sessions[next].clone())];
+        let rows = engine.db.select(db.to_string(), query, values, None)
+            .await
+            .map_err(|e| e.to_string())?;
+        sessions.extend(
+            rows.iter()
+                .filter_map(|row| row.get("child_session_id").and_then(JsonValue::as_str))
+                .map(|id| id.to_string()),
+        );
+        next += 1;
+    }
+    Ok(sessions)
+}
+
+async fn session_flow_id(engine: &Engine, db: &str, session_id: &str) -> Result<String, String> {
+    let query = "SELECT flow_id FROM events WHERE session_id = $1 LIMIT 1".to_string();
+    let rows = engine.db.select(db.to_string(), query, vec![JsonValue::String(session_id.to_string())], None)
+        .await
+        .map_err(|e| e.to_string())?;
+    let row = rows.first().ok_or_else(|| format!("Session {} not found", session_id))?;
+    Ok(row.get("flow_id").and_then(JsonValue::as_str).unwrap_or_default().to_string())
+}
+
+/// The session's status: STARTING while it's set up, PENDING while it runs, then PAUSED, COMPLETE, FAILED or CANCELLED
+pub async fn session_status(engine: &Engine, db: &str, session_id: &str) -> Result<String, String> {
+    let query = "SELECT session_status FROM events WHERE session_id = $1 ORDER BY created_at DESC LIMIT 1".to_string();
+    let rows = engine.db.select(db.to_string(), query, vec![JsonValue::String(session_id.to_string())], None)
+        .await
+        .map_err(|e| e.to_string())?;
+    let row = rows.first().ok_or_else(|| format!("Session {} not found", session_id))?;
+    Ok(row.get("session_status").and_then(JsonValue::as_str).unwrap_or_default().to_string())
+}
+
+// Sets the session status of sessions that are still running or paused. Returns how many events changed.
+async fn set_session_status(engine: &Engine, db: &str, session_id: &str, status: &str) -> Result<u64, String> {
+    let query = "UPDATE events SET session_status = $1
+        WHERE session_id = $2 AND session_status IN ('PENDING', 'PAUSED')"
+        .to_string();
+    let values = vec![JsonValue::String(status.to_string()), JsonValue::String(session_id.to_string())];
+    let (changed, _) = engine.db.execute(db.to_string(), query, values)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(changed)
+}
+
+/// Stops a session and the subflows it started. Events that haven't finished are marked
+/// CANCELLED and running workers are interrupted: terminal commands are killed and HTTP
+/// requests dropped.
+pub async fn cancel_session_tree(engine: &Engine, db: &str, session_id: &str) -> Result<(), String> {
+    let flow_id = session_flow_id(engine, db, session_id).await?;
+
+    for session in session_tree(engine, db, session_id).await? {
+        if set_session_status(engine, db, &session, "CANCELLED").await? == 0 {
+            continue;
+        }
+        let query = "UPDATE events SET event_status = 'CANCELLED'
+            WHERE session_id = $1 AND event_status NOT IN ('COMPLETE', 'SKIPPED', 'FAILED')"
+            .to_string();
+        engine.db.execute(db.to_string(), query, vec![JsonValue::String(session.clone())])
+            .await
+            .map_err(|e| e.to_string())?;
+
+        engine.running.interrupt(&session);
+        // A subflow cancelled on its own fails the node waiting on it
+        wake_parent(engine, db, &session).await;
+        info!("Cancelled session {}", session);
+    }
+
+    engine.notify(Event::SessionCancelled {
+        message: format!("Session {} was cancelled", session_id),
+        flow_id,
+        session_id: session_id.to_string(),
+    });
+    Ok(())
+}
+
+pub async fn cancel_session(engine: &Engine, session_id: String, stage: Option<String>) -> Result<(), String> {
+    let db = engine.db_for_stage(stage)?;
+    cancel_session_tree(engine, &db, &session_id).await
+}
+
+/// Stops the scheduler from starting more of the session's events. Workers already
+/// running finish, and what they queue waits for the session to be resumed.
+pub async fn pause_session(engine: &Engine, session_id: String, stage: Option<String>) -> Result<(), String> {
+    let db = engine.db_for_stage(stage)?;
+    let flow_id = session_flow_id(engine, &db, &session_id).await?;
+
+    let mut changed = 0;
+    for session in session_tree(engine, &db, &session_id).await? {
+        changed += set_session_status(engine, &db, &session, "PAUSED").await?;
+    }
+    if changed == 0 {
+        return Err(format!("Session {} isn't running", session_id));
+    }
+
+    engine.notify(Event::SessionPaused {
+        message: format!("Session {} was paused", session_id),
+        flow_id,
+        session_id,
+    });
+    Ok(())
+}
+
+pub async fn resume_session(engine: &Engine, session_id: String, stage: Option<String>) -> Result<(), String> {
+    let db = engine.db_for_stage(stage)?;
+    let flow_id = session_flow_id(engine, &db, &session_id).await?;
+
+    let mut changed = 0;
+    for session in session_tree(engine, &db, &session_id).await? {
+        let query = "UPDATE events SET session_status = 'PENDING' WHERE session_id = $1 AND session_status = 'PAUSED'".to_string();
+        let (rows, _) = engine.db.execute(db.clone(), query, vec![JsonValue::String(session)])
+            .await
+            .map_err(|e| e.to_string())?;
+        changed += rows;
+    }
+    if changed == 0 {
+        return Err(format!("Session {} isn't paused", session_id));
+    }
+
+    engine.notify(Event::SessionResumed {
+        message: format!("Session {} was resumed", session_id),
+        flow_id,
+        session_id,
+    });
+    Ok(())
+}
@@ -0,0 +1,158 @@
+use std::fs;
+
+use chrono::Utc;
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use sha2::{Digest, Sha256};
+
+use crate::engine::Engine;
+
+/// The flow definition and settings a session started with. Stored once per
+/// content hash in `flow_snapshots` and linked from `sessions`.
+#[derive(Debug, Clone, Serialize)]
+pub struct FlowSnapshot {
+    pub snapshot_hash: String,
+    pub flow_id: String,
+    pub flow_version: String,
+    pub definition: JsonValue,
+    pub settings: JsonValue,
+}
+
+// Looks the flow up by id so renaming its folder doesn't break queued events
+async fn read_flow_files(
+    engine: &Engine,
+    flow_id: &str,
+) -> Result<(String, String), String> {
+    let flow_id = flow_id.to_string();
+    engine.flows.read_blocking(move |index| {
+        let dir = index
+            .get(&flow_id)
+            .ok_or_else(|| format!("Flow {} not found in the flows directory", flow_id))?;
+
+        let flow_toml = fs::read_to_string(dir.join("flow.toml"))
+            .map_err(|e| format!("Could not read flow.toml for flow {} at {:?}: {}", flow_id, dir, e))?;
+        // settings.toml is optional, a flow without one just has no settings
+        let settings_toml = fs::read_to_string(dir.join("settings.toml")).unwrap_or_default();
+
+        Ok((flow_toml, settings_toml))
+    }).await?
+}
+
+fn parse_toml(content: &str, file: &str, flow_id: &str) -> Result<JsonValue, String> {
+    toml::from_str(content).map_err(|e| format!("Failed to parse {} for flow {}: {}", file, flow_id, e))
+}
+
+/// Reads the flow definition and settings from disk without storing a snapshot
+pub async fn read_flow_definition(
+    engine: &Engine,
+    flow_id: &str,
+) -> Result<(JsonValue, JsonValue), String> {
+    let (flow_toml, settings_toml) = read_flow_files(engine, flow_id).await?;
+    let definition = parse_toml(&flow_toml, "flow.toml", flow_id)?;
+    let settings = parse_toml(&settings_toml, "settings.toml", flow_id)?;
+    Ok((definition, settings))
+}
+
+/// Reads the flow from disk and stores it in `flow_snapshots` if this exact
+/// content hasn't been seen before.
+pub async fn take_snapshot(
+    engine: &Engine,
+    db: &str,
+    flow_id: &str,
+) -> Result<FlowSnapshot, String> {
+    let (flow_toml, settings_toml) = read_flow_files(engine, flow_id).await?;
+
+    let mut hasher = Sha256::new();
+    hasher.update(flow_toml.as_bytes());
+    hasher.update([0u8]);
+    hasher.update(settings_toml.as_bytes());
+    let snapshot_hash = hex::encode(hasher.finalize());
+
+    let definition = parse_toml(&flow_toml, "flow.toml", flow_id)?;
+    let settings = parse_toml(&settings_toml, "settings.toml", flow_id)?;
+    let flow_version = definition
+        .get("flow")
+        .and_then(|flow| flow.get("version"))
+        .and_then(JsonValue::as_str)
+        .unwrap_or_default()
+        .to_string();
+
+    let query = "INSERT OR IGNORE INTO flow_snapshots (snapshot_hash, flow_id, flow_version, flow_definition, flow_settings, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6)";
+    let values = vec![
+        JsonValue::String(snapshot_hash.clone()),
+        JsonValue::String(flow_id.to_string()),
+        JsonValue::String(flow_version.clone()),
+        JsonValue::String(definition.to_string()),
+        JsonValue::String(settings.to_string()),
+        JsonValue::String(Utc::now().to_rfc3339()),
+    ];
+    engine.db.execute(db.to_string(), query.to_string(), values)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(FlowSnapshot {
+        snapshot_hash,
+        flow_id: flow_id.to_string(),
+        flow_version,
+        definition,
+        settings,
+    })
+}
+
+/// Records which snapshot a session runs against.
+pub async fn start_session(
+    engine: &Engine,
+    db: &str,
+    session_id: &str,
+    snapshot: &FlowSnapshot,
+) -> Result<(), String> {
+    let query = "INSERT INTO sessions (session_id, flow_id, snapshot_hash, created_at) VALUES ($1, $2, $3, $4)";
+    let values = vec![
+        JsonValue::String(session_id.to_string()),
+        JsonValue::String(snapshot.flow_id.clone()),
+        JsonValue::String(snapshot.snapshot_hash.clone()),
+        JsonValue::String(Utc::now().to_rfc3339()),
+    ];
+    engine.db.execute(db.to_string(), query.to_string(), values)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// The snapshot a session started with.
+pub async fn session_snapshot(
+    engine: &Engine,
+    db: &str,
+    session_id: &str,
+) -> Result<FlowSnapshot, String> {
+    let query = "SELECT flow_snapshots.* FROM sessions
+        JOIN flow_snapshots ON flow_snapshots.snapshot_hash = sessions.snapshot_hash
+        WHERE sessions.session_id = $1";
+    let values = vec![JsonValue::String(session_id.to_string())];
+    let rows = engine.db.select(db.to_string(), query.to_string(), values, Some(true))
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let row = rows
+        .first()
+        .ok_or_else(|| format!("No flow snapshot found for session {}", session_id))?;
+    let text = |key: &str| row.get(key).and_then(JsonValue::as_str).unwrap_or_default().to_string();
+
+    Ok(FlowSnapshot {
+        snapshot_hash: text("snapshot_hash"),
+        flow_id: text("flow_id"),
+        flow_version: text("flow_version"),
+        definition: row.get("flow_definition").cloned().unwrap_or_default(),
+        settings: row.get("flow_settings").cloned().unwrap_or_default(),
+    })
+}
+
+pub async fn get_session_snapshot(
+    engine: &Engine,
+    session_id: String,
+    stage: Option<String>,
+) -> Result<FlowSnapshot, String> {
+    let db = engine.db_for_stage(stage)?;
+    session_snapshot(engine, &db, &session_id).await
+}
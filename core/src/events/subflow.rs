@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+
+use chrono::Utc;
+use serde_json::{json, Value as JsonValue};
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::engine::Engine;
+use crate::events::graph::FlowGraph;
+use crate::events::session::session_status;
+use crate::file_manager::flows::read_flow;
+
+/// How deep subflows may call subflows, so a flow calling itself can't run away
+pub const MAX_SUBFLOW_DEPTH: u64 = 5;
+
+type Row = HashMap<String, JsonValue>;
+
+fn text<'a>(row: &'a Row, key: &str) -> &'a str {
+    row.get(key).and_then(JsonValue::as_str).unwrap_or("")
+}
+
+async fn select_one(
+    engine: &Engine,
+    db: &str,
+    query: &str,
+    values: Vec<JsonValue>,
+) -> Result<Option<Row>, String> {
+    let rows = engine.db.select(db.to_string(), query.to_string(), values, None)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(rows.into_iter().next())
+}
+
+/// How many subflows deep a session runs, 0 for one started by a trigger
+pub async fn session_depth(engine: &Engine, db: &str, session_id: &str) -> Result<u64, String> {
+    let query = "SELECT depth FROM session_links WHERE child_session_id = $1";
+    let link = select_one(engine, db, query, vec![JsonValue::String(session_id.to_string())]).await?;
+    Ok(link.and_then(|row| row.get("depth").and_then(JsonValue::as_u64)).unwrap_or(0))
+}
+
+/// The start node and `[flow]` table of the flow as it is on disk right now. The session
+/// it starts takes its own snapshot when its start event runs.
+pub async fn flow_start(engine: &Engine, flow_id: &str) -> Result<(JsonValue, JsonValue), String> {
+    let id = flow_id.to_string();
+    let definition = engine.flows.read_blocking(move |index| {
+        let dir = index
+            .get(&id)
+            .ok_or_else(|| format!("Flow {} not found in the flows directory", id))?;
+        serde_json::to_value(read_flow(&dir)?).map_err(|e| e.to_string())
+    }).await??;
+    let graph = FlowGraph::new(&definition);
+    let start = graph
+        .start_node()
+        .and_then(|id| graph.node(id))
+        .cloned()
+        .ok_or_else(|| format!("Flow {} has no start node", flow_id))?;
+    Ok((start, definition.get("flow").cloned().unwrap_or_default()))
+}
+
+/// Runs twice. The first time it starts the child session and the event waits; when the
+/// child finishes the event is queued again and returns the child's final output.
+pub async fn run_subflow(
+    engine: &Engine,
+    db: &str,
+    event_data: &HashMap<String, JsonValue>,
+    data: &JsonValue,
+) -> Result<String, String> {
+    let event_id = text(event_data, "event_id");
+
+    let query = "SELECT child_session_id FROM session_links WHERE parent_event_id = $1";
+    if let Some(link) = select_one(engine, db, query, vec![JsonValue::String(event_id.to_string())]).await? {
+        return child_output(engine, db, text(&link, "child_session_id")).await;
+    }
+
+    let flow_id = data
+        .get("flow_id")
+        .and_then(JsonValue::as_str)
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .ok_or("Subflow node needs a flow_id")?;
+    let session_id = text(event_data, "session_id");
+    let depth = session_depth(engine, db, session_id).await? + 1;
+    if depth > MAX_SUBFLOW_DEPTH {
+        return Err(format!("Subflows are nested more than {} deep", MAX_SUBFLOW_DEPTH));
+    }
+
+    let input = match data.get("input") {
+        None | Some(JsonValue::Null) => String::new(),
+        Some(JsonValue::String(input)) => input.clone(),
+        Some(other) => other.to_string(),
+    };
+    let (start, flow_info) = flow_start(engine, flow_id).await?;
+    let child_session_id = Uuid::new_v4().to_string();
+
+    let query = "INSERT INTO session_links (child_session_id, parent_session_id, parent_event_id, parent_node_id, depth, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6)";
+    let values = vec![
+        JsonValue::String(child_session_id.clone()),
+        JsonValue::String(session_id.to_string()),
+        JsonValue::String(event_id.to_string()),
+        JsonValue::String(text(event_data, "node_id").to_string()),
+        json!(depth),
+        JsonValue::String(Utc::now().to_rfc3339()),
+    ];
+    engine.db.execute(db.to_string(), query.to_string(), values)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if let Err(e) = super::insert_event(engine, db, &start, &flow_info, &child_session_id, "", "PENDING", &input).await {
+        // A link to a child that never starts would leave the node waiting on it for good
+        let query = "DELETE FROM session_links WHERE child_session_id = $1".to_string();
+        if let Err(e) = engine.db.execute(db.to_string(), query, vec![JsonValue::String(child_session_id.clone())]).await {
+            error!("Error removing the link to subflow session {}: {}", child_session_id, e);
+        }
+        return Err(e.to_string());
+    }
+    info!("Started subflow {} as session {} at depth {}", flow_id, child_session_id, depth);
+
+    Ok(json!({ "waiting": true, "flow_id": flow_id, "child_session_id": child_session_id }).to_string())
+}
+
+// The result of the last node the child session completed
+async fn child_output(engine: &Engine, db: &str, child_session_id: &str) -> Result<String, String> {
+    match session_status(engine, db, child_session_id).await?.as_str() {
+        "COMPLETE" => {}
+        "FAILED" => return Err(format!("Subflow session {} failed", child_session_id)),
+        "CANCELLED" => return Err(format!("Subflow session {} was cancelled", child_session_id)),
+        other => return Err(format!("Subflow session {} is {} and hasn't finished", child_session_id, other)),
+    }
+
+    let query = "SELECT event_result FROM events
+        WHERE session_id = $1 AND event_status = 'COMPLETE'
+        ORDER BY created_at DESC, rowid DESC LIMIT 1";
+    let last = select_one(engine, db, query, vec![JsonValue::String(child_session_id.to_string())]).await?;
+    Ok(last.map(|row| text(&row, "event_result").to_string()).unwrap_or_default())
+}
+
+/// Queues the subflow event waiting on this session again once the session has finished
+pub async fn wake_parent(engine: &Engine, db: &str, child_session_id: &str) {
+    let query = "UPDATE events SET event_status = 'PENDING'
+        WHERE event_status = 'WAITING'
+        AND event_id = (SELECT parent_event_id FROM session_links WHERE child_session_id = $1)"
+        .to_string();
+    let values = vec![JsonValue::String(child_session_id.to_string())];
+    if let Err(e) = engine.db.execute(db.to_string(), query, values).await {
+        error!("Error waking the parent of session {}: {}", child_session_id, e);
+    }
+}
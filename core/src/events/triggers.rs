@@ -0,0 +1,241 @@
+use chrono::{DateTime, Datelike, Local, Timelike, Utc};
+use serde_json::{json, Value as JsonValue};
+use tokio::time::{sleep, Duration};
+use tracing::{error, info, warn};
+
+use crate::engine::Engine;
+use crate::events::graph::FlowGraph;
+use crate::events::pause::timestamp;
+use crate::file_manager::flows::read_flow;
+use crate::file_manager::settings::FlowSettings;
+
+/// Worker name of the start node that runs its flow on a schedule
+pub const CRON_TRIGGER: &str = "cron";
+
+// Lowest and highest value of each cron field
+const FIELD_BOUNDS: [(u32, u32); 5] = [(0, 59), (0, 23), (1, 31), (1, 12), (0, 7)];
+
+/// A cron schedule: minute, hour, day of month, month and day of week, in local time.
+/// Fields take `*`, numbers, ranges like `1-5`, lists like `1,15` and steps like `*/10`.
+/// Sunday is 0 or 7.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronPattern {
+    fields: Vec<Vec<u32>>,
+    // Restricted day fields match if either does, like in crontab
+    any_day_of_month: bool,
+    any_day_of_week: bool,
+}
+
+impl CronPattern {
+    pub fn parse(pattern: &str) -> Result<CronPattern, String> {
+        let parts: Vec<&str> = pattern.split_whitespace().collect();
+        if parts.len() != 5 {
+            return Err(format!(
+                "Invalid cron pattern {:?}, expected minute, hour, day of month, month and day of week",
+                pattern
+            ));
+        }
+        let mut fields = Vec::new();
+        for (part, (low, high)) in parts.iter().zip(FIELD_BOUNDS) {
+            let mut values = parse_field(part, low, high).map_err(|e| format!("Invalid cron pattern {:?}: {}", pattern, e))?;
+            if high == 7 {
+                // Sunday
+                values = values.into_iter().map(|day| day % 7).collect();
+            }
+            fields.push(values);
+        }
+        Ok(CronPattern {
+            fields,
+            any_day_of_month: parts[2] == "*",
+            any_day_of_week: parts[4] == "*",
+        })
+    }
+
+    pub fn matches(&self, time: DateTime<Local>) -> bool {
+        let has = |field: usize, value: u32| self.fields[field].contains(&value);
+        let day_of_month = has(2, time.day());
+        let day_of_week = has(4, time.weekday().num_days_from_sunday());
+        let day = match (self.any_day_of_month, self.any_day_of_week) {
+            (false, false) => day_of_month || day_of_week,
+            _ => day_of_month && day_of_week,
+        };
+        has(0, time.minute()) && has(1, time.hour()) && has(3, time.month()) && day
+    }
+}
+
+fn parse_field(field: &str, low: u32, high: u32) -> Result<Vec<u32>, String> {
+    let number = |s: &str| -> Result<u32, String> {
+        let n: u32 = s.parse().map_err(|_| format!("{:?} is not a number", s))?;
+        if n < low || n > high {
+            return Err(format!("{} is outside {}-{}", n, low, high));
+        }
+        Ok(n)
+    };
+
+    let mut values = Vec::new();
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|s| *s > 0).ok_or(format!("Invalid step in {:?}", item))?),
+            None => (item, 1),
+        };
+        let (start, end) = match range {
+            "*" => (low, high),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (number(start)?, number(end)?),
+                // `5/15` runs from 5 to the end of the range
+                None if step > 1 => (number(range)?, high),
+                None => (number(range)?, number(range)?),
+            },
+        };
+        if start > end {
+            return Err(format!("Invalid range {:?}", range));
+        }
+        values.extend((start..=end).step_by(step as usize));
+    }
+    values.sort_unstable();
+    values.dedup();
+    Ok(values)
+}
+
+// The schedule of a flow whose start node is a cron trigger. Flows without one, or with
+// an empty pattern, have none.
+fn flow_schedule(definition: &JsonValue) -> Result<Option<CronPattern>, String> {
+    let graph = FlowGraph::new(definition);
+    let data = match graph.start_node() {
+        Some(start) => graph.data(start),
+        None => return Ok(None),
+    };
+    if data.get("worker_name").and_then(JsonValue::as_str) != Some(CRON_TRIGGER) {
+        return Ok(None);
+    }
+    match data.get("pattern").and_then(JsonValue::as_str).map(str::trim) {
+        None | Some("") => Ok(None),
+        Some(pattern) => CronPattern::parse(pattern).map(Some),
+    }
+}
+
+/// Validation for a cron trigger's pattern
+pub fn check_schedule(data: &JsonValue) -> Result<(), String> {
+    match data.get("pattern").and_then(JsonValue::as_str).map(str::trim) {
+        None | Some("") => Ok(()),
+        Some(pattern) => CronPattern::parse(pattern).map(|_| ()),
+    }
+}
+
+// Whether another scheduler, like the app's while the CLI daemon runs, already started this run
+async fn already_started(engine: &Engine, db: &str, flow_id: &str, input: &str) -> bool {
+    let query = "SELECT 1 FROM events WHERE flow_id = $1 AND worker_type = 'start' AND data = $2 LIMIT 1".to_string();
+    let values = vec![JsonValue::String(flow_id.to_string()), JsonValue::String(input.to_string())];
+    match engine.db.select(db.to_string(), query, values, None).await {
+        Ok(rows) => !rows.is_empty(),
+        Err(e) => {
+            error!("Error checking for a run of flow {}: {}", flow_id, e);
+            true
+        }
+    }
+}
+
+// Starts a session of every enabled flow scheduled for this minute in the current stage
+async fn start_due_flows(engine: &Engine, minute: DateTime<Local>) {
+    let db = engine.current_db();
+    for (flow_id, dir) in engine.flows.all() {
+        let read = engine.flows.read_blocking(move |_| {
+            let definition = read_flow(&dir).and_then(|flow| serde_json::to_value(flow).map_err(|e| e.to_string()));
+            (definition, FlowSettings::load(&dir))
+        });
+        let (definition, settings) = match read.await {
+            Ok(read) => read,
+            Err(e) => {
+                warn!("Not scheduling flow {}: {}", flow_id, e);
+                continue;
+            }
+        };
+        let schedule = match definition.and_then(|definition| flow_schedule(&definition)) {
+            Ok(Some(schedule)) => schedule,
+            Ok(None) => continue,
+            Err(e) => {
+                warn!("Not scheduling flow {}: {}", flow_id, e);
+                continue;
+            }
+        };
+        if !schedule.matches(minute) || !settings.map_or(true, |settings| settings.enabled) {
+            continue;
+        }
+
+        let input = json!({ "scheduled_at": timestamp(minute.with_timezone(&Utc)) }).to_string();
+        if already_started(engine, &db, &flow_id, &input).await {
+            continue;
+        }
+        match super::trigger_flow(engine, &db, &flow_id, &input).await {
+            Ok(session_id) => info!("Started scheduled session {} of flow {}", session_id, flow_id),
+            Err(e) => error!("Error starting scheduled flow {}: {}", flow_id, e),
+        }
+    }
+}
+
+/// Starts flows with a cron trigger when their schedule comes up. Runs missed while
+/// nothing was running aren't made up.
+pub async fn trigger_scheduler(engine: &Engine) {
+    let mut last_minute = None;
+    loop {
+        let now = Local::now();
+        let minute = now.with_second(0).and_then(|t| t.with_nanosecond(0)).unwrap_or(now);
+        if last_minute != Some(minute) {
+            last_minute = Some(minute);
+            start_due_flows(engine, minute).await;
+        }
+        sleep(Duration::from_secs(10)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(year, month, day, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn steps_ranges_and_lists() {
+        let pattern = CronPattern::parse("*/15 9-17 * * 1,3").unwrap();
+        // 2024-01-01 is a Monday, 2024-01-02 a Tuesday
+        assert!(pattern.matches(at(2024, 1, 1, 9, 45)));
+        assert!(!pattern.matches(at(2024, 1, 1, 9, 50)));
+        assert!(!pattern.matches(at(2024, 1, 1, 18, 0)));
+        assert!(!pattern.matches(at(2024, 1, 2, 9, 0)));
+
+        let offset = CronPattern::parse("5/20 * * * *").unwrap();
+        assert!(offset.matches(at(2024, 1, 1, 0, 45)));
+        assert!(!offset.matches(at(2024, 1, 1, 0, 0)));
+    }
+
+    #[test]
+    fn restricted_day_fields_match_if_either_does() {
+        // The 13th, or any Friday
+        let pattern = CronPattern::parse("0 0 13 * 5").unwrap();
+        assert!(pattern.matches(at(2024, 2, 13, 0, 0)));
+        assert!(pattern.matches(at(2024, 2, 16, 0, 0)));
+        assert!(!pattern.matches(at(2024, 2, 14, 0, 0)));
+
+        // With one day field left open only the other one counts
+        let fridays = CronPattern::parse("0 0 * * 5").unwrap();
+        assert!(!fridays.matches(at(2024, 2, 13, 0, 0)));
+        assert!(fridays.matches(at(2024, 2, 16, 0, 0)));
+    }
+
+    #[test]
+    fn sunday_is_zero_or_seven() {
+        // 2024-01-07 is a Sunday
+        assert!(CronPattern::parse("0 0 * * 7").unwrap().matches(at(2024, 1, 7, 0, 0)));
+        assert!(CronPattern::parse("0 0 * * 0").unwrap().matches(at(2024, 1, 7, 0, 0)));
+    }
+
+    #[test]
+    fn invalid_patterns_are_rejected() {
+        for pattern in ["* * * *", "60 * * * *", "*/0 * * * *", "5-1 * * * *", "* * 0 * *", "a * * * *"] {
+            assert!(CronPattern::parse(pattern).is_err(), "{} should be invalid", pattern);
+        }
+    }
+}
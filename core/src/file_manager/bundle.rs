@@ -0,0 +1,324 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use tracing::info;
+use uuid::Uuid;
+use zip::{write::FileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::config::{get_flows_dir, get_models_dir};
+use crate::events::SUPPORTED_WORKER_TYPES;
+use crate::file_manager::flow_index::FlowIndex;
+use crate::file_manager::flows::{
+    dir_name, flow_dir, free_dir, install_flow, staging_dir, validate_name, FlowSummary,
+};
+use crate::file_manager::settings::FlowSettings;
+use crate::local_models::models::AVAILABLE_MODELS;
+
+// Bump when the layout of a bundle changes. Older apps refuse newer bundles.
+const BUNDLE_FORMAT: u32 = 1;
+const MANIFEST_FILE: &str = "manifest.json";
+const SECRET_PREFIX: &str = "{{secrets.";
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ModelRef {
+    pub filename: String,
+    /// Where to download it from, if it is one of the models in `models.json`
+    pub url: Option<String>,
+}
+
+/// What a flow needs from the machine it runs on. Written to `manifest.json`
+/// next to `flow.toml` and `settings.toml` in the bundle.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundleManifest {
+    pub format: u32,
+    pub flow_id: String,
+    pub name: String,
+    pub flow_version: String,
+    pub exported_at: String,
+    /// Names referenced as `{{secrets.NAME}}`. Values are never exported.
+    pub secrets: Vec<String>,
+    pub models: Vec<ModelRef>,
+    pub worker_types: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportReport {
+    pub manifest: BundleManifest,
+    /// The installed flow. `None` when only inspecting a bundle.
+    pub flow: Option<FlowSummary>,
+    /// The flow id was already taken here so the import got a new one
+    pub new_flow_id: bool,
+    /// Secrets the bundled settings have no `env` value for
+    pub missing_secrets: Vec<String>,
+    /// Models not downloaded into the models dir yet
+    pub missing_models: Vec<ModelRef>,
+    pub unsupported_worker_types: Vec<String>,
+}
+
+// Collects every `{{secrets.NAME}}` in the strings of a value
+fn collect_secrets(value: &JsonValue, names: &mut BTreeSet<String>) {
+    match value {
+        JsonValue::String(text) => {
+            let mut rest = text.as_str();
+            while let Some(start) = rest.find(SECRET_PREFIX) {
+                rest = &rest[start + SECRET_PREFIX.len()..];
+                if let Some(end) = rest.find("}}") {
+                    let name = rest[..end].trim();
+                    if !name.is_empty() {
+                        names.insert(name.to_string());
+                    }
+                    rest = &rest[end..];
+                }
+            }
+        }
+        JsonValue::Array(items) => items.iter().for_each(|item| collect_secrets(item, names)),
+        JsonValue::Object(map) => map.values().for_each(|item| collect_secrets(item, names)),
+        _ => {}
+    }
+}
+
+fn node_data(flow: &JsonValue) -> Vec<&JsonValue> {
+    flow.get("nodes")
+        .and_then(JsonValue::as_array)
+        .map(|nodes| nodes.iter().filter_map(|node| node.get("data")).collect())
+        .unwrap_or_default()
+}
+
+/// Works out the requirements of a flow from its `flow.toml` and `settings.toml`.
+pub fn build_manifest(flow: &JsonValue, settings: &JsonValue) -> Result<BundleManifest, String> {
+    let info = |key: &str| {
+        flow.get("flow")
+            .and_then(|info| info.get(key))
+            .and_then(JsonValue::as_str)
+            .unwrap_or_default()
+            .to_string()
+    };
+    let flow_id = info("id");
+    if flow_id.is_empty() {
+        return Err("flow.toml has no [flow] id".to_string());
+    }
+
+    let mut secrets = BTreeSet::new();
+    collect_secrets(flow, &mut secrets);
+    collect_secrets(settings, &mut secrets);
+
+    let mut worker_types = BTreeSet::new();
+    let mut models = BTreeSet::new();
+    for data in node_data(flow) {
+        if let Some(worker_type) = data.get("worker_type").and_then(JsonValue::as_str) {
+            worker_types.insert(worker_type.to_string());
+        }
+        // local_model nodes name the model file they run
+        if let Some(filename) = data.get("filename").and_then(JsonValue::as_str).filter(|f| !f.is_empty()) {
+            let url = AVAILABLE_MODELS
+                .iter()
+                .find(|model| model.filename == filename)
+                .map(|model| model.url.clone());
+            models.insert(ModelRef {
+                filename: filename.to_string(),
+                url,
+            });
+        }
+    }
+
+    Ok(BundleManifest {
+        format: BUNDLE_FORMAT,
+        flow_id,
+        name: info("name"),
+        flow_version: info("version"),
+        exported_at: Utc::now().to_rfc3339(),
+        secrets: secrets.into_iter().collect(),
+        models: models.into_iter().collect(),
+        worker_types: worker_types.into_iter().collect(),
+    })
+}
+
+fn parse_toml(content: &str, file: &str) -> Result<JsonValue, String> {
+    toml::from_str(content).map_err(|e| format!("Failed to parse {} in bundle: {}", file, e))
+}
+
+struct Bundle {
+    manifest: BundleManifest,
+    flow_toml: String,
+    settings_toml: String,
+    // The bundled settings' env, where the flow's secrets come from
+    env: BTreeMap<String, String>,
+}
+
+fn read_entry<R: Read + std::io::Seek>(archive: &mut ZipArchive<R>, name: &str) -> Result<Option<String>, String> {
+    let mut file = match archive.by_name(name) {
+        Ok(file) => file,
+        Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+        Err(e) => return Err(e.to_string()),
+    };
+    let mut content = String::new();
+    file.read_to_string(&mut content).map_err(|e| e.to_string())?;
+    Ok(Some(content))
+}
+
+// Reads a bundle and checks the manifest agrees with the flow inside it
+fn read_bundle(path: &Path) -> Result<Bundle, String> {
+    let file = File::open(path).map_err(|e| format!("Could not open bundle {:?}: {}", path, e))?;
+    let mut archive = ZipArchive::new(file).map_err(|e| format!("Not a flow bundle: {}", e))?;
+
+    let manifest: BundleManifest = read_entry(&mut archive, MANIFEST_FILE)?
+        .ok_or("Bundle has no manifest.json")
+        .and_then(|content| serde_json::from_str(&content).map_err(|_| "Bundle has an invalid manifest.json"))?;
+    if manifest.format > BUNDLE_FORMAT {
+        return Err(format!(
+            "Bundle format {} is newer than this app supports ({})",
+            manifest.format, BUNDLE_FORMAT
+        ));
+    }
+
+    let flow_toml = read_entry(&mut archive, "flow.toml")?.ok_or("Bundle has no flow.toml")?;
+    let settings_toml = read_entry(&mut archive, "settings.toml")?.unwrap_or_default();
+
+    // Requirements are taken from the flow itself so a hand edited manifest can't hide any
+    let settings = parse_toml(&settings_toml, "settings.toml")?;
+    let actual = build_manifest(&parse_toml(&flow_toml, "flow.toml")?, &settings)?;
+    if actual.flow_id != manifest.flow_id {
+        return Err(format!(
+            "Manifest is for flow {} but flow.toml has id {}",
+            manifest.flow_id, actual.flow_id
+        ));
+    }
+    let merge = |listed: &[String], found: Vec<String>| -> Vec<String> {
+        listed.iter().cloned().chain(found).collect::<BTreeSet<_>>().into_iter().collect()
+    };
+    let manifest = BundleManifest {
+        secrets: merge(&manifest.secrets, actual.secrets),
+        worker_types: merge(&manifest.worker_types, actual.worker_types),
+        models: manifest
+            .models
+            .iter()
+            .cloned()
+            .chain(actual.models)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect(),
+        ..manifest
+    };
+
+    Ok(Bundle {
+        manifest,
+        flow_toml,
+        settings_toml,
+        env: FlowSettings::from_json(&settings).map(|settings| settings.env).unwrap_or_default(),
+    })
+}
+
+fn report(manifest: BundleManifest, env: &BTreeMap<String, String>) -> Result<ImportReport, String> {
+    let models_dir = get_models_dir().map_err(|e| e.to_string())?;
+    Ok(ImportReport {
+        missing_secrets: manifest
+            .secrets
+            .iter()
+            .filter(|name| !env.contains_key(name.as_str()))
+            .cloned()
+            .collect(),
+        missing_models: manifest
+            .models
+            .iter()
+            .filter(|model| !models_dir.join(&model.filename).exists())
+            .cloned()
+            .collect(),
+        unsupported_worker_types: manifest
+            .worker_types
+            .iter()
+            .filter(|worker_type| !SUPPORTED_WORKER_TYPES.contains(&worker_type.as_str()))
+            .cloned()
+            .collect(),
+        manifest,
+        flow: None,
+        new_flow_id: false,
+    })
+}
+
+/// Writes the flow, its settings and a manifest of what it needs into a zip at `path`.
+pub fn export_flow(index: &FlowIndex, flow_id: String, path: String) -> Result<BundleManifest, String> {
+    let (flow_toml, settings_toml) = {
+        let _guard = index.read_lock();
+        let dir = flow_dir(index, &flow_id)?;
+        let flow_toml = fs::read_to_string(dir.join("flow.toml")).map_err(|e| e.to_string())?;
+        let settings_toml = fs::read_to_string(dir.join("settings.toml")).unwrap_or_default();
+        (flow_toml, settings_toml)
+    };
+
+    let manifest = build_manifest(&parse_toml(&flow_toml, "flow.toml")?, &parse_toml(&settings_toml, "settings.toml")?)?;
+    let manifest_json = serde_json::to_string_pretty(&manifest).map_err(|e| e.to_string())?;
+
+    let file = File::create(&path).map_err(|e| format!("Could not create {}: {}", path, e))?;
+    let mut zip = ZipWriter::new(file);
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    for (name, content) in [
+        (MANIFEST_FILE, &manifest_json),
+        ("flow.toml", &flow_toml),
+        ("settings.toml", &settings_toml),
+    ] {
+        zip.start_file(name, options).map_err(|e| e.to_string())?;
+        zip.write_all(content.as_bytes()).map_err(|e| e.to_string())?;
+    }
+    zip.finish().map_err(|e| e.to_string())?;
+
+    info!("Exported flow {} to {}", flow_id, path);
+    Ok(manifest)
+}
+
+/// Reads a bundle's manifest and reports what is missing on this machine without importing it.
+pub fn inspect_flow_bundle(path: String) -> Result<ImportReport, String> {
+    let bundle = read_bundle(Path::new(&path))?;
+    report(bundle.manifest, &bundle.env)
+}
+
+/// Installs a bundle as a new flow. The flow keeps its id unless a flow here already
+/// uses it. Missing secrets, models and worker types are reported, not fatal.
+pub fn import_flow(
+    index: &FlowIndex,
+    path: String,
+    flow_name: Option<String>,
+) -> Result<ImportReport, String> {
+    let bundle = read_bundle(Path::new(&path))?;
+
+    let _guard = index.write_lock();
+    let flows_dir = get_flows_dir().map_err(|e| e.to_string())?;
+    let name = match flow_name {
+        Some(name) => name,
+        None => {
+            let base = Some(bundle.manifest.name.clone())
+                .filter(|name| validate_name(name).is_ok())
+                .unwrap_or_else(|| "Imported Flow".to_string());
+            (1..)
+                .map(|n| if n == 1 { base.clone() } else { format!("{} {}", base, n) })
+                .find(|name| !flows_dir.join(name).exists())
+                .unwrap()
+        }
+    };
+    let target = free_dir(&flows_dir, &name)?;
+
+    let new_flow_id = index.get(&bundle.manifest.flow_id).is_some();
+    let flow_id = if new_flow_id {
+        Uuid::new_v4().to_string()
+    } else {
+        bundle.manifest.flow_id.clone()
+    };
+
+    let staged = staging_dir()?;
+    let installed = fs::write(staged.join("flow.toml"), &bundle.flow_toml)
+        .and_then(|_| fs::write(staged.join("settings.toml"), &bundle.settings_toml))
+        .map_err(|e| e.to_string())
+        .and_then(|_| install_flow(index, &staged, &target, &name, &flow_id));
+    let _ = fs::remove_dir_all(&staged);
+    let flow = installed?;
+
+    info!("Imported flow {} from {} into {}", flow_id, path, dir_name(&target));
+    let mut report = report(bundle.manifest, &bundle.env)?;
+    report.flow = Some(flow);
+    report.new_flow_id = new_flow_id;
+    Ok(report)
+}
@@ -0,0 +1,258 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::thread;
+use std::time::Duration;
+
+use notify::{recommended_watcher, Event as FsEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use serde_json::Value as JsonValue;
+use tracing::{error, warn};
+
+use crate::config::get_flows_dir;
+use crate::file_manager::versions::VersionRecorder;
+
+// How long the watcher waits for a burst of changes to settle before acting on them
+const DEBOUNCE: Duration = Duration::from_millis(250);
+
+/// Maps the `[flow] id` in each `flow.toml` to the directory it lives in so the
+/// engine can find a flow after it has been renamed. Clones share the same index.
+#[derive(Default, Clone)]
+pub struct FlowIndex {
+    flows: Arc<RwLock<HashMap<String, PathBuf>>>,
+    // Ids a rescan didn't find. Only kept while the watcher runs, it clears them on any change.
+    misses: Arc<Mutex<HashSet<String>>>,
+    watcher: Arc<Mutex<Option<RecommendedWatcher>>>,
+    // Held for writing while flow directories are created, moved or deleted
+    ops: Arc<RwLock<()>>,
+    // Two settings updates at once would otherwise both read the old file and one would be lost
+    settings: Arc<Mutex<()>>,
+    versions: VersionRecorder,
+}
+
+impl FlowIndex {
+    pub fn build() -> FlowIndex {
+        let index = FlowIndex::default();
+        if let Err(e) = index.rebuild() {
+            error!("Error building flow index: {}", e);
+        }
+        // Catch edits made while the app was closed
+        for dir in index.all().values() {
+            if let Err(e) = index.versions.record(dir) {
+                error!("Error recording version of {:?}: {}", dir, e);
+            }
+        }
+        index
+    }
+
+    pub fn rebuild(&self) -> anyhow::Result<()> {
+        let flows = scan(&get_flows_dir()?)?;
+        *self.flows.write().unwrap() = flows;
+        self.misses.lock().unwrap().clear();
+        Ok(())
+    }
+
+    /// Directory of the flow with this id. Rescans once on a miss in case the
+    /// watcher hasn't caught up with a change yet, and not again for that id
+    /// until the watcher sees something change.
+    pub fn get(&self, flow_id: &str) -> Option<PathBuf> {
+        if let Some(dir) = self.flows.read().unwrap().get(flow_id) {
+            return Some(dir.clone());
+        }
+        if self.misses.lock().unwrap().contains(flow_id) {
+            return None;
+        }
+        self.rebuild().ok()?;
+        let dir = self.flows.read().unwrap().get(flow_id).cloned();
+        if dir.is_none() && self.watcher.lock().unwrap().is_some() {
+            self.misses.lock().unwrap().insert(flow_id.to_string());
+        }
+        dir
+    }
+
+    /// Hold while reading a flow's files so a rename or delete can't happen halfway through.
+    pub fn read_lock(&self) -> RwLockReadGuard<'_, ()> {
+        self.ops.read().unwrap()
+    }
+
+    /// Runs `read` with the read lock held on a blocking thread, so async code doesn't
+    /// stall the runtime on the disk or on a rename in progress.
+    pub async fn read_blocking<T, F>(&self, read: F) -> Result<T, String>
+    where
+        T: Send + 'static,
+        F: FnOnce(&FlowIndex) -> T + Send + 'static,
+    {
+        let index = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let _guard = index.read_lock();
+            read(&index)
+        })
+        .await
+        .map_err(|e| format!("Reading flows failed: {}", e))
+    }
+
+    pub fn write_lock(&self) -> RwLockWriteGuard<'_, ()> {
+        self.ops.write().unwrap()
+    }
+
+    /// Hold while reading and rewriting a flow's settings.toml
+    pub fn settings_lock(&self) -> MutexGuard<'_, ()> {
+        self.settings.lock().unwrap()
+    }
+
+    pub fn versions(&self) -> &VersionRecorder {
+        &self.versions
+    }
+
+    pub fn all(&self) -> HashMap<String, PathBuf> {
+        self.flows.read().unwrap().clone()
+    }
+
+    /// Keeps the index current while the app runs. Changes under the flows directory are
+    /// collected until they settle, then only the flow directories they touched are rescanned.
+    /// Hidden and temporary files are ignored.
+    pub fn watch(&self) -> anyhow::Result<()> {
+        let flows_dir = get_flows_dir()?;
+        let (sender, changes) = mpsc::channel::<PathBuf>();
+
+        let mut watcher = recommended_watcher(move |res: notify::Result<FsEvent>| match res {
+            Ok(event) => {
+                for path in event.paths {
+                    let _ = sender.send(path);
+                }
+            }
+            Err(e) => error!("Flow watcher error: {:?}", e),
+        })?;
+        watcher.watch(&flows_dir, RecursiveMode::Recursive)?;
+
+        let flows = self.flows.clone();
+        let misses = self.misses.clone();
+        let versions = self.versions.clone();
+        // Ends once the watcher, and with it the sender, is dropped
+        thread::spawn(move || {
+            while let Ok(path) = changes.recv() {
+                let mut paths = vec![path];
+                while let Ok(path) = changes.recv_timeout(DEBOUNCE) {
+                    paths.push(path);
+                }
+
+                let mut dirs = BTreeSet::new();
+                let mut edited = BTreeSet::new();
+                for path in &paths {
+                    if let Some((dir, flow_file)) = changed_flow_dir(&flows_dir, path) {
+                        if flow_file {
+                            edited.insert(dir.clone());
+                        }
+                        dirs.insert(dir);
+                    }
+                }
+                if dirs.is_empty() {
+                    continue;
+                }
+
+                for dir in &dirs {
+                    rescan_dir(&flows, dir);
+                }
+                misses.lock().unwrap().clear();
+                for dir in edited.iter().filter(|dir| dir.join("flow.toml").exists()) {
+                    if let Err(e) = versions.record_change(dir) {
+                        error!("Error recording version of {:?}: {}", dir, e);
+                    }
+                }
+            }
+        });
+
+        *self.watcher.lock().unwrap() = Some(watcher);
+        Ok(())
+    }
+}
+
+// The flow directory a changed path belongs to and whether the change was to its
+// flow.toml. `None` for the flows dir itself and for hidden or temporary files.
+fn changed_flow_dir(flows_dir: &Path, path: &Path) -> Option<(PathBuf, bool)> {
+    let relative = path.strip_prefix(flows_dir).ok()?;
+    let names: Vec<&str> = relative
+        .components()
+        .map(|component| component.as_os_str().to_str())
+        .collect::<Option<_>>()?;
+    if names.iter().any(|name| name.starts_with('.') || name.ends_with(".tmp")) {
+        return None;
+    }
+    let dir = flows_dir.join(names.first()?);
+    Some((dir, names.len() == 2 && names[1] == "flow.toml"))
+}
+
+// Brings the index up to date with one flow directory that changed, moved or went away
+fn rescan_dir(flows: &RwLock<HashMap<String, PathBuf>>, dir: &Path) {
+    let flow_id = read_flow_id(dir);
+    let mut flows = flows.write().unwrap();
+    flows.retain(|_, path| path != dir);
+    if let Some(flow_id) = flow_id {
+        if let Some(existing) = flows.insert(flow_id.clone(), dir.to_path_buf()) {
+            warn!("Duplicate flow id {} in {:?} and {:?}", flow_id, existing, dir);
+        }
+    }
+}
+
+// Reads `[flow] id` out of a flow directory's flow.toml
+pub fn read_flow_id(dir: &Path) -> Option<String> {
+    let content = fs::read_to_string(dir.join("flow.toml")).ok()?;
+    let parsed: JsonValue = toml::from_str(&content).ok()?;
+    parsed
+        .get("flow")?
+        .get("id")?
+        .as_str()
+        .map(|id| id.to_string())
+}
+
+fn scan(flows_dir: &Path) -> anyhow::Result<HashMap<String, PathBuf>> {
+    let mut flows = HashMap::new();
+    for entry in fs::read_dir(flows_dir)? {
+        let path = entry?.path();
+        let hidden = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_none_or(|name| name.starts_with('.'));
+        if !path.is_dir() || hidden {
+            continue;
+        }
+        match read_flow_id(&path) {
+            Some(flow_id) => {
+                if let Some(existing) = flows.insert(flow_id.clone(), path.clone()) {
+                    warn!(
+                        "Duplicate flow id {} in {:?} and {:?}",
+                        flow_id, existing, path
+                    );
+                }
+            }
+            None => warn!("No flow id found in {:?}", path),
+        }
+    }
+    Ok(flows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changes_map_to_their_flow_directory() {
+        let flows = Path::new("/app/flows");
+        let changed = |path: &str| changed_flow_dir(flows, Path::new(path));
+        assert_eq!(changed("/app/flows/A/flow.toml"), Some((flows.join("A"), true)));
+        assert_eq!(changed("/app/flows/A/settings.toml"), Some((flows.join("A"), false)));
+        assert_eq!(changed("/app/flows/A/nested/flow.toml"), Some((flows.join("A"), false)));
+        assert_eq!(changed("/app/flows/B"), Some((flows.join("B"), false)));
+    }
+
+    #[test]
+    fn hidden_and_temporary_files_are_ignored() {
+        let flows = Path::new("/app/flows");
+        let changed = |path: &str| changed_flow_dir(flows, Path::new(path));
+        assert_eq!(changed("/app/flows/A/.flow.toml.tmp"), None);
+        assert_eq!(changed("/app/flows/A/flow.toml.tmp"), None);
+        assert_eq!(changed("/app/flows/.staging/x/flow.toml"), None);
+        assert_eq!(changed("/app/flows"), None);
+        assert_eq!(changed("/elsewhere/A/flow.toml"), None);
+    }
+}
@@ -0,0 +1,486 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::config::{get_app_dir, get_flows_dir, get_templates_dir, get_trash_dir};
+use crate::file_manager::flow_index::FlowIndex;
+
+pub const BLANK_TEMPLATE: &str = "blank";
+
+const BLANK_SETTINGS: &str = "[settings]\n";
+
+lazy_static! {
+    // A node referenced from a template or condition, like `{{nodes.3.result}}` or `nodes.3.result.ok`
+    static ref NODE_REFERENCE: Regex = Regex::new(r"nodes\.([A-Za-z0-9_-]+)").unwrap();
+}
+
+/// A flow directory with the metadata from its `flow.toml`. Flows that fail to
+/// parse are still listed with `error` set so they can be fixed or deleted.
+#[derive(Debug, Clone, Serialize)]
+pub struct FlowSummary {
+    pub flow_id: Option<String>,
+    /// Name of the directory under the flows dir
+    pub flow_name: String,
+    pub name: Option<String>,
+    pub version: Option<String>,
+    pub author: Option<String>,
+    pub description: Option<String>,
+    pub path: String,
+    pub node_count: usize,
+    pub edge_count: usize,
+    pub has_settings: bool,
+    pub error: Option<String>,
+}
+
+impl FlowSummary {
+    fn read(dir: &Path) -> FlowSummary {
+        let mut summary = FlowSummary {
+            flow_id: None,
+            flow_name: dir_name(dir),
+            name: None,
+            version: None,
+            author: None,
+            description: None,
+            path: dir.to_string_lossy().to_string(),
+            node_count: 0,
+            edge_count: 0,
+            has_settings: dir.join("settings.toml").exists(),
+            error: None,
+        };
+
+        let parsed: JsonValue = match fs::read_to_string(dir.join("flow.toml"))
+            .map_err(|e| e.to_string())
+            .and_then(|content| toml::from_str(&content).map_err(|e| e.to_string()))
+        {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                summary.error = Some(format!("Could not read flow.toml: {}", e));
+                return summary;
+            }
+        };
+
+        let field = |key: &str| {
+            parsed
+                .get("flow")
+                .and_then(|flow| flow.get(key))
+                .and_then(JsonValue::as_str)
+                .map(|value| value.to_string())
+        };
+        let count = |key: &str| parsed.get(key).and_then(JsonValue::as_array).map_or(0, |items| items.len());
+
+        summary.flow_id = field("id");
+        summary.name = field("name");
+        summary.version = field("version");
+        summary.author = field("author");
+        summary.description = field("description");
+        summary.node_count = count("nodes");
+        summary.edge_count = count("edges");
+        if summary.flow_id.is_none() {
+            summary.error = Some("flow.toml has no [flow] id".to_string());
+        }
+        summary
+    }
+}
+
+pub fn dir_name(dir: &Path) -> String {
+    dir.file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("Unknown")
+        .to_string()
+}
+
+// Flow names become directory names
+pub fn validate_name(name: &str) -> Result<(), String> {
+    let trimmed = name.trim();
+    if trimmed.is_empty() {
+        return Err("Flow name can't be empty".to_string());
+    }
+    if trimmed != name {
+        return Err(format!("Flow name can't start or end with whitespace: {:?}", name));
+    }
+    if name.starts_with('.') || name.contains(['/', '\\', ':', '*', '?', '"', '<', '>', '|']) {
+        return Err(format!("Invalid flow name: {}", name));
+    }
+    Ok(())
+}
+
+pub fn free_dir(flows_dir: &Path, name: &str) -> Result<PathBuf, String> {
+    validate_name(name)?;
+    let dir = flows_dir.join(name);
+    if dir.exists() {
+        return Err(format!("A flow named {} already exists", name));
+    }
+    Ok(dir)
+}
+
+// "Flow 1", "Flow 2", ... whichever is free first
+fn next_flow_name(flows_dir: &Path) -> String {
+    (1..)
+        .map(|n| format!("Flow {}", n))
+        .find(|name| !flows_dir.join(name).exists())
+        .unwrap()
+}
+
+pub fn flow_dir(index: &FlowIndex, flow_id: &str) -> Result<PathBuf, String> {
+    index
+        .get(flow_id)
+        .ok_or_else(|| format!("Flow {} not found in the flows directory", flow_id))
+}
+
+pub fn read_flow(dir: &Path) -> Result<toml::Value, String> {
+    let content = fs::read_to_string(dir.join("flow.toml")).map_err(|e| e.to_string())?;
+    toml::from_str(&content).map_err(|e| format!("Failed to parse flow.toml in {:?}: {}", dir, e))
+}
+
+fn set_flow_field(flow: &mut toml::Value, key: &str, value: &str) -> Result<(), String> {
+    let table = flow
+        .as_table_mut()
+        .ok_or("flow.toml is not a table")?
+        .entry("flow")
+        .or_insert_with(|| toml::Value::Table(Default::default()))
+        .as_table_mut()
+        .ok_or("[flow] is not a table")?;
+    table.insert(key.to_string(), toml::Value::String(value.to_string()));
+    Ok(())
+}
+
+/// Writes next to the target and renames over it so readers never see half a file.
+/// The temporary file is hidden so the index watcher ignores it.
+pub fn write_atomic(path: &Path, content: &str) -> Result<(), String> {
+    let name = path.file_name().and_then(|name| name.to_str()).ok_or("Invalid file name")?;
+    let tmp = path.with_file_name(format!(".{}.tmp", name));
+    fs::write(&tmp, content).map_err(|e| e.to_string())?;
+    fs::rename(&tmp, path).map_err(|e| e.to_string())
+}
+
+fn write_flow(dir: &Path, flow: &toml::Value) -> Result<(), String> {
+    let content = toml::to_string(flow).map_err(|e| e.to_string())?;
+    write_atomic(&dir.join("flow.toml"), &content)
+}
+
+pub fn copy_dir(from: &Path, to: &Path) -> Result<(), String> {
+    fs::create_dir_all(to).map_err(|e| e.to_string())?;
+    for entry in fs::read_dir(from).map_err(|e| e.to_string())? {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        let target = to.join(path.file_name().ok_or("Invalid file name")?);
+        if path.is_dir() {
+            copy_dir(&path, &target)?;
+        } else {
+            fs::copy(&path, &target).map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+// New flows are assembled outside the flows dir and moved in once complete, so the
+// scheduler and the index watcher never pick up a flow that is still being written
+pub fn staging_dir() -> Result<PathBuf, String> {
+    let dir = get_app_dir()
+        .map_err(|e| e.to_string())?
+        .join(".staging")
+        .join(Uuid::new_v4().to_string());
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    Ok(dir)
+}
+
+// Gives a copied flow its own identity
+fn install_new_flow(
+    index: &FlowIndex,
+    staged: &Path,
+    target: &Path,
+    name: &str,
+) -> Result<FlowSummary, String> {
+    install_flow(index, staged, target, name, &Uuid::new_v4().to_string())
+}
+
+/// Moves a flow assembled in a staging dir into the flows dir under `name` and `flow_id`.
+/// Callers hold the index's write lock.
+pub fn install_flow(
+    index: &FlowIndex,
+    staged: &Path,
+    target: &Path,
+    name: &str,
+    flow_id: &str,
+) -> Result<FlowSummary, String> {
+    let mut flow = read_flow(staged)?;
+    set_flow_field(&mut flow, "id", flow_id)?;
+    set_flow_field(&mut flow, "name", name)?;
+    write_flow(staged, &flow)?;
+    if !staged.join("settings.toml").exists() {
+        fs::write(staged.join("settings.toml"), BLANK_SETTINGS).map_err(|e| e.to_string())?;
+    }
+
+    fs::rename(staged, target).map_err(|e| e.to_string())?;
+    verify(index, target, flow_id)
+}
+
+// Re-reads the flow from disk and makes sure the index points at it
+fn verify(index: &FlowIndex, dir: &Path, flow_id: &str) -> Result<FlowSummary, String> {
+    index.rebuild().map_err(|e| e.to_string())?;
+
+    let summary = FlowSummary::read(dir);
+    if let Some(e) = &summary.error {
+        return Err(format!("Flow in {:?} is invalid: {}", dir, e));
+    }
+    if summary.flow_id.as_deref() != Some(flow_id) {
+        return Err(format!(
+            "Flow in {:?} has id {:?}, expected {}",
+            dir, summary.flow_id, flow_id
+        ));
+    }
+    if index.get(flow_id).as_deref() != Some(dir) {
+        return Err(format!("Flow index does not point flow {} at {:?}", flow_id, dir));
+    }
+    Ok(summary)
+}
+
+fn blank_flow(name: &str) -> String {
+    format!(
+        "[flow]\nname = {:?}\nid = \"\"\nversion = \"0.0.1\"\nauthor = \"Your Name <your.email@example.com>\"\ndescription = \"Description of your flow\"\n",
+        name
+    )
+}
+
+pub fn list_flows() -> Result<Vec<FlowSummary>, String> {
+    let flows_dir = get_flows_dir().map_err(|e| e.to_string())?;
+    let mut flows: Vec<FlowSummary> = fs::read_dir(flows_dir)
+        .map_err(|e| e.to_string())?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir() && !dir_name(path).starts_with('.'))
+        .map(|path| FlowSummary::read(&path))
+        .collect();
+    flows.sort_by(|a, b| a.flow_name.cmp(&b.flow_name));
+    Ok(flows)
+}
+
+/// Templates are flow directories in the templates dir, plus the built in blank flow.
+pub fn list_flow_templates() -> Result<Vec<String>, String> {
+    let templates_dir = get_templates_dir().map_err(|e| e.to_string())?;
+    let mut templates: Vec<String> = fs::read_dir(templates_dir)
+        .map_err(|e| e.to_string())?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.join("flow.toml").exists())
+        .map(|path| dir_name(&path))
+        .collect();
+    templates.sort();
+    templates.insert(0, BLANK_TEMPLATE.to_string());
+    Ok(templates)
+}
+
+pub fn create_flow(
+    index: &FlowIndex,
+    flow_name: Option<String>,
+    template: Option<String>,
+) -> Result<FlowSummary, String> {
+    let _guard = index.write_lock();
+    let flows_dir = get_flows_dir().map_err(|e| e.to_string())?;
+    let name = flow_name.unwrap_or_else(|| next_flow_name(&flows_dir));
+    let target = free_dir(&flows_dir, &name)?;
+
+    let template = template.unwrap_or_else(|| BLANK_TEMPLATE.to_string());
+    let source = if template == BLANK_TEMPLATE {
+        None
+    } else {
+        validate_name(&template)?;
+        let source = get_templates_dir().map_err(|e| e.to_string())?.join(&template);
+        if !source.join("flow.toml").exists() {
+            return Err(format!("Template {} not found", template));
+        }
+        Some(source)
+    };
+
+    let staged = staging_dir()?;
+    let result = match &source {
+        Some(source) => copy_dir(source, &staged),
+        None => fs::write(staged.join("flow.toml"), blank_flow(&name)).map_err(|e| e.to_string()),
+    }
+    .and_then(|_| install_new_flow(index, &staged, &target, &name));
+
+    let _ = fs::remove_dir_all(&staged);
+    result
+}
+
+/// Renames the flow's directory and `[flow] name`. The flow id stays the same so
+/// queued events and session history still find it.
+pub fn rename_flow(
+    index: &FlowIndex,
+    flow_id: String,
+    new_name: String,
+) -> Result<FlowSummary, String> {
+    let _guard = index.write_lock();
+    let dir = flow_dir(index, &flow_id)?;
+    let flows_dir = get_flows_dir().map_err(|e| e.to_string())?;
+
+    let target = if dir_name(&dir) == new_name {
+        dir.clone()
+    } else {
+        free_dir(&flows_dir, &new_name)?
+    };
+
+    // Move first, so a failed move leaves the old name in flow.toml too
+    if target != dir {
+        fs::rename(&dir, &target).map_err(|e| e.to_string())?;
+    }
+    let renamed = read_flow(&target).and_then(|mut flow| {
+        set_flow_field(&mut flow, "name", &new_name)?;
+        write_flow(&target, &flow)
+    });
+    if let Err(e) = renamed {
+        if target != dir {
+            if let Err(undo) = fs::rename(&target, &dir) {
+                return Err(format!("{}, and moving the flow back to {:?} failed: {}", e, dir, undo));
+            }
+        }
+        return Err(e);
+    }
+
+    verify(index, &target, &flow_id)
+}
+
+/// Copies a flow under a new name with a new flow id and new node ids.
+pub fn duplicate_flow(
+    index: &FlowIndex,
+    flow_id: String,
+    new_name: Option<String>,
+) -> Result<FlowSummary, String> {
+    let _guard = index.write_lock();
+    let dir = flow_dir(index, &flow_id)?;
+    let flows_dir = get_flows_dir().map_err(|e| e.to_string())?;
+    let name = new_name.unwrap_or_else(|| {
+        let base = format!("{} copy", dir_name(&dir));
+        (1..)
+            .map(|n| if n == 1 { base.clone() } else { format!("{} {}", base, n) })
+            .find(|name| !flows_dir.join(name).exists())
+            .unwrap()
+    });
+    let target = free_dir(&flows_dir, &name)?;
+
+    let staged = staging_dir()?;
+    let result = copy_dir(&dir, &staged)
+        .and_then(|_| {
+            let mut flow = read_flow(&staged)?;
+            renumber_nodes(&mut flow);
+            write_flow(&staged, &flow)
+        })
+        .and_then(|_| install_new_flow(index, &staged, &target, &name));
+
+    let _ = fs::remove_dir_all(&staged);
+    result
+}
+
+// Gives every node a new id and points the edges and the `nodes.ID` references in
+// node data at it, so the copy shares nothing keyed by node id with the original
+fn renumber_nodes(flow: &mut toml::Value) {
+    let mut ids = HashMap::new();
+    for node in toml_items(flow, "nodes") {
+        if let Some(toml::Value::String(id)) = node.get_mut("id") {
+            let new_id = Uuid::new_v4().to_string();
+            ids.insert(std::mem::replace(id, new_id.clone()), new_id);
+        }
+    }
+    for node in toml_items(flow, "nodes") {
+        if let Some(data) = node.get_mut("data") {
+            rewrite_references(data, &ids);
+        }
+    }
+    for edge in toml_items(flow, "edges") {
+        for end in ["source", "target"] {
+            if let Some(toml::Value::String(id)) = edge.get_mut(end) {
+                if let Some(new_id) = ids.get(id.as_str()) {
+                    *id = new_id.clone();
+                }
+            }
+        }
+    }
+}
+
+fn toml_items<'a>(flow: &'a mut toml::Value, key: &str) -> impl Iterator<Item = &'a mut toml::value::Table> {
+    flow.get_mut(key)
+        .and_then(toml::Value::as_array_mut)
+        .into_iter()
+        .flatten()
+        .filter_map(toml::Value::as_table_mut)
+}
+
+fn rewrite_references(value: &mut toml::Value, ids: &HashMap<String, String>) {
+    match value {
+        toml::Value::String(text) => {
+            let rewritten = NODE_REFERENCE.replace_all(text, |caps: &Captures| match ids.get(&caps[1]) {
+                Some(new_id) => format!("nodes.{}", new_id),
+                None => caps[0].to_string(),
+            });
+            *text = rewritten.into_owned();
+        }
+        toml::Value::Array(items) => items.iter_mut().for_each(|item| rewrite_references(item, ids)),
+        toml::Value::Table(table) => table.iter_mut().for_each(|(_, item)| rewrite_references(item, ids)),
+        _ => {}
+    }
+}
+
+/// Moves the flow's directory to the trash dir instead of deleting it. Returns
+/// where it ended up.
+pub fn delete_flow(index: &FlowIndex, flow_id: String) -> Result<String, String> {
+    let _guard = index.write_lock();
+    let dir = flow_dir(index, &flow_id)?;
+
+    let trashed = get_trash_dir()
+        .map_err(|e| e.to_string())?
+        .join(format!("{}-{}", dir_name(&dir), Utc::now().format("%Y%m%dT%H%M%S")));
+    fs::rename(&dir, &trashed).map_err(|e| e.to_string())?;
+
+    index.rebuild().map_err(|e| e.to_string())?;
+    if index.get(&flow_id).is_some() {
+        return Err(format!("Flow {} is still in the flows directory", flow_id));
+    }
+
+    info!("Moved flow {} to {:?}", flow_id, trashed);
+    Ok(trashed.to_string_lossy().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duplicated_nodes_get_new_ids_and_keep_their_wiring() {
+        let mut flow: toml::Value = toml::from_str(r#"
+            [flow]
+            id = "f"
+
+            [[nodes]]
+            id = "1"
+            data = { worker_type = "start" }
+
+            [[nodes]]
+            id = "12"
+            data = { worker_type = "terminal", command = "echo {{nodes.1.result}} {{nodes.9.result}}", condition = { field = "nodes.1.result.ok" } }
+
+            [[edges]]
+            id = "e1"
+            source = "1"
+            target = "12"
+        "#).unwrap();
+        renumber_nodes(&mut flow);
+
+        let nodes = flow["nodes"].as_array().unwrap();
+        let (start, terminal) = (nodes[0]["id"].as_str().unwrap(), nodes[1]["id"].as_str().unwrap());
+        assert!(Uuid::parse_str(start).is_ok() && Uuid::parse_str(terminal).is_ok());
+        assert_eq!(flow["edges"][0]["source"].as_str(), Some(start));
+        assert_eq!(flow["edges"][0]["target"].as_str(), Some(terminal));
+        assert_eq!(
+            nodes[1]["data"]["command"].as_str().unwrap(),
+            format!("echo {{{{nodes.{}.result}}}} {{{{nodes.9.result}}}}", start)
+        );
+        assert_eq!(nodes[1]["data"]["condition"]["field"].as_str().unwrap(), format!("nodes.{}.result.ok", start));
+    }
+}
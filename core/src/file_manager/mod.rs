@@ -1,17 +1,23 @@
 use std::fs;
 use serde_json::Value as JsonValue;
 use toml;
-use tauri; 
+use tracing::debug;
 use crate::config::get_flows_dir;
 use serde::Serialize;
 
+pub mod bundle;
+pub mod flow_index;
+pub mod flows;
+pub mod settings;
+pub mod validation;
+pub mod versions;
+
 #[derive(Debug, Serialize)]
 pub struct FlowInfo {
     flow_name: String,
     flow_value: JsonValue,
 }
 
-#[tauri::command]
 pub fn get_chat_flows() -> Result<Vec<FlowInfo>, String> {
     let mut flows_with_receive_chat_node: Vec<FlowInfo> = Vec::new();
 
@@ -30,12 +36,12 @@ pub fn get_chat_flows() -> Result<Vec<FlowInfo>, String> {
             let parsed_toml: JsonValue = toml::from_str(&toml_content).map_err(|e| e.to_string())?;
             //BUG: broken when we changed how we generate Nodes
             if let Some(nodes) = parsed_toml.get("nodes") {
-                println!("nodes in chat finder {:?}", nodes);
+                debug!("nodes in chat finder {:?}", nodes);
                 for node in nodes.as_array().unwrap() {
                     if let Some(node_data) = node.get("data") {
-                        println!("node type {:?}", node_data.as_str());
+                        debug!("node type {:?}", node_data.as_str());
                         if let Some(node_label) = node_data.get("node_label") {
-                            println!("node type {:?}", node_label.as_str());
+                            debug!("node type {:?}", node_label.as_str());
                         //TODO: this is a bad hack. We need a better way to do this
                         if node_label.as_str().unwrap_or("") == "App Chat Trigger" {
                             // flows_with_receive_chat_node.push(flow_name.to_string());
@@ -57,6 +63,6 @@ pub fn get_chat_flows() -> Result<Vec<FlowInfo>, String> {
             }
         }
     }
-    println!("{:?}", flows_with_receive_chat_node);
+    debug!("{:?}", flows_with_receive_chat_node);
     Ok(flows_with_receive_chat_node)
 }
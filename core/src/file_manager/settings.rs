@@ -0,0 +1,218 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value as JsonValue};
+use tokio::time::Duration;
+
+use crate::events::priority::SessionPriority;
+use crate::file_manager::flow_index::FlowIndex;
+use crate::file_manager::flows::{flow_dir, write_atomic};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    /// Extra attempts after a node fails. 0 fails the session on the first error.
+    #[serde(default)]
+    pub retries: u32,
+    #[serde(default = "default_backoff_secs")]
+    pub backoff_secs: u64,
+}
+
+fn default_backoff_secs() -> u64 {
+    5
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            retries: 0,
+            backoff_secs: default_backoff_secs(),
+        }
+    }
+}
+
+/// What starting a session does when the flow already runs `concurrency_limit` of them
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConcurrencyPolicy {
+    /// Wait for a running session to finish
+    #[default]
+    Queue,
+    /// Don't start the session
+    Skip,
+    /// Cancel the oldest running session and start this one
+    ReplaceOldest,
+}
+
+/// The `[settings]` table of a flow's `settings.toml`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlowSettings {
+    /// Disabled flows don't start new sessions
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Most sessions of this flow running at once. `concurrency_policy` says what extra starts do.
+    #[serde(default)]
+    pub concurrency_limit: Option<usize>,
+    #[serde(default)]
+    pub concurrency_policy: ConcurrencyPolicy,
+    /// How soon the flow's sessions run, instead of what their trigger gives them
+    #[serde(default)]
+    pub priority: Option<SessionPriority>,
+    /// Seconds a node may run before it fails
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    /// Environment for terminal nodes. Also the only place `{{secrets.NAME}}` is looked up.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Available to nodes as `{{settings.NAME}}`
+    #[serde(default)]
+    pub variables: BTreeMap<String, JsonValue>,
+    /// Keys from older settings files, kept so saving doesn't drop them
+    #[serde(flatten)]
+    pub extra: Map<String, JsonValue>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for FlowSettings {
+    fn default() -> Self {
+        FlowSettings {
+            enabled: true,
+            concurrency_limit: None,
+            concurrency_policy: ConcurrencyPolicy::default(),
+            priority: None,
+            timeout_secs: None,
+            retry: RetryPolicy::default(),
+            env: BTreeMap::new(),
+            variables: BTreeMap::new(),
+            extra: Map::new(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SettingsFile {
+    #[serde(default)]
+    settings: FlowSettings,
+}
+
+impl FlowSettings {
+    /// Settings from the parsed `settings.toml` stored in a flow snapshot
+    pub fn from_json(file: &JsonValue) -> Result<FlowSettings, String> {
+        match file.get("settings") {
+            Some(settings) => serde_json::from_value(settings.clone()).map_err(|e| format!("Invalid settings: {}", e)),
+            None => Ok(FlowSettings::default()),
+        }
+    }
+
+    /// A flow without a `settings.toml` gets the defaults
+    pub fn load(flow_dir: &Path) -> Result<FlowSettings, String> {
+        let path = flow_dir.join("settings.toml");
+        if !path.exists() {
+            return Ok(FlowSettings::default());
+        }
+        let content = fs::read_to_string(&path).map_err(|e| e.to_string())?;
+        let file: SettingsFile = toml::from_str(&content).map_err(|e| format!("Invalid settings.toml: {}", e))?;
+        Ok(file.settings)
+    }
+
+    fn save(&self, flow_dir: &Path) -> Result<(), String> {
+        let file = SettingsFile { settings: self.clone() };
+        // Through toml::Value so plain keys are written before tables whatever order the fields are in
+        let value = toml::Value::try_from(&file).map_err(|e| e.to_string())?;
+        let content = toml::to_string(&value).map_err(|e| e.to_string())?;
+        write_atomic(&flow_dir.join("settings.toml"), &content)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.concurrency_limit == Some(0) {
+            return Err("concurrency_limit must be at least 1".to_string());
+        }
+        if self.timeout_secs == Some(0) {
+            return Err("timeout_secs must be at least 1".to_string());
+        }
+        for name in self.env.keys() {
+            if name.is_empty() || name.contains(['=', '\0']) {
+                return Err(format!("Invalid environment variable name: {:?}", name));
+            }
+        }
+        for name in self.variables.keys() {
+            if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-') {
+                return Err(format!("Invalid variable name: {:?}", name));
+            }
+        }
+        // toml has no null
+        if self.variables.values().chain(self.extra.values()).any(JsonValue::is_null) {
+            return Err("Settings values can't be null".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout_secs.map(Duration::from_secs)
+    }
+
+    /// What nodes see as `{{settings.*}}`. Variables sit next to the typed fields
+    /// so `{{settings.api_base}}` works, but can't shadow them.
+    pub fn context(&self) -> JsonValue {
+        let mut context = Map::new();
+        for (name, value) in &self.variables {
+            context.insert(name.clone(), value.clone());
+        }
+        if let Ok(JsonValue::Object(typed)) = serde_json::to_value(self) {
+            for (key, value) in typed {
+                context.insert(key, value);
+            }
+        }
+        JsonValue::Object(context)
+    }
+}
+
+pub fn get_flow_settings(index: &FlowIndex, flow_id: String) -> Result<FlowSettings, String> {
+    let _guard = index.read_lock();
+    FlowSettings::load(&flow_dir(index, &flow_id)?)
+}
+
+/// Replaces the flow's settings. Running sessions keep the settings they started with.
+pub fn update_flow_settings(
+    index: &FlowIndex,
+    flow_id: String,
+    settings: FlowSettings,
+) -> Result<FlowSettings, String> {
+    settings.validate()?;
+
+    let _guard = index.read_lock();
+    let _settings_guard = index.settings_lock();
+    let dir = flow_dir(index, &flow_id)?;
+    settings.save(&dir)?;
+
+    // Read it back so what we return is what the engine will see
+    FlowSettings::load(&dir)
+}
+
+/// Sets or removes (`value` of `None`) a single variable without touching the rest.
+pub fn set_flow_variable(
+    index: &FlowIndex,
+    flow_id: String,
+    name: String,
+    value: Option<JsonValue>,
+) -> Result<FlowSettings, String> {
+    let _guard = index.read_lock();
+    let _settings_guard = index.settings_lock();
+    let dir = flow_dir(index, &flow_id)?;
+
+    let mut settings = FlowSettings::load(&dir)?;
+    match value {
+        Some(value) => settings.variables.insert(name, value),
+        None => settings.variables.remove(&name),
+    };
+    settings.validate()?;
+    settings.save(&dir)?;
+
+    FlowSettings::load(&dir)
+}
@@ -0,0 +1,574 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+
+use crate::events::claims::interrupt_policy;
+use crate::events::control::{loop_condition, map_concurrency, max_iterations, router_routes, router_takes_all};
+use crate::events::graph::{repeats_body, FlowGraph, LOOP_BODY_HANDLE, LOOP_DONE_HANDLE, ROUTER_DEFAULT_HANDLE};
+use crate::events::pause::{parse_duration, parse_until, APPROVED_HANDLE, REJECTED_HANDLE};
+use crate::events::triggers::{check_schedule, CRON_TRIGGER};
+use crate::events::SUPPORTED_WORKER_TYPES;
+use crate::file_manager::flow_index::FlowIndex;
+use crate::file_manager::flows::flow_dir;
+
+const HTTP_METHODS: [&str; 7] = ["GET", "POST", "PUT", "PATCH", "DELETE", "HEAD", "OPTIONS"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// The flow can't run
+    Error,
+    /// The flow runs but probably not the way it was meant to
+    Warning,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Stable identifier for the kind of problem, e.g. `dangling_edge`
+    pub code: String,
+    pub message: String,
+    pub node_id: Option<String>,
+    pub edge_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ValidationReport {
+    pub valid: bool,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl ValidationReport {
+    pub fn errors(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics.iter().filter(|d| d.severity == Severity::Error)
+    }
+
+    /// One line per error, for failing a session
+    pub fn error_summary(&self) -> String {
+        self.errors()
+            .map(|d| match &d.node_id {
+                Some(node_id) => format!("node {}: {}", node_id, d.message),
+                None => d.message.clone(),
+            })
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+}
+
+#[derive(Default)]
+struct Diagnostics(Vec<Diagnostic>);
+
+impl Diagnostics {
+    fn push(&mut self, severity: Severity, code: &str, message: String, node_id: Option<&str>, edge_id: Option<&str>) {
+        self.0.push(Diagnostic {
+            severity,
+            code: code.to_string(),
+            message,
+            node_id: node_id.map(|id| id.to_string()),
+            edge_id: edge_id.map(|id| id.to_string()),
+        });
+    }
+
+    fn error(&mut self, code: &str, message: String, node_id: Option<&str>) {
+        self.push(Severity::Error, code, message, node_id, None);
+    }
+
+    fn warning(&mut self, code: &str, message: String, node_id: Option<&str>) {
+        self.push(Severity::Warning, code, message, node_id, None);
+    }
+}
+
+fn text<'a>(value: &'a JsonValue, key: &str) -> Option<&'a str> {
+    value.get(key).and_then(JsonValue::as_str).filter(|s| !s.trim().is_empty())
+}
+
+// Fields each worker type can't run without
+fn check_node_fields(node_id: &str, worker_type: &str, data: &JsonValue, diagnostics: &mut Diagnostics) {
+    if let Err(e) = interrupt_policy(worker_type, data) {
+        diagnostics.error("invalid_field", e, Some(node_id));
+    }
+    match worker_type {
+        "rest" => {
+            match text(data, "url") {
+                None => diagnostics.error("missing_field", "REST node has no url".to_string(), Some(node_id)),
+                Some(url) if !url.starts_with("http://") && !url.starts_with("https://") && !url.contains("{{") => {
+                    diagnostics.error("invalid_field", format!("url {:?} is not an http(s) url", url), Some(node_id))
+                }
+                _ => {}
+            }
+            if let Some(method) = text(data, "method") {
+                if !HTTP_METHODS.contains(&method.to_uppercase().as_str()) {
+                    diagnostics.error("invalid_field", format!("Unknown HTTP method {:?}", method), Some(node_id));
+                }
+            }
+            // The worker silently drops headers it can't parse
+            if let Some(headers) = text(data, "headers") {
+                if serde_json::from_str::<HashMap<String, String>>(headers).is_err() {
+                    diagnostics.warning(
+                        "invalid_field",
+                        "headers is not a JSON object of strings and will be ignored".to_string(),
+                        Some(node_id),
+                    );
+                }
+            }
+        }
+        "terminal"
+            if text(data, "command").is_none() => {
+                diagnostics.error("missing_field", "Terminal node has no command".to_string(), Some(node_id));
+            }
+        "loop" => {
+            if let Err(e) = max_iterations(data) {
+                diagnostics.error("invalid_field", e, Some(node_id));
+            }
+            if let Err(e) = loop_condition(data) {
+                diagnostics.error("invalid_field", e, Some(node_id));
+            }
+        }
+        "delay" => match data.get("duration") {
+            None => diagnostics.error("missing_field", "Delay node has no duration".to_string(), Some(node_id)),
+            Some(JsonValue::String(d)) if d.contains("{{") => {}
+            Some(duration) => {
+                if let Err(e) = parse_duration(duration) {
+                    diagnostics.error("invalid_field", e, Some(node_id));
+                }
+            }
+        },
+        "wait_until" => match text(data, "until") {
+            None => diagnostics.error("missing_field", "Wait node has no until timestamp".to_string(), Some(node_id)),
+            Some(until) if until.contains("{{") => {}
+            Some(until) => {
+                if let Err(e) = parse_until(until) {
+                    diagnostics.error("invalid_field", e, Some(node_id));
+                }
+            }
+        },
+        "subflow"
+            if text(data, "flow_id").is_none() => {
+                diagnostics.error("missing_field", "Subflow node has no flow_id".to_string(), Some(node_id));
+            }
+        "map" => {
+            if let Err(e) = map_concurrency(data) {
+                diagnostics.error("invalid_field", e, Some(node_id));
+            }
+        }
+        "start" if data.get("worker_name").and_then(JsonValue::as_str) == Some(CRON_TRIGGER) => {
+            if let Err(e) = check_schedule(data) {
+                diagnostics.error("invalid_field", e, Some(node_id));
+            }
+        }
+        "router" => {
+            if let Err(e) = router_routes(data) {
+                diagnostics.error("invalid_field", e, Some(node_id));
+            }
+            if let Err(e) = router_takes_all(data) {
+                diagnostics.error("invalid_field", e, Some(node_id));
+            }
+        }
+        _ => {}
+    }
+}
+
+// Approvals only leave through their approved and rejected handles
+fn check_approvals(graph: &FlowGraph, diagnostics: &mut Diagnostics) {
+    for node_id in graph.node_ids().iter().filter(|id| graph.worker_type(id) == "approval") {
+        for edge in graph.outgoing(node_id) {
+            if !edge.leaves_through(APPROVED_HANDLE) && !edge.leaves_through(REJECTED_HANDLE) {
+                diagnostics.error(
+                    "invalid_handle",
+                    format!("Edge to {} must leave the approval through its approved or rejected handle", edge.target),
+                    Some(node_id.as_str()),
+                );
+            }
+        }
+    }
+}
+
+// Edges out of a router only fire through the handle of a route or the default handle
+fn check_routers(graph: &FlowGraph, diagnostics: &mut Diagnostics) {
+    for router_id in graph.node_ids().iter().filter(|id| graph.worker_type(id) == "router") {
+        let router_id = router_id.as_str();
+        let routes = match router_routes(graph.data(router_id)) {
+            Ok(routes) => routes,
+            Err(_) => continue,
+        };
+        let handles: Vec<&str> = routes.iter().map(|r| r.handle.trim()).collect();
+
+        for edge in graph.outgoing(router_id) {
+            match edge.source_handle.as_deref() {
+                Some(handle) if handle == ROUTER_DEFAULT_HANDLE || handles.contains(&handle) => {}
+                _ => diagnostics.error(
+                    "invalid_handle",
+                    format!("Edge to {} doesn't leave through a route or the default handle", edge.target),
+                    Some(router_id),
+                ),
+            }
+        }
+        for handle in handles {
+            if !graph.outgoing(router_id).iter().any(|e| e.leaves_through(handle)) {
+                diagnostics.warning(
+                    "unused_route",
+                    format!("Route {} isn't connected to anything", handle),
+                    Some(router_id),
+                );
+            }
+        }
+    }
+}
+
+// Loops and maps only run what is wired to their body handle, and the body has to lead back to them
+fn check_loops(graph: &FlowGraph, diagnostics: &mut Diagnostics) {
+    for loop_id in graph.node_ids().iter().filter(|id| repeats_body(graph.worker_type(id))) {
+        let loop_id = loop_id.as_str();
+        let kind = graph.worker_type(loop_id);
+        let body = match graph.loop_body(loop_id) {
+            Some(body) => body,
+            None => continue,
+        };
+
+        for edge in graph.outgoing(loop_id) {
+            if !edge.leaves_through(LOOP_BODY_HANDLE) && !edge.leaves_through(LOOP_DONE_HANDLE) {
+                diagnostics.error(
+                    "invalid_handle",
+                    format!("Edge to {} must leave the {} through its body or done handle", edge.target, kind),
+                    Some(loop_id),
+                );
+            } else if edge.leaves_through(LOOP_DONE_HANDLE) && body.contains(&edge.target) {
+                diagnostics.error(
+                    "loop_overlap",
+                    format!("Node {} is both inside the {} and after it", edge.target, kind),
+                    Some(loop_id),
+                );
+            }
+        }
+
+        if body.is_empty() {
+            diagnostics.error(
+                "empty_loop",
+                format!("The {} has nothing connected to its body handle", kind),
+                Some(loop_id),
+            );
+            continue;
+        }
+
+        let back_edges: Vec<_> = graph.incoming(loop_id).iter().filter(|e| e.back).collect();
+        if back_edges.is_empty() {
+            diagnostics.error(
+                "open_loop",
+                format!("The {} body never leads back to the {} node", kind, kind),
+                Some(loop_id),
+            );
+        }
+        for edge in back_edges {
+            if graph.enclosing_loops(&edge.source).last().map(|id| id.as_str()) != Some(loop_id) {
+                diagnostics.error(
+                    "misplaced_back_edge",
+                    format!("Node {} is inside a nested loop or map and can't lead back to {}", edge.source, loop_id),
+                    Some(edge.source.as_str()),
+                );
+            }
+        }
+
+        // Nodes of the body only run when the loop starts an iteration or the map an item
+        let mut entries: Vec<&str> = body
+            .iter()
+            .flat_map(|node_id| graph.incoming(node_id))
+            .filter(|e| e.source != loop_id && !body.contains(&e.source))
+            .map(|e| e.target.as_str())
+            .collect();
+        entries.sort();
+        entries.dedup();
+        for node_id in entries {
+            diagnostics.error(
+                "loop_entry",
+                format!("Node is inside {} {} but is also reached from outside it", kind, loop_id),
+                Some(node_id),
+            );
+        }
+    }
+}
+
+// Node ids end up in JSON paths of the session context, e.g. `$.nodes."3"`
+fn is_node_id(id: &str) -> bool {
+    id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Checks a parsed `flow.toml` for problems that would make it fail or misbehave at run time.
+pub fn validate(flow: &JsonValue) -> ValidationReport {
+    let mut diagnostics = Diagnostics::default();
+
+    if text(flow.get("flow").unwrap_or(&JsonValue::Null), "id").is_none() {
+        diagnostics.error("missing_flow_id", "flow.toml has no [flow] id".to_string(), None);
+    }
+
+    let empty = Vec::new();
+    let nodes = flow.get("nodes").and_then(JsonValue::as_array).unwrap_or(&empty);
+    let edges = flow.get("edges").and_then(JsonValue::as_array).unwrap_or(&empty);
+
+    // Nodes
+    let mut node_ids = HashSet::new();
+    let mut start_nodes = Vec::new();
+    for (position, node) in nodes.iter().enumerate() {
+        let node_id = match text(node, "id") {
+            Some(id) => id,
+            None => {
+                diagnostics.error("missing_node_id", format!("Node #{} has no id", position + 1), None);
+                continue;
+            }
+        };
+        if !is_node_id(node_id) {
+            diagnostics.error(
+                "invalid_node_id",
+                format!("Node id {:?} can only have letters, digits, _ and -", node_id),
+                Some(node_id),
+            );
+        }
+        if !node_ids.insert(node_id) {
+            diagnostics.error("duplicate_node_id", format!("More than one node has id {}", node_id), Some(node_id));
+        }
+
+        let data = node.get("data").unwrap_or(&JsonValue::Null);
+        match text(data, "worker_type") {
+            None => diagnostics.error("missing_worker_type", "Node has no worker_type".to_string(), Some(node_id)),
+            Some(worker_type) if !SUPPORTED_WORKER_TYPES.contains(&worker_type) => diagnostics.error(
+                "unknown_worker_type",
+                format!("Unknown worker_type {:?}", worker_type),
+                Some(node_id),
+            ),
+            Some(worker_type) => {
+                if worker_type == "start" {
+                    start_nodes.push(node_id);
+                }
+                check_node_fields(node_id, worker_type, data, &mut diagnostics);
+            }
+        }
+    }
+
+    match start_nodes.len() {
+        0 => diagnostics.error("missing_start", "Flow has no start node".to_string(), None),
+        1 => {}
+        _ => {
+            for node_id in &start_nodes[1..] {
+                diagnostics.warning(
+                    "multiple_starts",
+                    format!("Only the first start node ({}) is used", start_nodes[0]),
+                    Some(*node_id),
+                );
+            }
+        }
+    }
+
+    // Edges
+    for (position, edge) in edges.iter().enumerate() {
+        let edge_id = text(edge, "id");
+        let label = edge_id.map(|id| id.to_string()).unwrap_or_else(|| format!("#{}", position + 1));
+        let (source, target) = match (text(edge, "source"), text(edge, "target")) {
+            (Some(source), Some(target)) => (source, target),
+            _ => {
+                diagnostics.push(
+                    Severity::Error,
+                    "incomplete_edge",
+                    format!("Edge {} is missing its source or target", label),
+                    None,
+                    edge_id,
+                );
+                continue;
+            }
+        };
+        for end in [source, target] {
+            if !node_ids.contains(end) {
+                diagnostics.push(
+                    Severity::Error,
+                    "dangling_edge",
+                    format!("Edge {} points at node {} which doesn't exist", label, end),
+                    Some(end),
+                    edge_id,
+                );
+            }
+        }
+    }
+
+    let graph = FlowGraph::new(flow);
+    if let Some(cycle) = graph.find_cycle() {
+        diagnostics.error(
+            "cycle",
+            format!("Flow has a cycle: {}. Use a loop node to repeat steps", cycle.join(" -> ")),
+            cycle.first().map(|id| id.as_str()),
+        );
+    }
+    check_loops(&graph, &mut diagnostics);
+    check_routers(&graph, &mut diagnostics);
+    check_approvals(&graph, &mut diagnostics);
+
+    // Nodes the start node can't reach never run
+    if !start_nodes.is_empty() {
+        let reached = graph.reachable_from_start();
+        let mut unreachable: Vec<&&str> = node_ids.iter().filter(|id| !reached.contains(*id)).collect();
+        unreachable.sort();
+        for node_id in unreachable {
+            diagnostics.warning(
+                "unreachable_node",
+                "Node is not connected to the start node and will never run".to_string(),
+                Some(*node_id),
+            );
+        }
+    }
+
+    let diagnostics = diagnostics.0;
+    ValidationReport {
+        valid: !diagnostics.iter().any(|d| d.severity == Severity::Error),
+        diagnostics,
+    }
+}
+
+/// Validates the flow as it is on disk right now.
+pub fn validate_flow(index: &FlowIndex, flow_id: String) -> Result<ValidationReport, String> {
+    validate_flow_on_disk(index, &flow_id)
+}
+
+pub fn validate_flow_on_disk(index: &FlowIndex, flow_id: &str) -> Result<ValidationReport, String> {
+    let content = {
+        let _guard = index.read_lock();
+        let dir = flow_dir(index, flow_id)?;
+        fs::read_to_string(dir.join("flow.toml")).map_err(|e| e.to_string())?
+    };
+    match toml::from_str::<JsonValue>(&content) {
+        Ok(flow) => Ok(validate(&flow)),
+        Err(e) => Ok(ValidationReport {
+            valid: false,
+            diagnostics: vec![Diagnostic {
+                severity: Severity::Error,
+                code: "invalid_toml".to_string(),
+                message: format!("flow.toml doesn't parse: {}", e),
+                node_id: None,
+                edge_id: None,
+            }],
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn flow(nodes: JsonValue, edges: JsonValue) -> JsonValue {
+        json!({ "flow": { "id": "f" }, "nodes": nodes, "edges": edges })
+    }
+
+    fn node(id: &str, data: JsonValue) -> JsonValue {
+        json!({ "id": id, "data": data })
+    }
+
+    fn edge(source: &str, handle: Option<&str>, target: &str) -> JsonValue {
+        json!({ "id": format!("{}-{}", source, target), "source": source, "sourceHandle": handle, "target": target })
+    }
+
+    // (severity, code, node id) of every diagnostic
+    fn codes(flow: &JsonValue) -> Vec<(Severity, String, Option<String>)> {
+        validate(flow)
+            .diagnostics
+            .into_iter()
+            .map(|d| (d.severity, d.code, d.node_id))
+            .collect()
+    }
+
+    fn has(flow: &JsonValue, severity: Severity, code: &str, node_id: Option<&str>) -> bool {
+        codes(flow).contains(&(severity, code.to_string(), node_id.map(|id| id.to_string())))
+    }
+
+    #[test]
+    fn a_simple_flow_is_valid() {
+        let flow = flow(
+            json!([
+                node("1", json!({ "worker_type": "start" })),
+                node("2", json!({ "worker_type": "rest", "url": "https://example.com", "method": "get" })),
+                node("3", json!({ "worker_type": "terminal", "command": "echo {{nodes.2.result}}" })),
+            ]),
+            json!([edge("1", None, "2"), edge("2", None, "3")]),
+        );
+        let report = validate(&flow);
+        assert!(report.valid);
+        assert!(report.diagnostics.is_empty(), "{:?}", report.diagnostics);
+    }
+
+    #[test]
+    fn node_problems() {
+        let flow = json!({
+            "flow": {},
+            "nodes": [
+                node("1", json!({ "worker_type": "start" })),
+                node("1", json!({ "worker_type": "start" })),
+                node("a\"b", json!({ "worker_type": "terminal", "command": "ls" })),
+                node("4", json!({})),
+                node("5", json!({ "worker_type": "email" })),
+                node("6", json!({ "worker_type": "rest", "url": "ftp://x" })),
+                node("7", json!({ "worker_type": "terminal" })),
+                { "data": { "worker_type": "terminal" } },
+            ],
+            "edges": [],
+        });
+        let report = validate(&flow);
+        assert!(!report.valid);
+        assert!(has(&flow, Severity::Error, "missing_flow_id", None));
+        assert!(has(&flow, Severity::Error, "duplicate_node_id", Some("1")));
+        assert!(has(&flow, Severity::Warning, "multiple_starts", Some("1")));
+        assert!(has(&flow, Severity::Error, "invalid_node_id", Some("a\"b")));
+        assert!(has(&flow, Severity::Error, "missing_worker_type", Some("4")));
+        assert!(has(&flow, Severity::Error, "unknown_worker_type", Some("5")));
+        assert!(has(&flow, Severity::Error, "invalid_field", Some("6")));
+        assert!(has(&flow, Severity::Error, "missing_field", Some("7")));
+        assert!(has(&flow, Severity::Error, "missing_node_id", None));
+        assert!(has(&flow, Severity::Warning, "unreachable_node", Some("7")));
+    }
+
+    #[test]
+    fn edge_and_graph_problems() {
+        let flow = flow(
+            json!([
+                node("2", json!({ "worker_type": "terminal", "command": "ls" })),
+                node("3", json!({ "worker_type": "terminal", "command": "ls" })),
+            ]),
+            json!([edge("2", None, "3"), edge("3", None, "2"), edge("3", None, "9"), { "id": "half", "source": "2" }]),
+        );
+        assert!(has(&flow, Severity::Error, "missing_start", None));
+        assert!(has(&flow, Severity::Error, "cycle", Some("2")));
+        assert!(has(&flow, Severity::Error, "dangling_edge", Some("9")));
+        assert!(has(&flow, Severity::Error, "incomplete_edge", None));
+    }
+
+    #[test]
+    fn loop_and_router_problems() {
+        let flow = flow(
+            json!([
+                node("1", json!({ "worker_type": "start" })),
+                node("2", json!({ "worker_type": "loop", "max_iterations": 3 })),
+                node("3", json!({ "worker_type": "terminal", "command": "ls" })),
+                node("4", json!({ "worker_type": "loop" })),
+                node("5", json!({
+                    "worker_type": "router",
+                    "routes": [
+                        { "handle": "yes", "condition": { "field": "input.ok" } },
+                        { "handle": "unused", "condition": { "field": "input.ok", "operator": "falsy" } },
+                    ],
+                })),
+                node("6", json!({ "worker_type": "terminal", "command": "ls" })),
+            ]),
+            json!([
+                edge("1", None, "2"),
+                edge("2", Some("body"), "3"),
+                edge("2", Some("done"), "4"),
+                edge("4", None, "5"),
+                edge("5", Some("yes"), "6"),
+                edge("5", Some("no"), "6"),
+            ]),
+        );
+        assert!(has(&flow, Severity::Error, "open_loop", Some("2")));
+        assert!(has(&flow, Severity::Error, "invalid_field", Some("4")));
+        assert!(has(&flow, Severity::Error, "invalid_handle", Some("4")));
+        assert!(has(&flow, Severity::Error, "empty_loop", Some("4")));
+        assert!(has(&flow, Severity::Error, "invalid_handle", Some("5")));
+        assert!(has(&flow, Severity::Warning, "unused_route", Some("5")));
+    }
+}
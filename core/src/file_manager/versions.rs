@@ -0,0 +1,415 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use sha2::{Digest, Sha256};
+use tracing::info;
+use uuid::Uuid;
+
+use crate::config::get_app_dir;
+use crate::file_manager::flow_index::{read_flow_id, FlowIndex};
+
+// Fields the editor rewrites whenever a node is dragged or clicked. Not worth a version.
+const NODE_UI_FIELDS: [&str; 6] = ["position", "positionAbsolute", "selected", "dragging", "width", "height"];
+const EDGE_UI_FIELDS: [&str; 1] = ["selected"];
+
+/// One saved version of a flow. The content lives in `objects/{hash}.toml`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlowVersion {
+    pub number: usize,
+    pub hash: String,
+    pub flow_version: String,
+    pub saved_at: String,
+    /// Nodes or edges were added, removed or rewired compared to the previous version.
+    pub structural: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NodeChange {
+    pub id: String,
+    pub before: JsonValue,
+    pub after: JsonValue,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct FlowDiff {
+    pub nodes_added: Vec<JsonValue>,
+    pub nodes_removed: Vec<JsonValue>,
+    pub nodes_changed: Vec<NodeChange>,
+    pub edges_added: Vec<JsonValue>,
+    pub edges_removed: Vec<JsonValue>,
+}
+
+// Ids and hashes come from the frontend, so they are checked before they become paths
+fn history_dir(flow_id: &str) -> anyhow::Result<PathBuf> {
+    if Uuid::parse_str(flow_id).is_err() {
+        anyhow::bail!("Invalid flow id: {}", flow_id);
+    }
+    Ok(get_app_dir()?.join("history").join(flow_id))
+}
+
+fn is_hash(hash: &str) -> bool {
+    hash.len() == 64 && hash.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+fn read_log(dir: &Path) -> Vec<FlowVersion> {
+    fs::read_to_string(dir.join("versions.json"))
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+fn write_log(dir: &Path, log: &[FlowVersion]) -> anyhow::Result<()> {
+    fs::write(dir.join("versions.json"), serde_json::to_string_pretty(log)?)?;
+    Ok(())
+}
+
+fn read_object(flow_id: &str, hash: &str) -> anyhow::Result<String> {
+    if !is_hash(hash) {
+        anyhow::bail!("Invalid version hash: {}", hash);
+    }
+    let path = history_dir(flow_id)?.join("objects").join(format!("{}.toml", hash));
+    Ok(fs::read_to_string(path)?)
+}
+
+// The flow without editor noise, so moving a node around doesn't count as a change
+fn meaningful(flow: &JsonValue) -> JsonValue {
+    let mut flow = flow.clone();
+    for (key, fields) in [("nodes", &NODE_UI_FIELDS[..]), ("edges", &EDGE_UI_FIELDS[..])] {
+        if let Some(items) = flow.get_mut(key).and_then(JsonValue::as_array_mut) {
+            for item in items.iter_mut().filter_map(JsonValue::as_object_mut) {
+                for field in fields {
+                    item.remove(*field);
+                }
+            }
+        }
+    }
+    if let Some(info) = flow.get_mut("flow").and_then(JsonValue::as_object_mut) {
+        info.remove("version");
+    }
+    flow
+}
+
+fn items_by_id(flow: &JsonValue, key: &str) -> HashMap<String, JsonValue> {
+    flow.get(key)
+        .and_then(JsonValue::as_array)
+        .map(|items| {
+            items
+                .iter()
+                .filter_map(|item| {
+                    let id = item.get("id")?.as_str()?.to_string();
+                    Some((id, item.clone()))
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// (node id, worker type) and (source, source handle, target)
+type NodeShape = (String, String);
+type EdgeShape = (String, String, String);
+
+// Node ids with their worker types, plus every edge's endpoints
+fn structure(flow: &JsonValue) -> (HashSet<NodeShape>, HashSet<EdgeShape>) {
+    let nodes = items_by_id(flow, "nodes")
+        .into_iter()
+        .map(|(id, node)| {
+            let worker_type = node
+                .get("data")
+                .and_then(|data| data.get("worker_type"))
+                .and_then(JsonValue::as_str)
+                .unwrap_or_default()
+                .to_string();
+            (id, worker_type)
+        })
+        .collect();
+    let edges = items_by_id(flow, "edges")
+        .into_values()
+        .map(|edge| {
+            let field = |key: &str| edge.get(key).and_then(JsonValue::as_str).unwrap_or_default().to_string();
+            (field("source"), field("sourceHandle"), field("target"))
+        })
+        .collect();
+    (nodes, edges)
+}
+
+pub fn diff(before: &JsonValue, after: &JsonValue) -> FlowDiff {
+    let mut diff = FlowDiff::default();
+    let before_clean = meaningful(before);
+    let after_clean = meaningful(after);
+
+    let before_nodes = items_by_id(&before_clean, "nodes");
+    let after_nodes = items_by_id(&after_clean, "nodes");
+    for (id, node) in &after_nodes {
+        match before_nodes.get(id) {
+            None => diff.nodes_added.push(node.clone()),
+            Some(old) if old != node => diff.nodes_changed.push(NodeChange {
+                id: id.clone(),
+                before: old.clone(),
+                after: node.clone(),
+            }),
+            _ => {}
+        }
+    }
+    for (id, node) in &before_nodes {
+        if !after_nodes.contains_key(id) {
+            diff.nodes_removed.push(node.clone());
+        }
+    }
+
+    let before_edges = items_by_id(&before_clean, "edges");
+    let after_edges = items_by_id(&after_clean, "edges");
+    for (id, edge) in &after_edges {
+        if !before_edges.contains_key(id) {
+            diff.edges_added.push(edge.clone());
+        }
+    }
+    for (id, edge) in &before_edges {
+        if !after_edges.contains_key(id) {
+            diff.edges_removed.push(edge.clone());
+        }
+    }
+
+    diff
+}
+
+// "0.0.9" -> "0.0.10"
+fn bump(version: &str) -> String {
+    let mut parts: Vec<String> = version.split('.').map(|s| s.to_string()).collect();
+    match parts.last().and_then(|last| last.parse::<u64>().ok()) {
+        Some(patch) => {
+            *parts.last_mut().unwrap() = (patch + 1).to_string();
+            parts.join(".")
+        }
+        None => "0.0.1".to_string(),
+    }
+}
+
+// Sets `version` in the [flow] table by editing just that line, so the user's
+// comments and formatting survive. None if the file has no [flow] table.
+fn set_flow_version(content: &str, version: &str) -> Option<String> {
+    let mut lines: Vec<String> = content.lines().map(|line| line.to_string()).collect();
+    let header = lines.iter().position(|line| line.trim() == "[flow]")?;
+    let end = lines[header + 1..]
+        .iter()
+        .position(|line| line.trim_start().starts_with('['))
+        .map(|offset| header + 1 + offset)
+        .unwrap_or(lines.len());
+    let line = format!("version = \"{}\"", version);
+    let existing = (header + 1..end).find(|&i| {
+        let trimmed = lines[i].trim_start();
+        trimmed.strip_prefix("version").is_some_and(|rest| rest.trim_start().starts_with('='))
+    });
+    match existing {
+        Some(i) => {
+            let indent = &lines[i][..lines[i].len() - lines[i].trim_start().len()];
+            // Whatever follows the old value, like a comment
+            let value = lines[i].split_once('=').map_or("", |(_, value)| value.trim_start());
+            let rest = match value.strip_prefix('"') {
+                Some(quoted) => quoted.find('"').map_or("", |end| &quoted[end + 1..]),
+                None => value.find('#').map_or("", |start| &value[start..]),
+            };
+            let rest = if rest.trim().is_empty() { String::new() } else { format!(" {}", rest.trim()) };
+            lines[i] = format!("{}{}{}", indent, line, rest);
+        }
+        None => lines.insert(header + 1, line),
+    }
+    let mut patched = lines.join("\n");
+    if content.ends_with('\n') {
+        patched.push('\n');
+    }
+    Some(patched)
+}
+
+fn hash(content: &str) -> String {
+    hex::encode(Sha256::digest(content.as_bytes()))
+}
+
+/// Records versions of the flows in one index. Clones share a lock since the
+/// watcher and commands can record at the same time.
+#[derive(Default, Clone)]
+pub struct VersionRecorder(Arc<Mutex<HashMap<PathBuf, String>>>);
+
+impl VersionRecorder {
+    /// Records the current `flow.toml` of a flow directory as a new version if it
+    /// changed meaningfully since the last one. Structural changes bump `[flow] version`
+    /// in the file before it is stored.
+    pub fn record(&self, flow_dir: &Path) -> anyhow::Result<Option<FlowVersion>> {
+        let mut written = self.0.lock().unwrap();
+        record_version(flow_dir, &mut written)
+    }
+
+    /// Records a change the watcher saw, unless it is just the version bump written while recording
+    pub fn record_change(&self, flow_dir: &Path) -> anyhow::Result<Option<FlowVersion>> {
+        let mut written = self.0.lock().unwrap();
+        if let Some(bump) = written.remove(flow_dir) {
+            if hash(&fs::read_to_string(flow_dir.join("flow.toml"))?) == bump {
+                return Ok(None);
+            }
+        }
+        record_version(flow_dir, &mut written)
+    }
+}
+
+// `written` gets the hash of what a version bump writes to flow.toml, per flow directory
+fn record_version(flow_dir: &Path, written: &mut HashMap<PathBuf, String>) -> anyhow::Result<Option<FlowVersion>> {
+    let flow_id = read_flow_id(flow_dir)
+        .ok_or_else(|| anyhow::anyhow!("No flow id found in {:?}", flow_dir))?;
+    let flow_file = flow_dir.join("flow.toml");
+    let mut content = fs::read_to_string(&flow_file)?;
+    let mut current: JsonValue = toml::from_str(&content)?;
+
+    let dir = history_dir(&flow_id)?;
+    fs::create_dir_all(dir.join("objects"))?;
+    let mut log = read_log(&dir);
+
+    let mut structural = log.is_empty();
+    if let Some(last) = log.last() {
+        let previous: JsonValue = toml::from_str(&read_object(&flow_id, &last.hash)?)?;
+        if meaningful(&previous) == meaningful(&current) {
+            return Ok(None);
+        }
+        structural = structure(&previous) != structure(&current);
+
+        // Restoring an old version brings its old version string back too, so bump from the latest
+        let current_version = current["flow"]["version"].as_str().unwrap_or_default().to_string();
+        if structural && log.iter().any(|v| v.flow_version == current_version) {
+            let bumped = bump(&last.flow_version);
+            if let Some(patched) = set_flow_version(&content, &bumped) {
+                // The watcher sees this write too and skips it by its hash
+                fs::write(&flow_file, &patched)?;
+                written.insert(flow_dir.to_path_buf(), hash(&patched));
+                content = patched;
+                current = toml::from_str(&content)?;
+                info!("Bumped flow {} to version {}", flow_id, bumped);
+            }
+        }
+    }
+
+    let hash = hash(&content);
+    fs::write(dir.join("objects").join(format!("{}.toml", hash)), &content)?;
+
+    let version = FlowVersion {
+        number: log.len() + 1,
+        hash,
+        flow_version: current["flow"]["version"].as_str().unwrap_or_default().to_string(),
+        saved_at: Utc::now().to_rfc3339(),
+        structural,
+    };
+    log.push(version.clone());
+    write_log(&dir, &log)?;
+
+    Ok(Some(version))
+}
+
+fn flow_dir(index: &FlowIndex, flow_id: &str) -> Result<PathBuf, String> {
+    index
+        .get(flow_id)
+        .ok_or_else(|| format!("Flow {} not found in the flows directory", flow_id))
+}
+
+fn parse_version(flow_id: &str, hash: &str) -> Result<JsonValue, String> {
+    let content = read_object(flow_id, hash).map_err(|e| format!("Version {} not found: {}", hash, e))?;
+    toml::from_str(&content).map_err(|e| e.to_string())
+}
+
+pub fn list_flow_versions(flow_id: String) -> Result<Vec<FlowVersion>, String> {
+    let dir = history_dir(&flow_id).map_err(|e| e.to_string())?;
+    Ok(read_log(&dir))
+}
+
+pub fn get_flow_version(flow_id: String, hash: String) -> Result<JsonValue, String> {
+    parse_version(&flow_id, &hash)
+}
+
+pub fn diff_flow_versions(flow_id: String, from_hash: String, to_hash: String) -> Result<FlowDiff, String> {
+    let before = parse_version(&flow_id, &from_hash)?;
+    let after = parse_version(&flow_id, &to_hash)?;
+    Ok(diff(&before, &after))
+}
+
+/// Writes an old version back to `flow.toml`. It is recorded as a new version,
+/// so restoring is itself part of the history.
+pub fn restore_flow_version(
+    index: &FlowIndex,
+    flow_id: String,
+    hash: String,
+) -> Result<Option<FlowVersion>, String> {
+    let _guard = index.read_lock();
+    let dir = flow_dir(index, &flow_id)?;
+    let content = read_object(&flow_id, &hash).map_err(|e| format!("Version {} not found: {}", hash, e))?;
+    fs::write(dir.join("flow.toml"), content).map_err(|e| e.to_string())?;
+    index.versions().record(&dir).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn bump_increments_the_last_number() {
+        assert_eq!(bump("0.0.9"), "0.0.10");
+        assert_eq!(bump("1.2"), "1.3");
+        assert_eq!(bump("7"), "8");
+        assert_eq!(bump("1.0.beta"), "0.0.1");
+        assert_eq!(bump(""), "0.0.1");
+    }
+
+    #[test]
+    fn diff_ignores_editor_fields_and_the_version() {
+        let before = json!({
+            "flow": { "id": "f", "version": "0.0.1" },
+            "nodes": [
+                { "id": "1", "data": { "worker_type": "start" }, "position": { "x": 0, "y": 0 } },
+                { "id": "2", "data": { "worker_type": "rest", "url": "https://a" } },
+                { "id": "3", "data": { "worker_type": "terminal" } },
+            ],
+            "edges": [{ "id": "e1", "source": "1", "target": "2" }, { "id": "e2", "source": "2", "target": "3" }],
+        });
+        let after = json!({
+            "flow": { "id": "f", "version": "0.0.2" },
+            "nodes": [
+                { "id": "1", "data": { "worker_type": "start" }, "position": { "x": 50, "y": 10 }, "selected": true },
+                { "id": "2", "data": { "worker_type": "rest", "url": "https://b" } },
+                { "id": "4", "data": { "worker_type": "terminal" } },
+            ],
+            "edges": [{ "id": "e1", "source": "1", "target": "2", "selected": true }, { "id": "e3", "source": "2", "target": "4" }],
+        });
+
+        let changes = diff(&before, &after);
+        let ids = |items: &[JsonValue]| items.iter().map(|item| item["id"].as_str().unwrap().to_string()).collect::<Vec<_>>();
+        assert_eq!(ids(&changes.nodes_added), ["4"]);
+        assert_eq!(ids(&changes.nodes_removed), ["3"]);
+        assert_eq!(changes.nodes_changed.iter().map(|c| c.id.as_str()).collect::<Vec<_>>(), ["2"]);
+        assert_eq!(ids(&changes.edges_added), ["e3"]);
+        assert_eq!(ids(&changes.edges_removed), ["e2"]);
+
+        let moved = diff(&before, &json!({ "flow": before["flow"], "nodes": before["nodes"], "edges": before["edges"] }));
+        assert!(moved.nodes_added.is_empty() && moved.nodes_changed.is_empty() && moved.edges_added.is_empty());
+    }
+
+    #[test]
+    fn set_flow_version_only_touches_the_version_line() {
+        let content = "# my flow\n[flow]\n  version = \"0.0.9\" # keep\nid = \"x\"\n\n[[nodes]]\nversion = \"node\"\n";
+        assert_eq!(
+            set_flow_version(content, "0.0.10").unwrap(),
+            "# my flow\n[flow]\n  version = \"0.0.10\" # keep\nid = \"x\"\n\n[[nodes]]\nversion = \"node\"\n"
+        );
+        assert_eq!(set_flow_version("[flow]\nversion = 3 # odd\n", "0.0.1").unwrap(), "[flow]\nversion = \"0.0.1\" # odd\n");
+        assert_eq!(set_flow_version("[flow]\nid = \"x\"", "0.0.1").unwrap(), "[flow]\nversion = \"0.0.1\"\nid = \"x\"");
+        assert_eq!(set_flow_version("[[nodes]]\nid = \"1\"\n", "0.0.1"), None);
+    }
+
+    #[test]
+    fn only_uuids_and_sha256_hashes_make_paths() {
+        assert!(history_dir("../../etc").is_err());
+        assert!(is_hash(&hash("flow")));
+        assert!(!is_hash("../objects"));
+        assert!(!is_hash(&hash("flow").to_uppercase()));
+    }
+}
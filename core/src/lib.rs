@@ -0,0 +1,11 @@
+// The flow engine and everything it runs on, with no UI. The Tauri app and the CLI
+// are adapters over it.
+
+pub mod config;
+pub mod db;
+pub mod engine;
+pub mod events;
+pub mod file_manager;
+pub mod local_models;
+pub mod notifications;
+pub mod sql;
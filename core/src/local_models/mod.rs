@@ -3,17 +3,10 @@
 //Make models available to any node that wants to use them
 //There is no idea of a "selected model" because we hop back and forth alot
 
-#[cfg_attr(
-    all(not(test), any(target_os = "windows", target_os = "linux")),
-    feature(cublas)
-)]
-
-use tauri::Window;
-
 extern crate llm;
-use crate::ManagerState;
 
 use llm::{InferenceResponse, LoadProgress};
+use tracing::{debug, info};
 
 use crate::config;
 
@@ -22,34 +15,31 @@ pub mod models;
 pub mod cancellation;
 use cancellation::Canceller;
 
-use crate::notifications::Event; 
+use crate::notifications::{Event, EventSink}; 
 
 use prompt::Template; 
 use models::{get_local_model, Architecture, Model, ModelManager};
 use bytesize::ByteSize;
 use serde::Serialize;
-use tracing::info;
+use std::sync::Mutex;
 
+/// The model loaded with `start`, for `prompt`
+pub struct ManagerState(pub Mutex<Option<ModelManager>>);
 
-
-#[tauri::command]
 pub fn get_prompt_templates() -> Vec<Template> {
     prompt::AVAILABLE_TEMPLATES.clone()
 }
 
-#[tauri::command]
 pub fn get_architectures() -> Vec<Architecture> {
     models::AVAILABLE_ARCHITECTURES.clone()
 }
 
-#[tauri::command]
 pub async fn get_models() -> Result<Vec<Model>, String> {
     models::get_available_models()
         .await
         .map_err(|err| err.to_string())
 }
 
-#[tauri::command]
 pub async fn get_downloaded_models() -> Result<Vec<Model>, String> {
     models::get_downloaded_models()
         .await
@@ -58,11 +48,11 @@ pub async fn get_downloaded_models() -> Result<Vec<Model>, String> {
 
 //TODO: abstract this tauri command into code that can be called from rust event processing system
 
-#[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub async fn start(
-    window: Window,
-    state: tauri::State<'_, ManagerState>,
-    canceller: tauri::State<'_, Canceller>,
+    sink: &dyn EventSink,
+    state: &ManagerState,
+    canceller: &Canceller,
     model_filename: String,
     architecture: String,
     tokenizer: String,
@@ -95,7 +85,7 @@ pub async fn start(
             ByteSize(downloaded),
             ByteSize(total)
         );
-        Event::ModelLoading { message, progress }.send(&window);
+        sink.send(&Event::ModelLoading { message, progress });
     })
     .await
     .map_err(|err| err.to_string())?;
@@ -128,21 +118,18 @@ pub async fn start(
         tokenizer,
         params,
         |progress| match progress {
-            LoadProgress::HyperparametersLoaded => Event::ModelLoading {
+            LoadProgress::HyperparametersLoaded => sink.send(&Event::ModelLoading {
                 message: "Hyper-parameters loaded".to_string(),
                 progress: 0.05,
-            }
-            .send(&window),
-            LoadProgress::ContextSize { .. } => Event::ModelLoading {
+            }),
+            LoadProgress::ContextSize { .. } => sink.send(&Event::ModelLoading {
                 message: "Context created".to_string(),
                 progress: 0.1,
-            }
-            .send(&window),
-            LoadProgress::LoraApplied { .. } => Event::ModelLoading {
+            }),
+            LoadProgress::LoraApplied { .. } => sink.send(&Event::ModelLoading {
                 message: "LoRA applied".to_string(),
                 progress: 0.15,
-            }
-            .send(&window),
+            }),
             LoadProgress::TensorLoaded {
                 current_tensor,
                 tensor_count,
@@ -153,17 +140,15 @@ pub async fn start(
                 let end = 0.5;
                 let progress =
                     start + (end - start) * (current_tensor as f32 / tensor_count as f32);
-                Event::ModelLoading {
+                sink.send(&Event::ModelLoading {
                     message: format!("Loading tensor {}/{}", current_tensor, tensor_count),
                     progress,
-                }
-                .send(&window)
+                })
             }
-            LoadProgress::Loaded { .. } => Event::ModelLoading {
+            LoadProgress::Loaded { .. } => sink.send(&Event::ModelLoading {
                 message: "Model loaded".to_string(),
                 progress: 0.6,
-            }
-            .send(&window),
+            }),
         },
     )
     .map_err(|e| format!("Error loading model: {}", e))?;
@@ -182,22 +167,20 @@ pub async fn start(
                 InferenceResponse::PromptToken(t) => {
                     progress_length += t.len();
                     let progress = progress_length as f32 / warmup_prompt.len() as f32;
-                    Event::ModelLoading {
+                    sink.send(&Event::ModelLoading {
                         message: format!("Warming up model ({:.0}%)", progress * 100.0),
                         progress,
-                    }
-                    .send(&window);
+                    });
                     canceller.inference_feedback()
                 }
                 _ => canceller.inference_feedback(),
             }),
         )
         .map_err(|e| format!("Error feeding prompt: {}", e))?;
-    Event::ModelLoading {
+    sink.send(&Event::ModelLoading {
         message: "Model loaded".to_string(),
         progress: 1.0,
-    }
-    .send(&window);
+    });
 
     if canceller.is_cancelled() {
         return Ok(false);
@@ -219,12 +202,11 @@ pub struct PromptResponse {
     pub message: String,
 }
 
-#[tracing::instrument(skip(window, state, canceller, message))]
-#[tauri::command]
+#[tracing::instrument(skip(sink, state, canceller, message))]
 pub async fn prompt(
-    window: Window,
-    state: tauri::State<'_, ManagerState>,
-    canceller: tauri::State<'_, Canceller>,
+    sink: &dyn EventSink,
+    state: &ManagerState,
+    canceller: &Canceller,
     message: String,
 ) -> Result<PromptResponse, String> {
     info!("received prompt");
@@ -239,17 +221,16 @@ pub async fn prompt(
     let stats = manager.infer(&message, |res| match res {
         InferenceResponse::InferredToken(tokens) => {
             response.push_str(&tokens);
-            Event::PromptResponse { message: tokens }.send(&window);
+            sink.send(&Event::PromptResponse { message: tokens });
             canceller.inference_feedback()
         }
         _ => canceller.inference_feedback(),
     })?;
 
     info!("finished prompt response");
-    Event::PromptResponse {
+    sink.send(&Event::PromptResponse {
         message: Default::default(),
-    }
-    .send(&window);
+    });
 
     Ok(PromptResponse {
         stats,
@@ -388,7 +369,6 @@ pub async fn prompt(
 //     Ok(())
 // }
 
-#[tauri::command]
 pub async fn download_model(  
     filename: &str
 ) -> Result<(), String> { 
@@ -398,7 +378,7 @@ pub async fn download_model(
             ByteSize(downloaded),
             ByteSize(total)
         );
-        println!("{}", message); 
+        debug!("{}", message); 
         // Event::ModelLoading { message, progress }.send(&window);
     })
     .await
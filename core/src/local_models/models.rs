@@ -76,7 +76,7 @@ pub async fn get_available_models() -> Result<Vec<Model>> {
         })
         .collect::<Vec<_>>();
     models.append(&mut known_models);
-    models.sort_by(|a, b| b.custom.cmp(&a.custom));
+    models.sort_by_key(|model| std::cmp::Reverse(model.custom));
     Ok(models)
 }
 
@@ -107,7 +107,7 @@ pub async fn get_downloaded_models() -> Result<Vec<Model>> {
 #[serde(rename_all = "camelCase")]
 pub struct Model {
     name: String,
-    url: String,
+    pub url: String,
     #[serde(default)]
     pub custom: bool,
     #[serde(default)]
@@ -160,7 +160,7 @@ impl ModelManager {
                 &mut rand::thread_rng(),
                 &llm::InferenceRequest {
                     prompt: self.template.process(prompt).as_str().into(),
-                    parameters: &llm::InferenceParameters::default(),
+                    parameters: &llm::InferenceParameters,
                     play_back_previous_tokens: false,
                     maximum_token_count: None,
                 },
@@ -227,7 +227,7 @@ mod tests {
     #[test]
     fn test_model_existence() {
         for model in AVAILABLE_MODELS.iter() {
-            println!("Testing {} ({})", model.name, model.url);
+            tracing::info!("Testing {} ({})", model.name, model.url);
             let response = reqwest::blocking::get(&model.url).expect("Failed to get model");
             assert!(
                 response.status().is_success(),
@@ -0,0 +1,42 @@
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use std::collections::HashMap;
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "untagged")]
+pub enum Event {
+    ModelLoading { message: String, progress: f32 },
+    PromptResponse { message: String },
+    EventProcessing { message: String, event_id: String, node_id: String, flow_id: String, session_id: String },
+    SessionComplete {  event_id: String, node_id: String, flow_id: String, session_id: String },
+    EventFailed { message: String, event_id: String, node_id: String, flow_id: String, session_id: String },
+    SessionCancelled { message: String, flow_id: String, session_id: String },
+    SessionPaused { message: String, flow_id: String, session_id: String },
+    SessionResumed { message: String, flow_id: String, session_id: String },
+    EventWaiting { message: String, resume_at: Option<String>, event_id: String, node_id: String, flow_id: String, session_id: String },
+    EventsRecovered { message: String, session_ids: Vec<String> },
+    SelectBatch { stream_id: String, rows: Vec<HashMap<String, JsonValue>>, done: bool }
+}
+
+impl Event {
+    pub fn name(&self) -> &str {
+        match self {
+            Event::ModelLoading { .. } => "model_loading",
+            Event::PromptResponse { .. } => "prompt_response",
+            Event::EventProcessing { .. } => "event_processing",
+            Event::SessionComplete { .. } => "session_complete",
+            Event::EventFailed { .. } => "event_failed",
+            Event::EventWaiting { .. } => "event_waiting",
+            Event::SessionCancelled { .. } => "session_cancelled",
+            Event::SessionPaused { .. } => "session_paused",
+            Event::SessionResumed { .. } => "session_resumed",
+            Event::EventsRecovered { .. } => "events_recovered",
+            Event::SelectBatch { .. } => "select_batch"
+        }
+    }
+}
+
+/// Where the engine sends its events: the app's window, or the CLI's output
+pub trait EventSink: Send + Sync {
+    fn send(&self, event: &Event);
+}
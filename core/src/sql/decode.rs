@@ -0,0 +1,157 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde_json::Value as JsonValue;
+use sqlx::{sqlite::SqliteValueRef, TypeInfo, Value, ValueRef};
+use time::{Date, PrimitiveDateTime, Time};
+// from https://github.com/tauri-apps/plugins-workspace/blob/v1/plugins/sql/src/decode/sqlite.rs
+// use crate::Error;
+use crate::sql::Error;
+
+/// How to treat values that don't map cleanly onto JSON.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DecodeOptions {
+    /// Parse TEXT values that hold a JSON object or array (like `event_result`)
+    /// into nested values instead of returning them as escaped strings.
+    pub parse_json: bool,
+}
+
+pub fn to_json(v: SqliteValueRef) -> Result<JsonValue, Error> {
+    to_json_with(v, "", DecodeOptions::default())
+}
+
+/// Decodes by the column's declared type first (for the types SQLite only has by
+/// convention, like DATETIME and BOOLEAN) and falls back to the value's storage class.
+/// JSON is only ever stored as TEXT, so it's recognised by its content with `parse_json`.
+pub fn to_json_with(
+    v: SqliteValueRef,
+    declared_type: &str,
+    options: DecodeOptions,
+) -> Result<JsonValue, Error> {
+    if v.is_null() {
+        return Ok(JsonValue::Null);
+    }
+
+    let declared = match declared_type.to_ascii_uppercase().as_str() {
+        "BOOLEAN" | "BOOL" => v.to_owned().try_decode::<bool>().ok().map(JsonValue::Bool),
+        "DATE" => v
+            .to_owned()
+            .try_decode::<Date>()
+            .ok()
+            .map(|v| JsonValue::String(v.to_string())),
+        "TIME" => v
+            .to_owned()
+            .try_decode::<Time>()
+            .ok()
+            .map(|v| JsonValue::String(v.to_string())),
+        "DATETIME" | "TIMESTAMP" => v
+            .to_owned()
+            .try_decode::<PrimitiveDateTime>()
+            .ok()
+            .map(|v| JsonValue::String(v.to_string())),
+        _ => None,
+    };
+
+    if let Some(value) = declared {
+        return Ok(value);
+    }
+
+    // Whatever the column claims to be, the value itself is always one of SQLite's storage classes
+    let res = match v.type_info().name() {
+        "TEXT" => {
+            if let Ok(v) = v.to_owned().try_decode::<String>() {
+                if options.parse_json {
+                    parse_json_text(v)
+                } else {
+                    JsonValue::String(v)
+                }
+            } else {
+                JsonValue::Null
+            }
+        }
+        "REAL" => {
+            if let Ok(v) = v.to_owned().try_decode::<f64>() {
+                JsonValue::from(v)
+            } else {
+                JsonValue::Null
+            }
+        }
+        "INTEGER" | "NUMERIC" => {
+            if let Ok(v) = v.to_owned().try_decode::<i64>() {
+                JsonValue::Number(v.into())
+            } else {
+                JsonValue::Null
+            }
+        }
+        "BLOB" => {
+            if let Ok(v) = v.to_owned().try_decode::<Vec<u8>>() {
+                JsonValue::String(BASE64.encode(v))
+            } else {
+                JsonValue::Null
+            }
+        }
+        "NULL" => JsonValue::Null,
+        _ => return Err(Error::UnsupportedDatatype(v.type_info().name().to_string())),
+    };
+
+    Ok(res)
+}
+
+// Only objects and arrays are worth parsing. A TEXT of "42" should stay a string.
+fn parse_json_text(text: String) -> JsonValue {
+    let trimmed = text.trim_start();
+    if trimmed.starts_with('{') || trimmed.starts_with('[') {
+        if let Ok(parsed) = serde_json::from_str(&text) {
+            return parsed;
+        }
+    }
+    JsonValue::String(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::{Column, Row, SqlitePool};
+
+    async fn decode_row(query: &str, options: DecodeOptions) -> Vec<JsonValue> {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        let row = sqlx::query(query).fetch_one(&pool).await.unwrap();
+        row.columns()
+            .iter()
+            .enumerate()
+            .map(|(i, column)| to_json_with(row.try_get_raw(i).unwrap(), column.type_info().name(), options).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn json_text_is_parsed_only_when_asked() {
+        let query = r#"SELECT '{"a": [1, 2]}', '[true]', '42', 'not {json'"#;
+        let parsed = decode_row(query, DecodeOptions { parse_json: true }).await;
+        assert_eq!(parsed, vec![
+            serde_json::json!({ "a": [1, 2] }),
+            serde_json::json!([true]),
+            JsonValue::String("42".to_string()),
+            JsonValue::String("not {json".to_string()),
+        ]);
+
+        let raw = decode_row(query, DecodeOptions::default()).await;
+        assert_eq!(raw[0], JsonValue::String(r#"{"a": [1, 2]}"#.to_string()));
+    }
+
+    #[tokio::test]
+    async fn json_columns_are_decoded_by_their_content() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::query("CREATE TABLE t (data JSON, raw BLOB)").execute(&pool).await.unwrap();
+        sqlx::query(r#"INSERT INTO t VALUES ('{"ok": true}', x'00ff')"#).execute(&pool).await.unwrap();
+        let row = sqlx::query("SELECT data, raw FROM t").fetch_one(&pool).await.unwrap();
+        let decode = |i: usize, options| to_json_with(row.try_get_raw(i).unwrap(), row.columns()[i].type_info().name(), options).unwrap();
+
+        assert_eq!(decode(0, DecodeOptions { parse_json: true }), serde_json::json!({ "ok": true }));
+        assert_eq!(decode(0, DecodeOptions::default()), JsonValue::String(r#"{"ok": true}"#.to_string()));
+        assert_eq!(decode(1, DecodeOptions { parse_json: true }), JsonValue::String("AP8=".to_string()));
+    }
+
+    #[tokio::test]
+    async fn numbers_and_nulls_keep_their_storage_class() {
+        let values = decode_row("SELECT 7, 1.5, NULL", DecodeOptions { parse_json: true }).await;
+        assert_eq!(values, vec![JsonValue::from(7), JsonValue::from(1.5), JsonValue::Null]);
+    }
+}
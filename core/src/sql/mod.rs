@@ -0,0 +1,70 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use futures_core::future::BoxFuture;
+use serde::{ser::Serializer, Serialize};
+use serde_json::Value as JsonValue;
+
+pub mod decode;
+pub mod sqlite;
+
+pub type LastInsertId = i64;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Sql(#[from] sqlx::Error),
+    #[error(transparent)]
+    Migration(#[from] sqlx::migrate::MigrateError),
+    #[error("database {0} not loaded")]
+    DatabaseNotLoaded(String),
+    #[error("unsupported datatype: {0}")]
+    UnsupportedDatatype(String),
+    #[error("invalid page: {0}")]
+    InvalidPage(String),
+}
+
+impl Serialize for Error {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.to_string().as_ref())
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Where the engine keeps its events and sessions. `db` names a stage database, like
+/// `sqlite:dev.sqlite`, and queries bind `values` to `$1`, `$2`, ...
+/// `sqlite::DbInstances` is the one the app and the CLI use.
+pub trait Storage: Send + Sync {
+    /// Opens `db` and brings its schema up to date. Does nothing if it's already open.
+    fn connect<'a>(&'a self, db: &'a str) -> BoxFuture<'a, Result<()>>;
+
+    /// Closes `db`, e.g. before its file is replaced.
+    fn disconnect<'a>(&'a self, db: &'a str) -> BoxFuture<'a, ()>;
+
+    /// Replaces `db` with a copy of the database file at `source`, e.g. a backup. Nothing
+    /// else can use `db` until it's reopened.
+    fn replace<'a>(&'a self, db: &'a str, source: &'a Path) -> BoxFuture<'a, Result<()>>;
+
+    /// Every database that is open.
+    fn loaded(&self) -> BoxFuture<'_, Vec<String>>;
+
+    /// Runs a statement, returning the rows it changed and the last inserted row id.
+    fn execute(&self, db: String, query: String, values: Vec<JsonValue>) -> BoxFuture<'_, Result<(u64, LastInsertId)>>;
+
+    /// Runs statements, each a query and its values, in one transaction. If one fails none apply.
+    fn execute_all(&self, db: String, statements: Vec<(String, Vec<JsonValue>)>) -> BoxFuture<'_, Result<()>>;
+
+    /// Runs a query. With `parse_json` TEXT values holding a JSON object or array come
+    /// back parsed.
+    fn select(
+        &self,
+        db: String,
+        query: String,
+        values: Vec<JsonValue>,
+        parse_json: Option<bool>,
+    ) -> BoxFuture<'_, Result<Vec<HashMap<String, JsonValue>>>>;
+}
@@ -0,0 +1,579 @@
+// Inspiration
+//sqlite tauri plugin
+//https://github.com/lzdyes/tauri-plugin-sqlite/blob/main/src/lib.rs
+//tauri plugin sql official
+//https://github.com/tauri-apps/plugins-workspace/blob/v1/plugins/sql/src/plugin.rs
+//sql implementation w/ diesel w/orion
+//https://github.com/taecontrol/orion/blob/main/src-tauri/src/db.rs
+
+// Copyright 2021 Tauri Programme within The Commons Conservancy
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+use dirs::document_dir;
+use futures_core::future::BoxFuture;
+use futures_util::TryStreamExt;
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use sqlx::{
+    error::BoxDynError,
+    migrate::{
+        MigrateDatabase, Migration as SqlxMigration, MigrationSource, MigrationType, Migrator,
+    },
+    query::Query,
+    sqlite::{SqliteArguments, SqliteRow},
+    Column, Pool, Row, TypeInfo,
+};
+use tokio::sync::{Mutex, OwnedRwLockReadGuard, RwLock};
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use std::{fs::create_dir_all, path::{Path, PathBuf}};
+
+use crate::sql::decode::{self, DecodeOptions};
+use crate::sql::{Error, LastInsertId, Result, Storage};
+
+type Db = sqlx::sqlite::Sqlite;
+type SqliteQuery<'q> = Query<'q, Db, SqliteArguments<'q>>;
+
+/// Where the databases of the app named `package_name` live. The CLI opens the same files.
+pub fn db_dir(package_name: &str) -> PathBuf {
+    document_dir()
+    .expect("No document directory was found!")
+    .join(package_name)
+    .join("db")
+}
+
+/// Maps the user supplied DB connection string to a connection string
+/// with a fully qualified file path to the App's designed "app_path"
+fn path_mapper(mut app_path: PathBuf, connection_string: &str) -> String {
+    app_path.push(
+        connection_string
+            .split_once(':')
+            .expect("Couldn't parse the connection string for DB!")
+            .1,
+    );
+
+    format!(
+        "sqlite:{}",
+        app_path
+            .to_str()
+            .expect("Problem creating fully qualified path to Database file!")
+    )
+}
+
+/// Open pools by database, plus what's needed to open more. Clones share the same pools,
+/// so the app's commands and the engine see the same databases.
+#[derive(Clone)]
+pub struct DbInstances {
+    pools: Arc<Mutex<HashMap<String, Pool<Db>>>>,
+    // Queries hold a read lock on their database so `replace` can wait them out
+    locks: Arc<Mutex<HashMap<String, Arc<RwLock<()>>>>>,
+    migrations: Arc<Migrations>,
+    dir: PathBuf,
+}
+
+impl DbInstances {
+    /// No databases are open yet, `connect` opens them from `dir` and migrates them
+    /// with `migrations`.
+    pub fn new(dir: PathBuf, migrations: Vec<Migration>) -> DbInstances {
+        DbInstances::with_migrations(dir, HashMap::new(), Some(migrations))
+    }
+
+    /// Like `new`, with migrations for particular databases. `default` is for the rest.
+    pub fn with_migrations(dir: PathBuf, by_db: HashMap<String, Vec<Migration>>, default: Option<Vec<Migration>>) -> DbInstances {
+        DbInstances {
+            pools: Default::default(),
+            locks: Default::default(),
+            migrations: Arc::new(Migrations {
+                by_db: Mutex::new(by_db.into_iter().map(|(db, list)| (db, MigrationList(list))).collect()),
+                default: default.map(MigrationList),
+            }),
+            dir,
+        }
+    }
+
+    /// Where the database files are, the stage databases among them.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    async fn lock(&self, db: &str) -> Arc<RwLock<()>> {
+        self.locks.lock().await.entry(db.to_string()).or_default().clone()
+    }
+
+    /// Closes every pool, e.g. when the app exits.
+    pub async fn close_all(&self) {
+        for pool in self.pools.lock().await.values() {
+            pool.close().await;
+        }
+    }
+}
+
+/// Migrations keyed by database, plus a default set applied to any
+/// database (like a new stage) that has none registered.
+struct Migrations {
+    by_db: Mutex<HashMap<String, MigrationList>>,
+    default: Option<MigrationList>,
+}
+
+#[derive(Debug, Clone)]
+pub enum MigrationKind {
+    Up,
+    Down,
+}
+
+impl From<MigrationKind> for MigrationType {
+    fn from(kind: MigrationKind) -> Self {
+        match kind {
+            MigrationKind::Up => Self::ReversibleUp,
+            MigrationKind::Down => Self::ReversibleDown,
+        }
+    }
+}
+
+/// A migration definition.
+#[derive(Debug, Clone)]
+pub struct Migration {
+    pub version: i64,
+    pub description: &'static str,
+    pub sql: &'static str,
+    pub kind: MigrationKind,
+}
+
+#[derive(Debug, Clone)]
+struct MigrationList(Vec<Migration>);
+
+impl MigrationSource<'static> for MigrationList {
+    fn resolve(self) -> BoxFuture<'static, std::result::Result<Vec<SqlxMigration>, BoxDynError>> {
+        Box::pin(async move {
+            let mut migrations = Vec::new();
+            for migration in self.0 {
+                if matches!(migration.kind, MigrationKind::Up) {
+                    migrations.push(SqlxMigration::new(
+                        migration.version,
+                        migration.description.into(),
+                        migration.kind.into(),
+                        migration.sql.into(),
+                    ));
+                }
+            }
+            Ok(migrations)
+        })
+    }
+}
+
+/// Opens a pool for `db` (creating the file if needed) and runs its migrations.
+/// Does nothing if the database is already loaded.
+pub async fn connect(db_instances: &DbInstances, db: &str) -> Result<()> {
+    let _guard = db_instances.lock(db).await.read_owned().await;
+    open(db_instances, db).await
+}
+
+async fn open(db_instances: &DbInstances, db: &str) -> Result<()> {
+    if db_instances.pools.lock().await.contains_key(db) {
+        return Ok(());
+    }
+
+    let fqdb = path_mapper(db_instances.dir.clone(), db);
+
+    create_dir_all(&db_instances.dir).expect("Problem creating App directory!");
+
+    if !Db::database_exists(&fqdb).await.unwrap_or(false) {
+        Db::create_database(&fqdb).await?;
+    }
+    let pool = Pool::connect(&fqdb).await?;
+
+    let migrations = &db_instances.migrations;
+    let list = migrations
+        .by_db
+        .lock()
+        .await
+        .get(db)
+        .cloned()
+        .or_else(|| migrations.default.clone());
+    if let Some(list) = list {
+        let migrator = Migrator::new(list).await?;
+        migrator.run(&pool).await?;
+    }
+
+    db_instances.pools.lock().await.insert(db.to_string(), pool);
+    Ok(())
+}
+
+/// Closes and forgets the pool for `db`.
+pub async fn disconnect(db_instances: &DbInstances, db: &str) {
+    let _guard = db_instances.lock(db).await.read_owned().await;
+    forget(db_instances, db).await;
+}
+
+async fn forget(db_instances: &DbInstances, db: &str) {
+    let pool = db_instances.pools.lock().await.remove(db);
+    if let Some(pool) = pool {
+        pool.close().await;
+    }
+}
+
+/// Allows the database connection(s) to be closed; if no database
+/// name is passed in then _all_ database connection pools will be
+/// shut down.
+pub async fn close(db_instances: &DbInstances, db: Option<String>) -> Result<bool> {
+    let mut instances = db_instances.pools.lock().await;
+
+    let pools = if let Some(db) = db {
+        vec![db]
+    } else {
+        instances.keys().cloned().collect()
+    };
+
+    for pool in pools {
+        let db = instances
+            .get_mut(&pool) //
+            .ok_or(Error::DatabaseNotLoaded(pool))?;
+        db.close().await;
+    }
+
+    Ok(true)
+}
+
+/// Swaps the file behind `db` for a copy of `source` and reopens it, which migrates it.
+/// Waits for running queries on `db` and holds new ones until it's done.
+pub async fn replace(db_instances: &DbInstances, db: &str, source: &Path) -> Result<()> {
+    let _guard = db_instances.lock(db).await.write_owned().await;
+    forget(db_instances, db).await;
+
+    let file = db
+        .split_once(':')
+        .map(|(_, file)| file)
+        .ok_or_else(|| Error::DatabaseNotLoaded(db.to_string()))?;
+    let target = db_instances.dir.join(file);
+    for suffix in ["-wal", "-shm"] {
+        let sidecar = db_instances.dir.join(format!("{}{}", file, suffix));
+        if sidecar.exists() {
+            std::fs::remove_file(sidecar).map_err(sqlx::Error::Io)?;
+        }
+    }
+    std::fs::copy(source, target).map_err(sqlx::Error::Io)?;
+
+    open(db_instances, db).await
+}
+
+/// Clones the pool for `db` so long running queries don't hold the instances lock.
+/// The guard keeps `replace` from swapping the file while it's in use.
+async fn get_pool(db_instances: &DbInstances, db: &str) -> Result<(Pool<Db>, OwnedRwLockReadGuard<()>)> {
+    let guard = db_instances.lock(db).await.read_owned().await;
+    let pool = db_instances
+        .pools
+        .lock()
+        .await
+        .get(db)
+        .cloned()
+        .ok_or_else(|| Error::DatabaseNotLoaded(db.to_string()))?;
+    Ok((pool, guard))
+}
+
+/// Binds JSON values using the closest SQLite type. Arrays and objects are stored as JSON text.
+fn bind_values<'q>(mut query: SqliteQuery<'q>, values: Vec<JsonValue>) -> SqliteQuery<'q> {
+    for value in values {
+        query = match value {
+            JsonValue::Null => query.bind(None::<String>),
+            JsonValue::Bool(b) => query.bind(b),
+            JsonValue::Number(n) => {
+                if let Some(i) = n.as_i64() {
+                    query.bind(i)
+                } else {
+                    query.bind(n.as_f64())
+                }
+            }
+            JsonValue::String(s) => query.bind(s),
+            value @ (JsonValue::Array(_) | JsonValue::Object(_)) => query.bind(value.to_string()),
+        };
+    }
+    query
+}
+
+fn row_to_json(row: &SqliteRow, options: DecodeOptions) -> Result<HashMap<String, JsonValue>> {
+    let mut value = HashMap::default();
+    for (i, column) in row.columns().iter().enumerate() {
+        let v = row.try_get_raw(i)?;
+
+        let v = decode::to_json_with(v, column.type_info().name(), options)?;
+
+        value.insert(column.name().to_string(), v);
+    }
+    Ok(value)
+}
+
+fn decode_options(parse_json: Option<bool>) -> DecodeOptions {
+    DecodeOptions {
+        parse_json: parse_json.unwrap_or(false),
+    }
+}
+
+/// Table names can't be bound as parameters so quote them as identifiers instead.
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Execute a command against the database
+pub async fn execute(
+    db_instances: &DbInstances,
+    db: String,
+    query: String,
+    values: Vec<JsonValue>,
+) -> Result<(u64, LastInsertId)> {
+    let (pool, _guard) = get_pool(db_instances, &db).await?;
+    let query = bind_values(sqlx::query(&query), values);
+    let result = query.execute(&pool).await?;
+    
+    Ok((result.rows_affected(), result.last_insert_rowid()))
+}
+
+/// Execute commands in one transaction, so either all of them apply or none do
+pub async fn execute_all(
+    db_instances: &DbInstances,
+    db: String,
+    statements: Vec<(String, Vec<JsonValue>)>,
+) -> Result<()> {
+    let (pool, _guard) = get_pool(db_instances, &db).await?;
+    let mut transaction = pool.begin().await?;
+    for (query, values) in statements {
+        bind_values(sqlx::query(&query), values).execute(&mut *transaction).await?;
+    }
+    transaction.commit().await?;
+    Ok(())
+}
+
+pub async fn select(
+    db_instances: &DbInstances,
+    db: String,
+    query: String,
+    values: Vec<JsonValue>,
+    parse_json: Option<bool>,
+) -> Result<Vec<HashMap<String, JsonValue>>> {
+    let (pool, _guard) = get_pool(db_instances, &db).await?;
+    let options = decode_options(parse_json);
+    let query = bind_values(sqlx::query(&query), values);
+    let rows = query.fetch_all(&pool).await?;
+    let mut values = Vec::new();
+    for row in rows {
+        values.push(row_to_json(&row, options)?);
+    }
+
+    Ok(values)
+}
+
+/// One page of a paginated select.
+#[derive(Debug, Serialize)]
+pub struct Page {
+    pub rows: Vec<HashMap<String, JsonValue>>,
+    pub limit: i64,
+    pub offset: i64,
+    pub has_more: bool,
+}
+
+/// The most rows `select_page` returns at once
+pub const MAX_PAGE_SIZE: i64 = 1000;
+
+/// Runs `query` as a subquery and returns at most `limit` rows starting at `offset`.
+/// Fetches one extra row to know if there is another page.
+pub async fn select_page(
+    db_instances: &DbInstances,
+    db: String,
+    query: String,
+    values: Vec<JsonValue>,
+    limit: i64,
+    offset: Option<i64>,
+    parse_json: Option<bool>,
+) -> Result<Page> {
+    let offset = offset.unwrap_or(0);
+    let paged = page_query(&query, limit, offset)?;
+    let (pool, _guard) = get_pool(db_instances, &db).await?;
+    let options = decode_options(parse_json);
+    let rows = bind_values(sqlx::query(&paged), values)
+        .fetch_all(&pool)
+        .await?;
+
+    let has_more = rows.len() as i64 > limit;
+    let mut page = Vec::new();
+    for row in rows.iter().take(limit as usize) {
+        page.push(row_to_json(row, options)?);
+    }
+
+    Ok(Page {
+        rows: page,
+        limit,
+        offset,
+        has_more,
+    })
+}
+
+// Wraps `query` to fetch a page and one extra row. A trailing `;` would end the subquery early.
+fn page_query(query: &str, limit: i64, offset: i64) -> Result<String> {
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(Error::InvalidPage(format!("limit must be between 1 and {}, got {}", MAX_PAGE_SIZE, limit)));
+    }
+    if offset < 0 {
+        return Err(Error::InvalidPage(format!("offset can't be negative, got {}", offset)));
+    }
+    let query = query.trim_end().trim_end_matches(';').trim_end();
+    Ok(format!("SELECT * FROM ({}) LIMIT {} OFFSET {}", query, limit + 1, offset))
+}
+
+/// Reads the rows of `query` in batches of `batch_size`, handing each to `on_batch`
+/// along with whether it's the last. Returns the total row count.
+pub async fn stream_select(
+    db_instances: &DbInstances,
+    db: String,
+    query: String,
+    values: Vec<JsonValue>,
+    batch_size: usize,
+    parse_json: Option<bool>,
+    mut on_batch: impl FnMut(Vec<HashMap<String, JsonValue>>, bool),
+) -> Result<usize> {
+    let (pool, _guard) = get_pool(db_instances, &db).await?;
+    let options = decode_options(parse_json);
+    let batch_size = batch_size.max(1);
+    let mut rows = bind_values(sqlx::query(&query), values).fetch(&pool);
+
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut total = 0;
+    while let Some(row) = rows.try_next().await? {
+        batch.push(row_to_json(&row, options)?);
+        total += 1;
+        if batch.len() == batch_size {
+            on_batch(std::mem::take(&mut batch), false);
+        }
+    }
+    on_batch(batch, true);
+
+    Ok(total)
+}
+
+/// Counts the rows `query` would return.
+pub async fn count(
+    db_instances: &DbInstances,
+    db: String,
+    query: String,
+    values: Vec<JsonValue>,
+) -> Result<i64> {
+    let (pool, _guard) = get_pool(db_instances, &db).await?;
+    let counted = format!("SELECT COUNT(*) FROM ({})", query);
+    let row = bind_values(sqlx::query(&counted), values)
+        .fetch_one(&pool)
+        .await?;
+    Ok(row.try_get(0)?)
+}
+
+/// A column as reported by `PRAGMA table_info`.
+#[derive(Debug, Serialize)]
+pub struct ColumnInfo {
+    pub cid: i64,
+    pub name: String,
+    pub declared_type: String,
+    pub not_null: bool,
+    pub default_value: JsonValue,
+    pub primary_key: bool,
+}
+
+/// Column names and declared types for `table` so the UI doesn't have to guess.
+pub async fn table_info(
+    db_instances: &DbInstances,
+    db: String,
+    table: String,
+) -> Result<Vec<ColumnInfo>> {
+    let (pool, _guard) = get_pool(db_instances, &db).await?;
+    let query = format!("PRAGMA table_info({})", quote_identifier(&table));
+    let rows = sqlx::query(&query).fetch_all(&pool).await?;
+
+    let mut columns = Vec::new();
+    for row in rows {
+        columns.push(ColumnInfo {
+            cid: row.try_get("cid")?,
+            name: row.try_get("name")?,
+            declared_type: row.try_get("type")?,
+            not_null: row.try_get::<i64, _>("notnull")? != 0,
+            default_value: decode::to_json(row.try_get_raw("dflt_value")?)?,
+            primary_key: row.try_get::<i64, _>("pk")? != 0,
+        });
+    }
+
+    Ok(columns)
+}
+
+impl Storage for DbInstances {
+    fn connect<'a>(&'a self, db: &'a str) -> BoxFuture<'a, Result<()>> {
+        Box::pin(connect(self, db))
+    }
+
+    fn disconnect<'a>(&'a self, db: &'a str) -> BoxFuture<'a, ()> {
+        Box::pin(disconnect(self, db))
+    }
+
+    fn replace<'a>(&'a self, db: &'a str, source: &'a Path) -> BoxFuture<'a, Result<()>> {
+        Box::pin(replace(self, db, source))
+    }
+
+    fn loaded(&self) -> BoxFuture<'_, Vec<String>> {
+        Box::pin(async move { self.pools.lock().await.keys().cloned().collect() })
+    }
+
+    fn execute(&self, db: String, query: String, values: Vec<JsonValue>) -> BoxFuture<'_, Result<(u64, LastInsertId)>> {
+        Box::pin(execute(self, db, query, values))
+    }
+
+    fn execute_all(&self, db: String, statements: Vec<(String, Vec<JsonValue>)>) -> BoxFuture<'_, Result<()>> {
+        Box::pin(execute_all(self, db, statements))
+    }
+
+    fn select(
+        &self,
+        db: String,
+        query: String,
+        values: Vec<JsonValue>,
+        parse_json: Option<bool>,
+    ) -> BoxFuture<'_, Result<Vec<HashMap<String, JsonValue>>>> {
+        Box::pin(select(self, db, query, values, parse_json))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn page_query_fetches_one_extra_row() {
+        let paged = page_query("SELECT * FROM events;  ", 10, 20).unwrap();
+        assert_eq!(paged, "SELECT * FROM (SELECT * FROM events) LIMIT 11 OFFSET 20");
+    }
+
+    #[test]
+    fn page_query_rejects_bad_limits_and_offsets() {
+        assert!(page_query("SELECT 1", 0, 0).is_err());
+        assert!(page_query("SELECT 1", -1, 0).is_err());
+        assert!(page_query("SELECT 1", i64::MAX, 0).is_err());
+        assert!(page_query("SELECT 1", MAX_PAGE_SIZE, 0).is_ok());
+        assert!(page_query("SELECT 1", 10, -5).is_err());
+    }
+
+    #[tokio::test]
+    async fn execute_all_applies_nothing_if_a_statement_fails() {
+        let dir = std::env::temp_dir().join(format!("anything-sqlite-{}", uuid::Uuid::new_v4()));
+        let db = DbInstances::new(dir.clone(), Vec::new());
+        let name = "sqlite:test.db".to_string();
+        db.connect(&name).await.unwrap();
+        db.execute(name.clone(), "CREATE TABLE items (id INTEGER PRIMARY KEY)".to_string(), Vec::new())
+            .await
+            .unwrap();
+
+        let insert = |id: i64| ("INSERT INTO items (id) VALUES ($1)".to_string(), vec![JsonValue::from(id)]);
+        assert!(db.execute_all(name.clone(), vec![insert(1), insert(1)]).await.is_err());
+        db.execute_all(name.clone(), vec![insert(1), insert(2)]).await.unwrap();
+
+        let rows = select(&db, name, "SELECT id FROM items".to_string(), Vec::new(), None).await.unwrap();
+        assert_eq!(rows.len(), 2);
+        db.close_all().await;
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,55 @@
+use anything_core::db::backup::{self, BackupInfo, BackupPolicy};
+use anything_core::db::{self, StageInfo, StageState};
+use anything_core::engine::Engine;
+use tauri::{AppHandle, Manager, State};
+
+#[tauri::command]
+pub fn get_current_stage(stage: State<'_, StageState>) -> StageInfo {
+    db::get_current_stage(&stage)
+}
+
+#[tauri::command]
+pub fn get_stages(stage: State<'_, StageState>) -> Result<Vec<String>, String> {
+    db::get_stages(&stage)
+}
+
+#[tauri::command]
+pub async fn set_stage(app: AppHandle, engine: State<'_, Engine>, stage: String) -> Result<StageInfo, String> {
+    let info = db::set_stage(&engine, stage).await?;
+    app.emit_all("stage_changed", &info.stage)
+        .map_err(|e| e.to_string())?;
+    Ok(info)
+}
+
+#[tauri::command]
+pub async fn backup_database(engine: State<'_, Engine>, stage: Option<String>) -> Result<BackupInfo, String> {
+    backup::backup_database(&engine, stage).await
+}
+
+#[tauri::command]
+pub fn list_backups(engine: State<'_, Engine>, stage: Option<String>) -> Result<Vec<BackupInfo>, String> {
+    backup::list_backups(&engine, stage)
+}
+
+#[tauri::command]
+pub async fn restore_database(
+    app: AppHandle,
+    engine: State<'_, Engine>,
+    file_name: String,
+    stage: Option<String>,
+) -> Result<BackupInfo, String> {
+    let safety = backup::restore_database(&engine, file_name, stage).await?;
+    app.emit_all("database_restored", &safety.stage)
+        .map_err(|e| e.to_string())?;
+    Ok(safety)
+}
+
+#[tauri::command]
+pub fn get_backup_policy() -> BackupPolicy {
+    backup::get_backup_policy()
+}
+
+#[tauri::command]
+pub fn set_backup_policy(policy: BackupPolicy) -> Result<BackupPolicy, String> {
+    backup::set_backup_policy(policy)
+}
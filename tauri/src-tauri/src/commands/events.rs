@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+
+use anything_core::engine::Engine;
+use anything_core::events::claims::{self, RecoveryReport};
+use anything_core::events::dry_run::{self, NodeTest};
+use anything_core::events::limits::{self, EngineLimits};
+use anything_core::events::priority::{self, QueueDepth};
+use anything_core::events::progress::Row;
+use anything_core::events::retention::{self, MaintenanceReport, RetentionPolicy};
+use anything_core::events::snapshot::{self, FlowSnapshot};
+use anything_core::events::{pause, replay, session};
+use serde_json::Value as JsonValue;
+use tauri::State;
+
+#[tauri::command]
+pub fn get_retention_policy() -> RetentionPolicy {
+    retention::get_retention_policy()
+}
+
+#[tauri::command]
+pub fn set_retention_policy(policy: RetentionPolicy) -> Result<RetentionPolicy, String> {
+    retention::set_retention_policy(policy)
+}
+
+#[tauri::command]
+pub async fn run_maintenance(engine: State<'_, Engine>) -> Result<Vec<MaintenanceReport>, String> {
+    retention::run_maintenance(&engine).await
+}
+
+#[tauri::command]
+pub fn get_maintenance_report(engine: State<'_, Engine>) -> Vec<MaintenanceReport> {
+    retention::get_maintenance_report(&engine)
+}
+
+#[tauri::command]
+pub async fn get_session_snapshot(
+    engine: State<'_, Engine>,
+    session_id: String,
+    stage: Option<String>,
+) -> Result<FlowSnapshot, String> {
+    snapshot::get_session_snapshot(&engine, session_id, stage).await
+}
+
+#[tauri::command]
+pub async fn list_waiting_events(engine: State<'_, Engine>, stage: Option<String>) -> Result<Vec<Row>, String> {
+    pause::list_waiting_events(&engine, stage).await
+}
+
+#[tauri::command]
+pub async fn resume_event(
+    engine: State<'_, Engine>,
+    event_id: String,
+    approved: Option<bool>,
+    note: Option<String>,
+    stage: Option<String>,
+) -> Result<(), String> {
+    pause::resume_event(&engine, event_id, approved, note, stage).await
+}
+
+#[tauri::command]
+pub async fn cancel_session(engine: State<'_, Engine>, session_id: String, stage: Option<String>) -> Result<(), String> {
+    session::cancel_session(&engine, session_id, stage).await
+}
+
+#[tauri::command]
+pub async fn pause_session(engine: State<'_, Engine>, session_id: String, stage: Option<String>) -> Result<(), String> {
+    session::pause_session(&engine, session_id, stage).await
+}
+
+#[tauri::command]
+pub async fn resume_session(engine: State<'_, Engine>, session_id: String, stage: Option<String>) -> Result<(), String> {
+    session::resume_session(&engine, session_id, stage).await
+}
+
+#[tauri::command]
+pub async fn replay_session(engine: State<'_, Engine>, session_id: String, stage: Option<String>) -> Result<String, String> {
+    replay::replay_session(&engine, session_id, stage).await
+}
+
+#[tauri::command]
+pub async fn retry_from_node(
+    engine: State<'_, Engine>,
+    session_id: String,
+    node_id: String,
+    stage: Option<String>,
+) -> Result<String, String> {
+    replay::retry_from_node(&engine, session_id, node_id, stage).await
+}
+
+#[tauri::command]
+pub async fn test_node(
+    engine: State<'_, Engine>,
+    flow_id: String,
+    node_id: String,
+    mock_results: Option<HashMap<String, JsonValue>>,
+    trigger: Option<JsonValue>,
+    dry_run: Option<bool>,
+) -> Result<NodeTest, String> {
+    dry_run::test_node(&engine, flow_id, node_id, mock_results, trigger, dry_run).await
+}
+
+#[tauri::command]
+pub fn get_engine_limits() -> EngineLimits {
+    limits::get_engine_limits()
+}
+
+#[tauri::command]
+pub fn set_engine_limits(limits: EngineLimits) -> Result<EngineLimits, String> {
+    limits::set_engine_limits(limits)
+}
+
+#[tauri::command]
+pub async fn get_queue_depth(engine: State<'_, Engine>, stage: Option<String>) -> Result<Vec<QueueDepth>, String> {
+    priority::get_queue_depth(&engine, stage).await
+}
+
+#[tauri::command]
+pub fn get_recovery_report(engine: State<'_, Engine>) -> Option<RecoveryReport> {
+    claims::get_recovery_report(&engine)
+}
+
+#[tauri::command]
+pub async fn list_attention_events(engine: State<'_, Engine>, stage: Option<String>) -> Result<Vec<Row>, String> {
+    claims::list_attention_events(&engine, stage).await
+}
+
+#[tauri::command]
+pub async fn resolve_attention_event(
+    engine: State<'_, Engine>,
+    event_id: String,
+    requeue: bool,
+    stage: Option<String>,
+) -> Result<(), String> {
+    claims::resolve_attention_event(&engine, event_id, requeue, stage).await
+}
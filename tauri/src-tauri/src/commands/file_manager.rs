@@ -0,0 +1,120 @@
+use anything_core::file_manager::bundle::{self, BundleManifest, ImportReport};
+use anything_core::file_manager::flow_index::FlowIndex;
+use anything_core::file_manager::flows::{self, FlowSummary};
+use anything_core::file_manager::settings::{self, FlowSettings};
+use anything_core::file_manager::validation::{self, ValidationReport};
+use anything_core::file_manager::versions::{self, FlowDiff, FlowVersion};
+use anything_core::file_manager::{self, FlowInfo};
+use serde_json::Value as JsonValue;
+use tauri::State;
+
+#[tauri::command]
+pub fn get_chat_flows() -> Result<Vec<FlowInfo>, String> {
+    file_manager::get_chat_flows()
+}
+
+#[tauri::command]
+pub fn list_flow_versions(flow_id: String) -> Result<Vec<FlowVersion>, String> {
+    versions::list_flow_versions(flow_id)
+}
+
+#[tauri::command]
+pub fn get_flow_version(flow_id: String, hash: String) -> Result<JsonValue, String> {
+    versions::get_flow_version(flow_id, hash)
+}
+
+#[tauri::command]
+pub fn diff_flow_versions(flow_id: String, from_hash: String, to_hash: String) -> Result<FlowDiff, String> {
+    versions::diff_flow_versions(flow_id, from_hash, to_hash)
+}
+
+#[tauri::command]
+pub fn restore_flow_version(
+    index: State<'_, FlowIndex>,
+    flow_id: String,
+    hash: String,
+) -> Result<Option<FlowVersion>, String> {
+    versions::restore_flow_version(&index, flow_id, hash)
+}
+
+#[tauri::command]
+pub fn list_flows() -> Result<Vec<FlowSummary>, String> {
+    flows::list_flows()
+}
+
+#[tauri::command]
+pub fn list_flow_templates() -> Result<Vec<String>, String> {
+    flows::list_flow_templates()
+}
+
+#[tauri::command]
+pub fn create_flow(
+    index: State<'_, FlowIndex>,
+    flow_name: Option<String>,
+    template: Option<String>,
+) -> Result<FlowSummary, String> {
+    flows::create_flow(&index, flow_name, template)
+}
+
+#[tauri::command]
+pub fn rename_flow(index: State<'_, FlowIndex>, flow_id: String, new_name: String) -> Result<FlowSummary, String> {
+    flows::rename_flow(&index, flow_id, new_name)
+}
+
+#[tauri::command]
+pub fn duplicate_flow(
+    index: State<'_, FlowIndex>,
+    flow_id: String,
+    new_name: Option<String>,
+) -> Result<FlowSummary, String> {
+    flows::duplicate_flow(&index, flow_id, new_name)
+}
+
+#[tauri::command]
+pub fn delete_flow(index: State<'_, FlowIndex>, flow_id: String) -> Result<String, String> {
+    flows::delete_flow(&index, flow_id)
+}
+
+#[tauri::command]
+pub fn export_flow(index: State<'_, FlowIndex>, flow_id: String, path: String) -> Result<BundleManifest, String> {
+    bundle::export_flow(&index, flow_id, path)
+}
+
+#[tauri::command]
+pub fn inspect_flow_bundle(path: String) -> Result<ImportReport, String> {
+    bundle::inspect_flow_bundle(path)
+}
+
+#[tauri::command]
+pub fn import_flow(index: State<'_, FlowIndex>, path: String, flow_name: Option<String>) -> Result<ImportReport, String> {
+    bundle::import_flow(&index, path, flow_name)
+}
+
+#[tauri::command]
+pub fn get_flow_settings(index: State<'_, FlowIndex>, flow_id: String) -> Result<FlowSettings, String> {
+    settings::get_flow_settings(&index, flow_id)
+}
+
+#[tauri::command]
+pub fn update_flow_settings(
+    index: State<'_, FlowIndex>,
+    flow_id: String,
+    settings: FlowSettings,
+) -> Result<FlowSettings, String> {
+    settings::update_flow_settings(&index, flow_id, settings)
+}
+
+#[tauri::command]
+pub fn set_flow_variable(
+    index: State<'_, FlowIndex>,
+    flow_id: String,
+    name: String,
+    value: Option<JsonValue>,
+) -> Result<FlowSettings, String> {
+    settings::set_flow_variable(&index, flow_id, name, value)
+}
+
+#[tauri::command]
+pub fn validate_flow(index: State<'_, FlowIndex>, flow_id: String) -> Result<ValidationReport, String> {
+    validation::validate_flow(&index, flow_id)
+}
@@ -0,0 +1,67 @@
+use anything_core::local_models::{self, cancellation::Canceller, ManagerState, PromptResponse};
+use anything_core::local_models::models::{Architecture, Model};
+use anything_core::local_models::prompt::Template;
+use tauri::{State, Window};
+
+use crate::notifications::CallerSink;
+
+#[tauri::command]
+pub fn get_prompt_templates() -> Vec<Template> {
+    local_models::get_prompt_templates()
+}
+
+#[tauri::command]
+pub fn get_architectures() -> Vec<Architecture> {
+    local_models::get_architectures()
+}
+
+#[tauri::command]
+pub async fn get_models() -> Result<Vec<Model>, String> {
+    local_models::get_models().await
+}
+
+#[tauri::command]
+pub async fn get_downloaded_models() -> Result<Vec<Model>, String> {
+    local_models::get_downloaded_models().await
+}
+
+#[tauri::command]
+pub async fn download_model(filename: &str) -> Result<(), String> {
+    local_models::download_model(filename).await
+}
+
+#[tauri::command]
+pub async fn start(
+    window: Window,
+    state: State<'_, ManagerState>,
+    canceller: State<'_, Canceller>,
+    model_filename: String,
+    architecture: String,
+    tokenizer: String,
+    context_size: usize,
+    use_gpu: bool,
+    prompt: Template,
+) -> Result<bool, String> {
+    local_models::start(
+        &CallerSink(window),
+        &state,
+        &canceller,
+        model_filename,
+        architecture,
+        tokenizer,
+        context_size,
+        use_gpu,
+        prompt,
+    )
+    .await
+}
+
+#[tauri::command]
+pub async fn prompt(
+    window: Window,
+    state: State<'_, ManagerState>,
+    canceller: State<'_, Canceller>,
+    message: String,
+) -> Result<PromptResponse, String> {
+    local_models::prompt(&CallerSink(window), &state, &canceller, message).await
+}
@@ -0,0 +1,7 @@
+//! The app's `#[tauri::command]`s. Each one pulls what it needs out of Tauri's managed
+//! state and calls into `anything_core`, which doesn't know about Tauri.
+
+pub mod db;
+pub mod events;
+pub mod file_manager;
+pub mod local_models;
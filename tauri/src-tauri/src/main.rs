@@ -1,30 +1,36 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-mod sql;
-mod events;
+mod commands;
 mod notifications;
-mod local_models;
-mod config;
-mod file_manager;
+mod sql;
 
-use config::get_logs_dir;
-use local_models::models::ModelManager;
-use local_models::cancellation::Canceller; 
+use anything_core::config::get_logs_dir;
+use anything_core::local_models::cancellation::Canceller; 
+use anything_core::local_models::ManagerState;
+use anything_core::{db, events, file_manager};
 
+use anything_core::sql::sqlite::DbInstances;
 use sql::plugin::Builder;
 use std::fs; 
 use events::scheduler; 
+use events::claims::recover_interrupted_events;
+use events::retention::maintenance_scheduler;
+use events::triggers::trigger_scheduler;
+use db::StageState;
+use db::backup::backup_scheduler;
+use anything_core::engine::Engine;
+use notifications::WindowSink;
+use file_manager::flow_index::FlowIndex;
+use tauri::Manager;
 
 use std::fs::create_dir_all;
-use tracing::info;
+use tracing::{error, info};
 
 use std::sync::Mutex;
 
 use tracing_subscriber::EnvFilter;
 
-pub struct ManagerState(Mutex<Option<ModelManager>>);
-
 fn main() {
 
     let log_file_path = get_logs_dir().expect("getting log directory");
@@ -41,32 +47,104 @@ fn main() {
 
     tauri::Builder::default()
         .plugin(tauri_plugin_fs_watch::init())
-        .plugin(Builder::default().build())
+        .plugin(Builder::default().add_default_migrations(db::migrations()).build())
         .invoke_handler(
             tauri::generate_handler![
-                local_models::get_architectures,
-                local_models::get_models,
-                local_models::get_prompt_templates,
-                local_models::download_model,
-                local_models::start,
-                local_models::prompt,
-                local_models::get_downloaded_models,
-                file_manager::get_chat_flows, 
+                commands::local_models::get_architectures,
+                commands::local_models::get_models,
+                commands::local_models::get_prompt_templates,
+                commands::local_models::download_model,
+                commands::local_models::start,
+                commands::local_models::prompt,
+                commands::local_models::get_downloaded_models,
+                commands::file_manager::get_chat_flows, 
+                commands::db::get_current_stage,
+                commands::db::get_stages,
+                commands::db::set_stage,
+                commands::events::get_retention_policy,
+                commands::events::set_retention_policy,
+                commands::events::run_maintenance,
+                commands::events::get_maintenance_report,
+                commands::db::backup_database,
+                commands::db::list_backups,
+                commands::db::restore_database,
+                commands::db::get_backup_policy,
+                commands::db::set_backup_policy,
+                commands::events::get_session_snapshot,
+                commands::events::list_waiting_events,
+                commands::events::resume_event,
+                commands::events::cancel_session,
+                commands::events::pause_session,
+                commands::events::resume_session,
+                commands::events::replay_session,
+                commands::events::retry_from_node,
+                commands::events::test_node,
+                commands::events::get_engine_limits,
+                commands::events::set_engine_limits,
+                commands::events::get_queue_depth,
+                commands::events::get_recovery_report,
+                commands::events::list_attention_events,
+                commands::events::resolve_attention_event,
+                commands::file_manager::list_flow_versions,
+                commands::file_manager::get_flow_version,
+                commands::file_manager::diff_flow_versions,
+                commands::file_manager::restore_flow_version,
+                commands::file_manager::list_flows,
+                commands::file_manager::list_flow_templates,
+                commands::file_manager::create_flow,
+                commands::file_manager::rename_flow,
+                commands::file_manager::duplicate_flow,
+                commands::file_manager::delete_flow,
+                commands::file_manager::export_flow,
+                commands::file_manager::inspect_flow_bundle,
+                commands::file_manager::import_flow,
+                commands::file_manager::get_flow_settings,
+                commands::file_manager::update_flow_settings,
+                commands::file_manager::set_flow_variable,
+                commands::file_manager::validate_flow,
                 ])
         // .plugin(local_models::init())
         .setup(|app| {
 
-            let app_handle = app.handle();
-            // let window = app_handle.get_window("main").unwrap();
+            if let Err(e) = app.state::<FlowIndex>().watch() {
+                error!("Error watching flows directory: {}", e);
+            }
+
+            let engine = Engine::new(
+                app.state::<DbInstances>().inner().clone(),
+                app.state::<FlowIndex>().inner().clone(),
+                app.state::<StageState>().inner().clone(),
+                WindowSink(app.handle()),
+            );
+            app.manage(engine.clone());
+
+            let scheduler_engine = engine.clone();
               // Spawn a new asynchronous task for scheduler
               tauri::async_runtime::spawn(async move {
-                scheduler(&app_handle).await;
+                // Events the last run left RUNNING are dealt with before anything new is picked up
+                recover_interrupted_events(&scheduler_engine).await;
+                scheduler(&scheduler_engine).await;
+            });
+
+            let trigger_engine = engine.clone();
+            tauri::async_runtime::spawn(async move {
+                trigger_scheduler(&trigger_engine).await;
+            });
+
+            let maintenance_engine = engine.clone();
+            tauri::async_runtime::spawn(async move {
+                maintenance_scheduler(&maintenance_engine).await;
+            });
+
+            tauri::async_runtime::spawn(async move {
+                backup_scheduler(&engine).await;
             });
 
             Ok(())
         })
         .manage(ManagerState(Mutex::new(None)))
         .manage(Canceller::default())
+        .manage(FlowIndex::build())
         .run(tauri::generate_context!())    
         .expect("error while running tauri application");
 }
@@ -0,0 +1,33 @@
+use anything_core::notifications::{Event, EventSink};
+use tauri::{AppHandle, Manager, Runtime, Window};
+use tracing::error;
+
+pub fn emit<R: Runtime>(window: &Window<R>, event: &Event) {
+    if let Err(error) = window.emit(event.name(), event) {
+        error!(
+            error = error.to_string(),
+            event = format!("{:?}", event),
+            "sending event"
+        );
+    }
+}
+
+/// Sends events to the main window. Dropped while there is no window, e.g. while it is closed.
+pub struct WindowSink<R: Runtime>(pub AppHandle<R>);
+
+impl<R: Runtime> EventSink for WindowSink<R> {
+    fn send(&self, event: &Event) {
+        if let Some(window) = self.0.get_window("main") {
+            emit(&window, event);
+        }
+    }
+}
+
+/// Sends events to the window that called a command, like a model's loading progress
+pub struct CallerSink<R: Runtime>(pub Window<R>);
+
+impl<R: Runtime> EventSink for CallerSink<R> {
+    fn send(&self, event: &Event) {
+        emit(&self.0, event);
+    }
+}
@@ -1,2 +1 @@
 pub mod plugin;
-pub mod decode;
\ No newline at end of file
@@ -10,259 +10,145 @@
 // SPDX-License-Identifier: Apache-2.0
 // SPDX-License-Identifier: MIT
 
-use futures_core::future::BoxFuture;
-use serde::{ser::Serializer, Deserialize, Serialize};
-use serde_json::Value as JsonValue;
-use sqlx::{
-    error::BoxDynError,
-    migrate::{
-        MigrateDatabase, Migration as SqlxMigration, MigrationSource, MigrationType, Migrator,
-    },
-    Column, Pool, Row,
-};
+use anything_core::db::{db_string, import_legacy_db, StageState};
+use anything_core::sql::sqlite::{connect, db_dir, DbInstances, Migration};
+use serde::Deserialize;
 use tauri::{
-    command,
     plugin::{Builder as PluginBuilder, TauriPlugin},
-    api::path::document_dir,
-    AppHandle, Manager, RunEvent, Runtime, State,
+    AppHandle, Manager, RunEvent, Runtime,
 };
-use tokio::sync::Mutex;
+use tracing::error;
 
 use std::collections::HashMap;
 
 use std::{fs::create_dir_all, path::PathBuf};
 
-use crate::sql::decode; 
-// use decode; 
-
-type Db = sqlx::sqlite::Sqlite;
-type LastInsertId = i64;
-
-pub const DB_STRING: &'static str = "sqlite:test.db";
- 
-#[derive(Debug, thiserror::Error)]
-pub enum Error {
-    #[error(transparent)]
-    Sql(#[from] sqlx::Error),
-    #[error(transparent)]
-    Migration(#[from] sqlx::migrate::MigrateError),
-    #[error("database {0} not loaded")]
-    DatabaseNotLoaded(String),
-    #[error("unsupported datatype: {0}")]
-    UnsupportedDatatype(String),
-}
-
-impl Serialize for Error {
-    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        serializer.serialize_str(self.to_string().as_ref())
-    }
-}
-
-type Result<T> = std::result::Result<T, Error>;
-
 /// Resolves the App's **file path** from the `AppHandle` context
 fn app_path<R: Runtime>(app: &AppHandle<R>) -> PathBuf {
-    document_dir()
-    .expect("No document directory was found!")
-    .join(app.package_info().package_name())
-    .join("db") 
+    db_dir(app.package_info().package_name())
 }
 
-/// Maps the user supplied DB connection string to a connection string
-/// with a fully qualified file path to the App's designed "app_path"
-fn path_mapper(mut app_path: PathBuf, connection_string: &str) -> String {
-    app_path.push(
-        connection_string
-            .split_once(':')
-            .expect("Couldn't parse the connection string for DB!")
-            .1,
-    );
-
-    format!(
-        "sqlite:{}",
-        app_path
-            .to_str()
-            .expect("Problem creating fully qualified path to Database file!")
-    )
+// Database string for whatever stage the app is pointed at right now.
+fn current_db<R: Runtime>(app: &AppHandle<R>) -> String {
+    db_string(&app.state::<StageState>().current())
 }
 
-#[derive(Default)]
-pub struct DbInstances(Mutex<HashMap<String, Pool<Db>>>);
-
-struct Migrations(Mutex<HashMap<String, MigrationList>>);
-
 #[derive(Default, Deserialize)]
 pub struct PluginConfig {
     #[serde(default)]
     preload: Vec<String>,
 }
 
-#[derive(Debug)]
-pub enum MigrationKind {
-    Up,
-    Down,
-}
-
-impl From<MigrationKind> for MigrationType {
-    fn from(kind: MigrationKind) -> Self {
-        match kind {
-            MigrationKind::Up => Self::ReversibleUp,
-            MigrationKind::Down => Self::ReversibleDown,
-        }
+/// The plugin's commands, called from JS as `plugin:sqlite|<name>`
+mod commands {
+    use anything_core::sql::sqlite::{self, ColumnInfo, DbInstances, Page};
+    use anything_core::sql::{LastInsertId, Result};
+    use anything_core::notifications::Event;
+    use serde_json::Value as JsonValue;
+    use std::collections::HashMap;
+    use tauri::{command, AppHandle, Runtime, State, Window};
+    use tracing::info;
+
+    use crate::notifications::emit;
+
+    /// Loads `db`, or the database of the current stage if none is given.
+    #[command]
+    pub async fn load<R: Runtime>(
+        app: AppHandle<R>,
+        db_instances: State<'_, DbInstances>,
+        db: Option<String>,
+    ) -> Result<String> {
+        info!("Loading db {:?}", db); 
+        let db = db.unwrap_or_else(|| super::current_db(&app));
+        sqlite::connect(&db_instances, &db).await?;
+        Ok(db)
     }
-}
 
-/// A migration definition.
-#[derive(Debug)]
-pub struct Migration {
-    pub version: i64,
-    pub description: &'static str,
-    pub sql: &'static str,
-    pub kind: MigrationKind,
-}
-
-#[derive(Debug)]
-struct MigrationList(Vec<Migration>);
-
-impl MigrationSource<'static> for MigrationList {
-    fn resolve(self) -> BoxFuture<'static, std::result::Result<Vec<SqlxMigration>, BoxDynError>> {
-        Box::pin(async move {
-            let mut migrations = Vec::new();
-            for migration in self.0 {
-                if matches!(migration.kind, MigrationKind::Up) {
-                    migrations.push(SqlxMigration::new(
-                        migration.version,
-                        migration.description.into(),
-                        migration.kind.into(),
-                        migration.sql.into(),
-                    ));
-                }
-            }
-            Ok(migrations)
-        })
+    #[command]
+    pub async fn close(db_instances: State<'_, DbInstances>, db: Option<String>) -> Result<bool> {
+        sqlite::close(&db_instances, db).await
     }
-}
 
-#[command]
-async fn load<R: Runtime>(
-    #[allow(unused_variables)] app: AppHandle<R>,
-    db_instances: State<'_, DbInstances>,
-    migrations: State<'_, Migrations>,
-) -> Result<String> {
-    println!("Loading db"); 
-    let db = DB_STRING.to_string();
-    let fqdb = path_mapper(app_path(&app), &db);
-  
-    create_dir_all(app_path(&app)).expect("Problem creating App directory!");
-
-    if !Db::database_exists(&fqdb).await.unwrap_or(false) {
-        Db::create_database(&fqdb).await?;
+    #[command]
+    pub async fn execute(
+        db_instances: State<'_, DbInstances>,
+        db: String,
+        query: String,
+        values: Vec<JsonValue>,
+    ) -> Result<(u64, LastInsertId)> {
+        sqlite::execute(&db_instances, db, query, values).await
     }
-    let pool = Pool::connect(&fqdb).await?;
 
-    if let Some(migrations) = migrations.0.lock().await.remove(&db) {
-        let migrator = Migrator::new(migrations).await?;
-        migrator.run(&pool).await?;
+    #[command]
+    pub async fn select(
+        db_instances: State<'_, DbInstances>,
+        db: String,
+        query: String,
+        values: Vec<JsonValue>,
+        parse_json: Option<bool>,
+    ) -> Result<Vec<HashMap<String, JsonValue>>> {
+        sqlite::select(&db_instances, db, query, values, parse_json).await
     }
 
-    db_instances.0.lock().await.insert(db.clone(), pool);
-    Ok(db)
-}
-
-/// Allows the database connection(s) to be closed; if no database
-/// name is passed in then _all_ database connection pools will be
-/// shut down.
-#[command]
-async fn close(db_instances: State<'_, DbInstances>, db: Option<String>) -> Result<bool> {
-    let mut instances = db_instances.0.lock().await;
-
-    let pools = if let Some(db) = db {
-        vec![db]
-    } else {
-        instances.keys().cloned().collect()
-    };
-
-    for pool in pools {
-        let db = instances
-            .get_mut(&pool) //
-            .ok_or(Error::DatabaseNotLoaded(pool))?;
-        db.close().await;
+    #[command]
+    pub async fn select_page(
+        db_instances: State<'_, DbInstances>,
+        db: String,
+        query: String,
+        values: Vec<JsonValue>,
+        limit: i64,
+        offset: Option<i64>,
+        parse_json: Option<bool>,
+    ) -> Result<Page> {
+        sqlite::select_page(&db_instances, db, query, values, limit, offset, parse_json).await
     }
 
-    Ok(true)
-}
-
-/// Execute a command against the database
-#[command]
-pub async fn execute(
-    db_instances: State<'_, DbInstances>,
-    db: String,
-    query: String,
-    values: Vec<JsonValue>,
-) -> Result<(u64, LastInsertId)> {
-   
-    let mut instances = db_instances.0.lock().await;
-
-    let db = instances.get_mut(&db).ok_or(Error::DatabaseNotLoaded(db))?;
-    let mut query = sqlx::query(&query);
-    for value in values {
-        if value.is_null() {
-            query = query.bind(None::<JsonValue>);
-        } else if value.is_string() {
-            query = query.bind(value.as_str().unwrap().to_owned())
-        } else {
-            query = query.bind(value);
-        }
+    /// Streams the rows of `query` to the calling window in batches of `batch_size`
+    /// as `select_batch` events tagged with `stream_id`. Returns the total row count.
+    #[command]
+    pub async fn stream_select<R: Runtime>(
+        window: Window<R>,
+        db_instances: State<'_, DbInstances>,
+        db: String,
+        query: String,
+        values: Vec<JsonValue>,
+        stream_id: String,
+        batch_size: Option<usize>,
+        parse_json: Option<bool>,
+    ) -> Result<usize> {
+        let batch_size = batch_size.unwrap_or(500);
+        sqlite::stream_select(&db_instances, db, query, values, batch_size, parse_json, |rows, done| {
+            let event = Event::SelectBatch {
+                stream_id: stream_id.clone(),
+                rows,
+                done,
+            };
+            emit(&window, &event);
+        })
+        .await
     }
-    let result = query.execute(&*db).await?;
-    let r = Ok((result.rows_affected(), result.last_insert_rowid()));
-    r
-}
 
-#[command]
-pub async fn select(
-    db_instances: State<'_, DbInstances>,
-    db: String,
-    query: String,
-    values: Vec<JsonValue>,
-) -> Result<Vec<HashMap<String, JsonValue>>> {
-    let mut instances = db_instances.0.lock().await;
-    let db = instances.get_mut(&db).ok_or(Error::DatabaseNotLoaded(db))?;
-    let mut query = sqlx::query(&query);
-    for value in values {
-        if value.is_null() {
-            query = query.bind(None::<JsonValue>);
-        } else if value.is_string() {
-            query = query.bind(value.as_str().unwrap().to_owned())
-        } else {
-            query = query.bind(value);
-        }
+    #[command]
+    pub async fn count(
+        db_instances: State<'_, DbInstances>,
+        db: String,
+        query: String,
+        values: Vec<JsonValue>,
+    ) -> Result<i64> {
+        sqlite::count(&db_instances, db, query, values).await
     }
-    let rows = query.fetch_all(&*db).await?;
-    let mut values = Vec::new();
-    for row in rows {
-        let mut value = HashMap::default();
-        for (i, column) in row.columns().iter().enumerate() {
-            let v = row.try_get_raw(i)?;
-
-            let v = decode::to_json(v)?;
-            
-            value.insert(column.name().to_string(), v);
-        }
 
-        values.push(value);
+    #[command]
+    pub async fn table_info(db_instances: State<'_, DbInstances>, db: String, table: String) -> Result<Vec<ColumnInfo>> {
+        sqlite::table_info(&db_instances, db, table).await
     }
-
-    Ok(values)
 }
 
 /// Tauri SQL plugin builder.
 #[derive(Default)]
 pub struct Builder {
-    migrations: Option<HashMap<String, MigrationList>>,
+    migrations: Option<HashMap<String, Vec<Migration>>>,
+    default_migrations: Option<Vec<Migration>>,
 }
 
 impl Builder {
@@ -272,43 +158,53 @@ impl Builder {
     pub fn add_migrations(mut self, db_url: &str, migrations: Vec<Migration>) -> Self {
         self.migrations
             .get_or_insert(Default::default())
-            .insert(db_url.to_string(), MigrationList(migrations));
+            .insert(db_url.to_string(), migrations);
+        self
+    }
+
+    /// Add migrations for every database without its own list, e.g. one per stage.
+    #[must_use]
+    pub fn add_default_migrations(mut self, migrations: Vec<Migration>) -> Self {
+        self.default_migrations = Some(migrations);
         self
     }
     
     pub fn build<R: Runtime>(mut self) -> TauriPlugin<R, Option<PluginConfig>> {
         PluginBuilder::new("sqlite")
-            .invoke_handler(tauri::generate_handler![load, execute, select, close])
+            .invoke_handler(tauri::generate_handler![
+                commands::load,
+                commands::execute,
+                commands::select,
+                commands::select_page,
+                commands::stream_select,
+                commands::count,
+                commands::table_info,
+                commands::close
+            ])
             .setup_with_config(|app, config: Option<PluginConfig>| {
                 let config = config.unwrap_or_default();
 
                 
                 create_dir_all(app_path(app)).expect("problems creating App directory!");
 
+                let instances = DbInstances::with_migrations(
+                    app_path(app),
+                    self.migrations.take().unwrap_or_default(),
+                    self.default_migrations.take(),
+                );
+                app.manage(instances.clone());
+                // Stages live next to the databases, so both agree on the directory
+                app.manage(StageState::load(instances.dir()));
+
                 tauri::async_runtime::block_on(async move {
-                    let instances = DbInstances::default();
-                    let mut lock = instances.0.lock().await;
+                    if let Err(e) = import_legacy_db(&instances, instances.dir()).await {
+                        error!("Error importing the old test.db: {}", e);
+                    }
                     for db in config.preload {
-                       
-                        let fqdb = path_mapper(app_path(app), &db);
-
-                        if !Db::database_exists(&fqdb).await.unwrap_or(false) {
-                            Db::create_database(&fqdb).await?;
-                        }
-                        let pool = Pool::connect(&fqdb).await?;
-
-                        if let Some(migrations) = self.migrations.as_mut().unwrap().remove(&db) {
-                            let migrator = Migrator::new(migrations).await?;
-                            migrator.run(&pool).await?;
-                        }
-                        lock.insert(db, pool);
+                        connect(&instances, &db).await?;
                     }
-                    drop(lock);
-
-                    app.manage(instances);
-                    app.manage(Migrations(Mutex::new(
-                        self.migrations.take().unwrap_or_default(),
-                    )));
+                    // The scheduler expects the current stage to be ready at startup
+                    connect(&instances, &current_db(app)).await?;
 
                     Ok(())
                 })
@@ -316,14 +212,10 @@ impl Builder {
             .on_event(|app, event| {
                 if let RunEvent::Exit = event {
                     tauri::async_runtime::block_on(async move {
-                        let instances = &*app.state::<DbInstances>();
-                        let instances = instances.0.lock().await;
-                        for value in instances.values() {
-                            value.close().await;
-                        }
+                        app.state::<DbInstances>().close_all().await;
                     });
                 }
             })
             .build()
     }
-}
\ No newline at end of file
+}
@@ -12,13 +12,9 @@ import {
   readDir,
   writeTextFile,
   FileEntry,
-  createDir,
-  removeDir,
-  copyFile,
-  exists,
   readTextFile
 } from "@tauri-apps/api/fs";
-import { v4 as uuidv4 } from "uuid";
+import { invoke } from "@tauri-apps/api";
 import { stringify, parse } from "iarna-toml-esm";
 
 interface LocalFileContextInterface {
@@ -65,46 +61,21 @@ export const LocalFileProvider = ({ children }: { children: ReactNode }) => {
     }
   };
 
-  //BUG: there is a bug where when you add new flows the names colide because we write files as names.
-  //TODO: more sophisticated way of determining new flow name
+  // flow directories are managed by the backend so renames keep the flow_id
+  const flowIdByName = async (flowName: string) => {
+    const flows: any[] = await invoke("list_flows");
+    const flow = flows.find((flow) => flow.flow_name === flowName);
+    if (!flow || !flow.flow_id) throw Error("No flow found named " + flowName);
+    return flow.flow_id as string;
+  };
+
   const createNewFlow = async () => {
     try {
-      let flowName = "Flow" + " " + (flowPaths.length + 1);
-      console.log("new flow name", flowName);
-      let flowId = uuidv4();
-      // Basic TOML structure for the flow.toml file
-      const flowTomlContent = `
-    [flow]
-    name = "${flowName}"
-    id =  "${flowId}"
-    version = "0.0.1"
-    author = "Your Name <your.email@example.com>"
-    description = "Description of your flow"
-    `;
-
-      // Basic TOML structure for the settings.toml file (modify as needed)
-      const settingsTomlContent = `
-    [settings]
-    some_key = "some_value"
-    `;
-
-      if (appDocuments !== undefined) {
-        await createDir(appDocuments + "/flows/" + flowName, {
-          recursive: true,
-        });
+      const flow = await invoke("create_flow", {});
+      console.log("new flow", flow);
 
-        await writeTextFile(
-          appDocuments + "/flows/" + flowName + "/flow.toml",
-          flowTomlContent
-        );
-        await writeTextFile(
-          appDocuments + "/flows/" + flowName + "/settings.toml",
-          settingsTomlContent
-        );
-
-        // get local files for ui again
-        await getLocalFiles();
-      }
+      // get local files for ui again
+      await getLocalFiles();
     } catch (error) {
       console.error(error);
     }
@@ -113,24 +84,16 @@ export const LocalFileProvider = ({ children }: { children: ReactNode }) => {
   const deleteFlow = async (flowName: string) => {
     //TODO: deal with situation where there are flow events in the db
     try {
-      if (appDocuments !== undefined) {
-        await removeDir(appDocuments + "/flows/" + flowName, {
-          recursive: true,
-        });
+      const flowId = await flowIdByName(flowName);
+      await invoke("delete_flow", { flowId });
 
-        // get local files for ui again
-        await getLocalFiles();
-      }
+      // get local files for ui again
+      await getLocalFiles();
     } catch (error) {
       console.error(error);
     }
   };
 
-  const allPathsExist = async (paths: string[]) => {
-    const results = await Promise.all(paths.map((path) => exists(path)));
-    return results.every((result) => result);
-  };
-
   const readToml = async (flow_name: string) => {
     try {
       if (!appDocuments || !flow_name) {
@@ -199,37 +162,9 @@ export const LocalFileProvider = ({ children }: { children: ReactNode }) => {
   const renameFlowFiles = async (flowName: string, newFlowName: string) => {
     console.log("renameFlowFiles", flowName, newFlowName);
     try {
-      // if(true) throw Error("Not implemented yet");
-      if (appDocuments === undefined) throw Error("AppDocuments Undefiend");
       if (flowName === newFlowName) throw Error("Flow names are the same");
-
-      let allExist = await allPathsExist([
-        appDocuments + "/flows/" + flowName + "/flow.toml",
-        appDocuments + "/flows/" + flowName + "/settings.toml",
-      ]);
-
-      if (!allExist) throw Error("Flow files do not all exist");
-
-      //make new dir first
-      await createDir(appDocuments + "/flows/" + newFlowName, {
-        recursive: true,
-      });
-      //copy files over
-      await copyFile(
-        appDocuments + "/flows/" + flowName + "/flow.toml",
-        appDocuments + "/flows/" + newFlowName + "/flow.toml"
-      );
-      await copyFile(
-        appDocuments + "/flows/" + flowName + "/settings.toml",
-        appDocuments + "/flows/" + newFlowName + "/settings.toml"
-      );
-
-      //delete old dir
-      await removeDir(appDocuments + "/flows/" + flowName, {
-        recursive: true,
-      });
-
-      //TODO: update file frontmatter
+      const flowId = await flowIdByName(flowName);
+      await invoke("rename_flow", { flowId, newName: newFlowName });
 
       await getLocalFiles();
     } catch (error) {
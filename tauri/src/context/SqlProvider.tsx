@@ -6,10 +6,9 @@ import {
   ReactNode,
 } from "react";
 import { invoke } from "@tauri-apps/api";
+import { listen } from "@tauri-apps/api/event";
 import { v4 as uuidv4 } from "uuid";
 
-const DB_STRING = "sqlite:test.db";
-
 export type EventInput = {
   flow_id: string; //flow needs a computer friendly name that can be changed without changing processing
   flow_name: string; //flow needs a user friendly name
@@ -25,13 +24,20 @@ export type EventInput = {
   created_at: string;
   data: any;
 };
-//Load Database once
-invoke("plugin:sqlite|load");
+//Load Database for the current stage. Reload when the stage is switched.
+let DB_STRING: Promise<string> = invoke("plugin:sqlite|load");
+listen("stage_changed", () => {
+  DB_STRING = invoke("plugin:sqlite|load");
+});
+
+//"sqlite:prod.sqlite" -> "prod"
+const stageFromDb = (db: string) =>
+  db.split(":")[1]?.replace(".sqlite", "") ?? "dev";
 
 interface SqlContextInterface {
   tables: any[];
   addEvent: (event: EventInput) => void;
-  getTableData: (tableName: string) => any;
+  getTableData: (tableName: string, limit?: number, offset?: number) => any;
   getSessionEvents: (flowName: string, session_id: string) => any;
   getEvent: (event_id: string) => any;
 }
@@ -53,7 +59,7 @@ export const SqlProvider = ({ children }: { children: ReactNode }) => {
     execute: async (query: string, values?: any[]) => {
       // console.log("Executing Sql on JS side", query, values);
       return await invoke("plugin:sqlite|execute", {
-        db: DB_STRING,
+        db: await DB_STRING,
         query,
         values: values ?? [],
       });
@@ -61,7 +67,7 @@ export const SqlProvider = ({ children }: { children: ReactNode }) => {
     select: async (query: string, values?: any[]): Promise<any> => {
       // console.log("Selecting Sql on JS side", query, values);
       return await invoke("plugin:sqlite|select", {
-        db: DB_STRING,
+        db: await DB_STRING,
         query,
         values: values ?? [],
       });
@@ -82,7 +88,7 @@ export const SqlProvider = ({ children }: { children: ReactNode }) => {
           event.flow_id,
           event.flow_name,
           event.flow_version,
-          event.stage,
+          stageFromDb(await DB_STRING),
           event.worker_type,
           event.worker_name,
           event.event_status,
@@ -108,10 +114,17 @@ export const SqlProvider = ({ children }: { children: ReactNode }) => {
     setTables(tables as any);
   };
 
-  const getTableData = async (tableName: string) => {
-    const tableData = await db.select(`SELECT * FROM ${tableName}`);
-    console.log("tableData in db", tableData);
-    return tableData;
+  //Only the first page. Big tables like events hang the UI if we select everything.
+  const getTableData = async (tableName: string, limit = 100, offset = 0) => {
+    const page: any = await invoke("plugin:sqlite|select_page", {
+      db: await DB_STRING,
+      query: `SELECT * FROM ${tableName}`,
+      values: [],
+      limit,
+      offset,
+    });
+    console.log("tableData in db", page);
+    return page.rows;
   };
 
   const getSessionEvents = async (flowName: string, session_id: string) => {
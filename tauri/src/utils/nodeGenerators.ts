@@ -22,6 +22,60 @@ const StartHandles: HandleProps[] = [
   },
 ];
 
+const LoopHandles: HandleProps[] = [
+  {
+    id: "a",
+    position: Position.Top,
+    type: "target",
+  },
+  {
+    id: "body",
+    position: Position.Bottom,
+    type: "source",
+  },
+  {
+    id: "done",
+    position: Position.Right,
+    type: "source",
+  },
+];
+
+const RouterHandles: HandleProps[] = [
+  {
+    id: "a",
+    position: Position.Top,
+    type: "target",
+  },
+  {
+    id: "match",
+    position: Position.Bottom,
+    type: "source",
+  },
+  {
+    id: "default",
+    position: Position.Right,
+    type: "source",
+  },
+];
+
+const ApprovalHandles: HandleProps[] = [
+  {
+    id: "a",
+    position: Position.Top,
+    type: "target",
+  },
+  {
+    id: "approved",
+    position: Position.Bottom,
+    type: "source",
+  },
+  {
+    id: "rejected",
+    position: Position.Right,
+    type: "source",
+  },
+];
+
 const EndHandles: HandleProps[] = [
   {
     id: "a",
@@ -207,6 +261,129 @@ export const NODES: Node[] = [
       trigger: false,
     },
   },
+  {
+    nodeType: "superNode",
+    nodeConfigurationData: {
+      max_iterations: 10,
+      condition: "",
+    },
+    nodePresentationData: {
+      node_label: "Loop",
+      alt: "Loop",
+      icon: "VscSync",
+      handles: LoopHandles,
+    },
+    nodeProcessData: {
+      worker_type: "loop",
+      worker_name: "loop",
+      trigger: false,
+    },
+  },
+  {
+    nodeType: "superNode",
+    nodeConfigurationData: {
+      routes: '[{"handle": "match", "condition": {"field": "input.status", "operator": "greater_than_or_equal", "value": 400}}]',
+      mode: "first",
+    },
+    nodePresentationData: {
+      node_label: "Router",
+      alt: "Router",
+      icon: "VscSplitVertical",
+      handles: RouterHandles,
+    },
+    nodeProcessData: {
+      worker_type: "router",
+      worker_name: "router",
+      trigger: false,
+    },
+  },
+  {
+    nodeType: "superNode",
+    nodeConfigurationData: {
+      items: "input",
+      concurrency: "",
+    },
+    nodePresentationData: {
+      node_label: "Map",
+      alt: "Map",
+      icon: "VscListOrdered",
+      handles: LoopHandles,
+    },
+    nodeProcessData: {
+      worker_type: "map",
+      worker_name: "map",
+      trigger: false,
+    },
+  },
+  {
+    nodeType: "superNode",
+    nodeConfigurationData: {
+      flow_id: "",
+      input: "",
+    },
+    nodePresentationData: {
+      node_label: "Subflow",
+      alt: "Subflow",
+      icon: "VscTypeHierarchySub",
+      handles: BaseHandles,
+    },
+    nodeProcessData: {
+      worker_type: "subflow",
+      worker_name: "subflow",
+      trigger: false,
+    },
+  },
+  {
+    nodeType: "superNode",
+    nodeConfigurationData: {
+      duration: "5m",
+    },
+    nodePresentationData: {
+      node_label: "Delay",
+      alt: "Delay",
+      icon: "VscWatch",
+      handles: BaseHandles,
+    },
+    nodeProcessData: {
+      worker_type: "delay",
+      worker_name: "delay",
+      trigger: false,
+    },
+  },
+  {
+    nodeType: "superNode",
+    nodeConfigurationData: {
+      until: "",
+    },
+    nodePresentationData: {
+      node_label: "Wait Until",
+      alt: "Wait Until",
+      icon: "VscCalendar",
+      handles: BaseHandles,
+    },
+    nodeProcessData: {
+      worker_type: "wait_until",
+      worker_name: "wait_until",
+      trigger: false,
+    },
+  },
+  {
+    nodeType: "superNode",
+    nodeConfigurationData: {
+      message: "",
+    },
+    nodePresentationData: {
+      node_label: "Approval",
+      alt: "Approval",
+      icon: "VscPassFilled",
+      handles: ApprovalHandles,
+    },
+    nodeProcessData: {
+      worker_type: "approval",
+      worker_name: "approval",
+      trigger: false,
+    },
+  },
   {
     nodeType: "superNode",
     nodeConfigurationData: {